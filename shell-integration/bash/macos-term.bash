@@ -0,0 +1,64 @@
+# macos-term shell integration for bash.
+#
+# The terminal starts bash with `--posix` and points ENV at this file, which
+# is then read instead of the usual startup files. It leaves POSIX mode, runs
+# those startup files itself and installs hooks that report the prompt and
+# command boundaries (OSC 133) and the working directory (OSC 7).
+#
+# To load it by hand, source it at the end of ~/.bashrc.
+
+if [[ -n "$MACOS_TERM_BASH_INJECT" ]]; then
+    builtin set +o posix
+    builtin unset MACOS_TERM_BASH_INJECT ENV
+    if [[ -n "$MACOS_TERM_BASH_ENV" ]]; then
+        builtin export ENV="$MACOS_TERM_BASH_ENV"
+    fi
+    builtin unset MACOS_TERM_BASH_ENV
+
+    # The files bash reads on its own, in the same order
+    if builtin shopt -q login_shell; then
+        [[ -r /etc/profile ]] && builtin source /etc/profile
+        for __macos_term_rc in ~/.bash_profile ~/.bash_login ~/.profile; do
+            if [[ -r "$__macos_term_rc" ]]; then
+                builtin source "$__macos_term_rc"
+                break
+            fi
+        done
+        builtin unset __macos_term_rc
+    elif [[ -r ~/.bashrc ]]; then
+        builtin source ~/.bashrc
+    fi
+fi
+
+if [[ $- == *i* && -z "$__macos_term_installed" ]]; then
+    __macos_term_installed=1
+
+    __macos_term_report_cwd() {
+        local LC_ALL=C path="$PWD" encoded="" char i
+        for (( i = 0; i < ${#path}; i++ )); do
+            char="${path:i:1}"
+            case "$char" in
+                [a-zA-Z0-9/._~-]) encoded+="$char" ;;
+                *) builtin printf -v char '%%%02X' "'$char"; encoded+="$char" ;;
+            esac
+        done
+        builtin printf '\e]7;file://%s%s\a' "$HOSTNAME" "$encoded"
+    }
+
+    # Runs first in PROMPT_COMMAND so `$?` is still the command's status.
+    # The terminal ignores the status when no command was started.
+    __macos_term_precmd() {
+        builtin printf '\e]133;D;%s\a' "$?"
+        __macos_term_report_cwd
+        builtin printf '\e]133;A\a'
+    }
+
+    if [[ "$(builtin declare -p PROMPT_COMMAND 2>/dev/null)" == "declare -a"* ]]; then
+        PROMPT_COMMAND=(__macos_term_precmd "${PROMPT_COMMAND[@]}")
+    else
+        PROMPT_COMMAND="__macos_term_precmd${PROMPT_COMMAND:+; $PROMPT_COMMAND}"
+    fi
+    # PS0 is printed as a command starts running; bash before 4.4 has no
+    # equivalent, so commands there only get prompt marks.
+    PS0+='\e]133;C\a'
+fi
@@ -0,0 +1,38 @@
+# macos-term shell integration for fish.
+#
+# The terminal adds this file's data directory to XDG_DATA_DIRS so fish loads
+# it as vendor configuration. It puts XDG_DATA_DIRS back and installs hooks
+# that report the prompt and command boundaries (OSC 133) and the working
+# directory (OSC 7).
+#
+# To load it by hand, source it from ~/.config/fish/config.fish.
+
+if set -q MACOS_TERM_FISH_XDG_DATA_DIRS
+    set -gx XDG_DATA_DIRS $MACOS_TERM_FISH_XDG_DATA_DIRS
+    set -e MACOS_TERM_FISH_XDG_DATA_DIRS
+else if set -q MACOS_TERM_FISH_INJECT
+    set -e XDG_DATA_DIRS
+end
+set -e MACOS_TERM_FISH_INJECT
+
+status is-interactive; or exit
+set -q __macos_term_installed; and exit
+set -g __macos_term_installed 1
+
+function __macos_term_report_cwd --on-variable PWD
+    printf '\e]7;file://%s%s\a' $hostname (string escape --style=url -- $PWD)
+end
+
+function __macos_term_prompt --on-event fish_prompt
+    printf '\e]133;A\a'
+end
+
+function __macos_term_preexec --on-event fish_preexec
+    printf '\e]133;C\a'
+end
+
+function __macos_term_postexec --on-event fish_postexec
+    printf '\e]133;D;%s\a' $status
+end
+
+__macos_term_report_cwd
@@ -0,0 +1,316 @@
+//! User configuration.
+//!
+//! Settings are read from `$XDG_CONFIG_HOME/macos-term/config` (falling back to
+//! `~/.config/macos-term/config`) as `key = value` lines; `#` starts a comment
+//! at the beginning of a line or after whitespace following a value.
+//! Unknown keys and malformed values are reported and skipped.
+//!
+//! ```text
+//! foreground = #e5e5e5
+//! background = #000000
+//! cursor = #00ffff
+//! color4 = #2472c8
+//! scrollback = 10000      # or `unlimited`
+//! word_chars = -_.~/:@+%  # selected by a double click along with letters and digits
+//! url_regex = (?:https?|ftp|file)://\S+
+//! file_regex = (?P<path>[\w./~-]+\.\w+):(?P<line>\d+)(?::(?P<col>\d+))?
+//! clipboard = allow-write  # OSC 52: deny, allow-write, ask or allow-all
+//! shell_integration = true  # load prompt and directory reporting into bash, zsh and fish
+//! title = {title}  # also {process} and {cwd}
+//! image_memory = 320  # MiB of decoded image pixels kept
+//! term = xterm-256color  # or xterm-macos-term once terminfo/ is installed
+//! ```
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+use crate::terminal::color::{Palette, Rgb};
+use crate::terminal::graphics::DEFAULT_IMAGE_MEMORY_LIMIT;
+use crate::terminal::screen::DEFAULT_SCROLLBACK_LINES;
+
+/// Characters that count as part of a word for double-click selection, so
+/// paths, URLs and `user@host` pick up in one go.
+const DEFAULT_WORD_CHARS: &str = "-_.~/:@+%";
+
+/// URLs recognised in output, without trailing punctuation.
+pub const DEFAULT_URL_REGEX: &str = r#"(?:(?:https?|ftp|file)://|mailto:)[^\s<>"'`]*[^\s<>"'`.,;:!?)\]}]"#;
+
+/// `path:line:col` locations as printed by compilers and grep. The path
+/// needs a directory or an extension so `host:port` is not taken for one.
+pub const DEFAULT_FILE_REGEX: &str =
+    r"(?P<path>(?:~|\.{1,2})?(?:/[\w.+@-]+)+|[\w.+@-]+(?:/[\w.+@-]+)*\.\w+):(?P<line>\d+)(?::(?P<col>\d+))?";
+
+/// The title applications set, falling back to the running program.
+const DEFAULT_TITLE_TEMPLATE: &str = "{title}";
+
+/// `TERM` for the shell. Every host has this entry, unlike our own, which
+/// has to be installed first.
+const DEFAULT_TERM: &str = "xterm-256color";
+
+/// What applications may do with the clipboard through OSC 52.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClipboardPolicy {
+    Deny,
+    /// Set the clipboard, but never read it.
+    #[default]
+    AllowWrite,
+    /// Set the clipboard; reading it asks first.
+    Ask,
+    AllowAll,
+}
+
+impl ClipboardPolicy {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "deny" => Some(Self::Deny),
+            "allow-write" => Some(Self::AllowWrite),
+            "ask" => Some(Self::Ask),
+            "allow-all" => Some(Self::AllowAll),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Deny => "deny",
+            Self::AllowWrite => "allow-write",
+            Self::Ask => "ask",
+            Self::AllowAll => "allow-all",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub palette: Palette,
+    /// Lines of history to keep; `None` is unlimited.
+    pub scrollback_lines: Option<usize>,
+    /// Non-alphanumeric characters that double-click selection treats as
+    /// part of a word.
+    pub word_chars: String,
+    /// Text Ctrl+click opens with the desktop's URI handler.
+    pub url_regex: Regex,
+    /// File locations Ctrl+click opens in `$EDITOR`; the `path`, `line` and
+    /// `col` groups are used when present.
+    pub file_regex: Regex,
+    pub clipboard: ClipboardPolicy,
+    /// Whether known shells are started with the integration scripts.
+    pub shell_integration: bool,
+    /// Window title; `{title}`, `{process}` and `{cwd}` are filled in.
+    pub title: String,
+    /// Bytes of decoded image pixels kept before the oldest images go.
+    pub image_memory_limit: usize,
+    /// `TERM` the shell is started with.
+    pub term: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            palette: Palette::default(),
+            scrollback_lines: Some(DEFAULT_SCROLLBACK_LINES),
+            word_chars: DEFAULT_WORD_CHARS.to_string(),
+            url_regex: Regex::new(DEFAULT_URL_REGEX).expect("default URL regex"),
+            file_regex: Regex::new(DEFAULT_FILE_REGEX).expect("default file regex"),
+            clipboard: ClipboardPolicy::default(),
+            shell_integration: true,
+            title: DEFAULT_TITLE_TEMPLATE.to_string(),
+            image_memory_limit: DEFAULT_IMAGE_MEMORY_LIMIT,
+            term: DEFAULT_TERM.to_string(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file, falling back to defaults when it does not exist.
+    pub fn load() -> Self {
+        let mut config = Config::default();
+        let Some(path) = config_path() else {
+            return config;
+        };
+        let Ok(contents) = fs::read_to_string(&path) else {
+            return config;
+        };
+        config.read(&contents, &path);
+        println!("✅ Loaded config from {}", path.display());
+        config
+    }
+
+    /// Applies the settings in `contents`, reporting bad lines as coming
+    /// from `path`.
+    fn read(&mut self, contents: &str, path: &Path) {
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                eprintln!("❌ {}:{}: expected `key = value`", path.display(), number + 1);
+                continue;
+            };
+            // Colors start with `#`, so only ` #` after the value begins a comment.
+            let value = value.trim().split(" #").next().unwrap_or_default().trim();
+            if let Err(message) = self.set(key.trim(), value) {
+                eprintln!("❌ {}:{}: {}", path.display(), number + 1, message);
+            }
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let color = || Rgb::parse(value).ok_or_else(|| format!("invalid color `{}` for {}", value, key));
+        let regex = || Regex::new(value).map_err(|err| format!("invalid regex for {}: {}", key, err));
+        match key {
+            "foreground" => self.palette.foreground = color()?,
+            "background" => self.palette.background = color()?,
+            "cursor" => self.palette.cursor = color()?,
+            "scrollback" => {
+                self.scrollback_lines = match value {
+                    "unlimited" => None,
+                    _ => Some(value.parse().map_err(|_| format!("invalid line count `{}`", value))?),
+                }
+            }
+            "word_chars" => self.word_chars = value.to_string(),
+            "url_regex" => self.url_regex = regex()?,
+            "file_regex" => self.file_regex = regex()?,
+            "clipboard" => {
+                self.clipboard = ClipboardPolicy::parse(value)
+                    .ok_or_else(|| format!("invalid clipboard policy `{}`, expected deny, allow-write, ask or allow-all", value))?
+            }
+            "shell_integration" => {
+                self.shell_integration = value
+                    .parse()
+                    .map_err(|_| format!("invalid value `{}` for shell_integration, expected true or false", value))?
+            }
+            "title" => self.title = value.to_string(),
+            "image_memory" => {
+                let mebibytes: usize = value.parse().map_err(|_| format!("invalid size `{}` in MiB", value))?;
+                self.image_memory_limit = mebibytes.saturating_mul(1024 * 1024);
+            }
+            "term" if value.is_empty() => return Err("empty value for term".to_string()),
+            "term" => self.term = value.to_string(),
+            _ => match key.strip_prefix("color").and_then(|index| index.parse::<u8>().ok()) {
+                Some(index) => self.palette.set(index, color()?),
+                None => return Err(format!("unknown key `{}`", key)),
+            },
+        }
+        Ok(())
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("macos-term").join("config"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(contents: &str) -> Config {
+        let mut config = Config::default();
+        config.read(contents, Path::new("config"));
+        config
+    }
+
+    #[test]
+    fn comments_need_whitespace_before_them() {
+        let config = read(
+            "# a comment\n\
+             \x20 # an indented one\n\
+             foreground = #e5e5e5  # the text color\n\
+             background=#102030\n\
+             word_chars = -#_ # a # in a value stays\n\
+             title = {title}#1\n",
+        );
+        assert_eq!(config.palette.foreground, Rgb::new(0xe5, 0xe5, 0xe5));
+        assert_eq!(config.palette.background, Rgb::new(0x10, 0x20, 0x30));
+        assert_eq!(config.word_chars, "-#_");
+        assert_eq!(config.title, "{title}#1");
+    }
+
+    #[test]
+    fn bad_lines_are_skipped() {
+        let config = read("foreground\nforeground = red\nscrollback = 500\n");
+        assert_eq!(config.palette.foreground, Palette::default().foreground);
+        assert_eq!(config.scrollback_lines, Some(500));
+    }
+
+    #[test]
+    fn palette_colors_by_index() {
+        let mut config = Config::default();
+        assert_eq!(config.set("color4", "#2472c8"), Ok(()));
+        assert_eq!(config.set("color255", "#010203"), Ok(()));
+        assert_eq!(config.palette.get(4), Rgb::new(0x24, 0x72, 0xc8));
+        assert_eq!(config.palette.get(255), Rgb::new(1, 2, 3));
+        assert_eq!(config.set("color256", "#ffffff"), Err("unknown key `color256`".to_string()));
+        assert_eq!(config.set("color-1", "#ffffff"), Err("unknown key `color-1`".to_string()));
+        assert_eq!(config.set("color", "#ffffff"), Err("unknown key `color`".to_string()));
+        assert_eq!(config.set("color4", "blue"), Err("invalid color `blue` for color4".to_string()));
+        assert_eq!(config.palette.get(4), Rgb::new(0x24, 0x72, 0xc8));
+    }
+
+    #[test]
+    fn scrollback_may_be_unlimited() {
+        let mut config = Config::default();
+        assert_eq!(config.scrollback_lines, Some(DEFAULT_SCROLLBACK_LINES));
+        assert_eq!(config.set("scrollback", "unlimited"), Ok(()));
+        assert_eq!(config.scrollback_lines, None);
+        assert_eq!(config.set("scrollback", "0"), Ok(()));
+        assert_eq!(config.scrollback_lines, Some(0));
+        assert!(config.set("scrollback", "-1").is_err());
+        assert!(config.set("scrollback", "lots").is_err());
+        assert_eq!(config.scrollback_lines, Some(0));
+    }
+
+    #[test]
+    fn image_memory_is_in_mebibytes() {
+        let mut config = Config::default();
+        assert_eq!(config.set("image_memory", "16"), Ok(()));
+        assert_eq!(config.image_memory_limit, 16 * 1024 * 1024);
+        assert_eq!(config.set("image_memory", &usize::MAX.to_string()), Ok(()));
+        assert_eq!(config.image_memory_limit, usize::MAX);
+        assert!(config.set("image_memory", "1.5").is_err());
+        assert_eq!(config.image_memory_limit, usize::MAX);
+    }
+
+    #[test]
+    fn term_cannot_be_empty() {
+        let mut config = Config::default();
+        assert_eq!(config.set("term", ""), Err("empty value for term".to_string()));
+        assert_eq!(config.term, DEFAULT_TERM);
+        let config = read("term =  \n");
+        assert_eq!(config.term, DEFAULT_TERM);
+        let config = read("term = xterm-macos-term\n");
+        assert_eq!(config.term, "xterm-macos-term");
+    }
+
+    #[test]
+    fn other_settings() {
+        let mut config = Config::default();
+        assert_eq!(config.set("clipboard", "ask"), Ok(()));
+        assert_eq!(config.clipboard, ClipboardPolicy::Ask);
+        assert!(config.set("clipboard", "sometimes").is_err());
+        assert_eq!(config.set("shell_integration", "false"), Ok(()));
+        assert!(!config.shell_integration);
+        assert!(config.set("shell_integration", "no").is_err());
+        assert_eq!(config.set("url_regex", r"https://\S+"), Ok(()));
+        assert_eq!(config.url_regex.as_str(), r"https://\S+");
+        assert!(config.set("file_regex", "(").is_err());
+        assert_eq!(config.file_regex.as_str(), DEFAULT_FILE_REGEX);
+    }
+
+    #[test]
+    fn unknown_keys_are_refused() {
+        let mut config = Config::default();
+        assert_eq!(config.set("font_size", "12"), Err("unknown key `font_size`".to_string()));
+        assert_eq!(config.set("", "12"), Err("unknown key ``".to_string()));
+        let config = read("font_size = 12\nscrollback = 42\n");
+        assert_eq!(config.scrollback_lines, Some(42));
+    }
+}
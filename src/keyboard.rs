@@ -0,0 +1,483 @@
+//! Key event encoding.
+//!
+//! Turns GDK key events into the bytes a terminal application expects. By
+//! default this is the legacy xterm encoding, where many combinations collapse
+//! onto the same byte (Ctrl+I and Tab are both `\t`). Applications can ask for
+//! unambiguous encodings with xterm's `modifyOtherKeys` (`CSI 27 ; m ; code ~`)
+//! or the kitty keyboard protocol (`CSI code ; m u`), which can also report
+//! key repeat and release.
+
+use gtk4::gdk;
+
+// Kitty keyboard protocol flags, as pushed with `CSI > flags u`. Flag 1,
+// "disambiguate escape codes", is implied by any other flag and is what a
+// non-zero value without the others means.
+const REPORT_EVENT_TYPES: u8 = 2;
+const REPORT_ALTERNATE_KEYS: u8 = 4;
+const REPORT_ALL_KEYS: u8 = 8;
+const REPORT_TEXT: u8 = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyEventKind {
+    Press,
+    Repeat,
+    Release,
+}
+
+/// A key event as seen by the view.
+pub struct KeyInput {
+    /// The key with the current modifiers applied (Shift+a is `A`).
+    pub keyval: gdk::Key,
+    /// The same physical key without any modifiers.
+    pub base: gdk::Key,
+    pub state: gdk::ModifierType,
+    pub kind: KeyEventKind,
+}
+
+/// The terminal state that changes how keys are encoded.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KeyboardModes {
+    pub application_cursor: bool,
+    pub application_keypad: bool,
+    pub modify_other_keys: u8,
+    pub kitty_flags: u8,
+}
+
+#[derive(Clone, Copy)]
+struct Modifiers {
+    shift: bool,
+    alt: bool,
+    ctrl: bool,
+    super_: bool,
+}
+
+impl Modifiers {
+    fn new(state: gdk::ModifierType) -> Self {
+        Self {
+            shift: state.contains(gdk::ModifierType::SHIFT_MASK),
+            alt: state.contains(gdk::ModifierType::ALT_MASK),
+            ctrl: state.contains(gdk::ModifierType::CONTROL_MASK),
+            super_: state.intersects(gdk::ModifierType::SUPER_MASK | gdk::ModifierType::META_MASK),
+        }
+    }
+
+    /// The xterm modifier parameter: 1 plus the modifier bits.
+    fn param(self) -> u32 {
+        1 + u32::from(self.shift) + u32::from(self.alt) * 2 + u32::from(self.ctrl) * 4 + u32::from(self.super_) * 8
+    }
+}
+
+/// Keys with a fixed escape sequence: `CSI number ; modifiers final`.
+struct Functional {
+    number: u32,
+    final_byte: char,
+}
+
+fn functional(keyval: gdk::Key, kitty: bool) -> Option<Functional> {
+    let key = |number, final_byte| Some(Functional { number, final_byte });
+    match keyval {
+        gdk::Key::Up | gdk::Key::KP_Up => key(1, 'A'),
+        gdk::Key::Down | gdk::Key::KP_Down => key(1, 'B'),
+        gdk::Key::Right | gdk::Key::KP_Right => key(1, 'C'),
+        gdk::Key::Left | gdk::Key::KP_Left => key(1, 'D'),
+        gdk::Key::Home | gdk::Key::KP_Home => key(1, 'H'),
+        gdk::Key::End | gdk::Key::KP_End => key(1, 'F'),
+        gdk::Key::Insert | gdk::Key::KP_Insert => key(2, '~'),
+        gdk::Key::Delete | gdk::Key::KP_Delete => key(3, '~'),
+        gdk::Key::Page_Up | gdk::Key::KP_Page_Up => key(5, '~'),
+        gdk::Key::Page_Down | gdk::Key::KP_Page_Down => key(6, '~'),
+        gdk::Key::F1 => key(1, 'P'),
+        gdk::Key::F2 => key(1, 'Q'),
+        // `CSI R` would be mistaken for a cursor position report.
+        gdk::Key::F3 if kitty => key(13, '~'),
+        gdk::Key::F3 => key(1, 'R'),
+        gdk::Key::F4 => key(1, 'S'),
+        gdk::Key::F5 => key(15, '~'),
+        gdk::Key::F6 => key(17, '~'),
+        gdk::Key::F7 => key(18, '~'),
+        gdk::Key::F8 => key(19, '~'),
+        gdk::Key::F9 => key(20, '~'),
+        gdk::Key::F10 => key(21, '~'),
+        gdk::Key::F11 => key(23, '~'),
+        gdk::Key::F12 => key(24, '~'),
+        _ => None,
+    }
+}
+
+/// Keys that send C0 controls in the legacy encoding, with their kitty code.
+fn control_key(keyval: gdk::Key) -> Option<u32> {
+    match keyval {
+        gdk::Key::Escape => Some(27),
+        gdk::Key::Return | gdk::Key::KP_Enter => Some(13),
+        gdk::Key::Tab | gdk::Key::ISO_Left_Tab | gdk::Key::KP_Tab => Some(9),
+        gdk::Key::BackSpace => Some(127),
+        _ => None,
+    }
+}
+
+/// Kitty's private-use codes for the modifier keys themselves, only sent when
+/// every key is reported.
+fn modifier_key(keyval: gdk::Key) -> Option<u32> {
+    match keyval {
+        gdk::Key::Caps_Lock => Some(57358),
+        gdk::Key::Num_Lock => Some(57360),
+        gdk::Key::Shift_L => Some(57441),
+        gdk::Key::Control_L => Some(57442),
+        gdk::Key::Alt_L => Some(57443),
+        gdk::Key::Super_L => Some(57444),
+        gdk::Key::Hyper_L => Some(57445),
+        gdk::Key::Meta_L => Some(57446),
+        gdk::Key::Shift_R => Some(57447),
+        gdk::Key::Control_R => Some(57448),
+        gdk::Key::Alt_R => Some(57449),
+        gdk::Key::Super_R => Some(57450),
+        gdk::Key::Hyper_R => Some(57451),
+        gdk::Key::Meta_R => Some(57452),
+        _ => None,
+    }
+}
+
+/// Encodes a key event, or returns `None` if the terminal has nothing to
+/// send for it and the key should be left to the rest of the UI.
+pub fn encode(input: &KeyInput, modes: &KeyboardModes) -> Option<Vec<u8>> {
+    if modes.kitty_flags != 0 {
+        return encode_kitty(input, modes);
+    }
+    if input.kind == KeyEventKind::Release {
+        return None;
+    }
+    encode_legacy(input, modes)
+}
+
+fn encode_legacy(input: &KeyInput, modes: &KeyboardModes) -> Option<Vec<u8>> {
+    let mods = Modifiers::new(input.state);
+    let keyval = input.keyval;
+
+    if let Some(key) = functional(keyval, false) {
+        return Some(functional_sequence(&key, mods.param(), None, modes.application_cursor));
+    }
+
+    if modes.application_keypad
+        && let Some(final_byte) = keypad_final(keyval)
+    {
+        return Some(format!("\x1bO{}", final_byte).into_bytes());
+    }
+
+    let escape_prefixed = |bytes: &[u8]| {
+        let mut out = if mods.alt { vec![0x1b] } else { Vec::new() };
+        out.extend_from_slice(bytes);
+        Some(out)
+    };
+
+    if let Some(code) = control_key(keyval) {
+        let modified = mods.param() > 1;
+        if modified && modes.modify_other_keys > 0 && !(code == 9 && mods.param() == 2) {
+            return Some(format!("\x1b[27;{};{}~", mods.param(), code).into_bytes());
+        }
+        return match code {
+            9 if mods.shift => Some(b"\x1b[Z".to_vec()),
+            127 if mods.ctrl => escape_prefixed(b"\x08"),
+            _ => escape_prefixed(&[code as u8]),
+        };
+    }
+
+    let ch = keyval.to_unicode().filter(|ch| !ch.is_control())?;
+    // Shift alone is already part of the character.
+    let modified = mods.ctrl || mods.alt || mods.super_;
+    let control = if mods.ctrl { control_byte(ch) } else { None };
+    if modified && (modes.modify_other_keys == 2 || (modes.modify_other_keys == 1 && mods.ctrl && control.is_none())) {
+        return Some(format!("\x1b[27;{};{}~", mods.param(), u32::from(ch)).into_bytes());
+    }
+    if mods.super_ {
+        // Command/Super shortcuts belong to the application menus.
+        return None;
+    }
+    match control {
+        Some(byte) => escape_prefixed(&[byte]),
+        None if mods.ctrl => None,
+        None => escape_prefixed(ch.encode_utf8(&mut [0; 4]).as_bytes()),
+    }
+}
+
+fn encode_kitty(input: &KeyInput, modes: &KeyboardModes) -> Option<Vec<u8>> {
+    let flags = modes.kitty_flags;
+    let mods = Modifiers::new(input.state);
+    let all_keys = flags & REPORT_ALL_KEYS != 0;
+    let event = match input.kind {
+        _ if flags & REPORT_EVENT_TYPES == 0 => None,
+        KeyEventKind::Press => None,
+        KeyEventKind::Repeat => Some(2),
+        KeyEventKind::Release => Some(3),
+    };
+    if input.kind == KeyEventKind::Release && event.is_none() {
+        return None;
+    }
+
+    if let Some(key) = functional(input.keyval, true) {
+        return Some(functional_sequence(&key, mods.param(), event, modes.application_cursor));
+    }
+
+    if let Some(code) = modifier_key(input.keyval) {
+        return all_keys.then(|| csi_u(code, None, mods.param(), event, None));
+    }
+
+    if let Some(code) = control_key(input.keyval) {
+        // Unmodified Enter, Tab and Backspace keep their legacy bytes so a
+        // shell that crashed mid-protocol stays usable.
+        if code != 27 && !all_keys && mods.param() == 1 {
+            return match input.kind {
+                KeyEventKind::Release => None,
+                _ => Some(vec![code as u8]),
+            };
+        }
+        return Some(csi_u(code, None, mods.param(), event, None));
+    }
+
+    let ch = input.keyval.to_unicode().filter(|ch| !ch.is_control())?;
+    let base = input.base.to_unicode().filter(|ch| !ch.is_control()).unwrap_or(ch);
+    let code = u32::from(base.to_lowercase().next().unwrap_or(base));
+
+    let plain = !(mods.ctrl || mods.alt || mods.super_);
+    if plain && !all_keys {
+        return match input.kind {
+            KeyEventKind::Release => None,
+            _ => Some(ch.to_string().into_bytes()),
+        };
+    }
+
+    let shifted = (flags & REPORT_ALTERNATE_KEYS != 0 && mods.shift && u32::from(ch) != code).then_some(u32::from(ch));
+    let text = (flags & REPORT_TEXT != 0 && plain && input.kind != KeyEventKind::Release).then_some(ch);
+    Some(csi_u(code, shifted, mods.param(), event, text))
+}
+
+/// `CSI number ; modifiers[:event] final`, collapsing to the short forms when
+/// there is nothing to report.
+fn functional_sequence(key: &Functional, modifiers: u32, event: Option<u8>, application_cursor: bool) -> Vec<u8> {
+    let mut out = String::from("\x1b[");
+    if modifiers == 1 && event.is_none() {
+        if key.final_byte == '~' {
+            out.push_str(&key.number.to_string());
+        } else if application_cursor || matches!(key.final_byte, 'P' | 'Q' | 'R' | 'S') {
+            out = String::from("\x1bO");
+        }
+    } else {
+        out.push_str(&format!("{};{}", key.number, modifiers));
+        if let Some(event) = event {
+            out.push_str(&format!(":{}", event));
+        }
+    }
+    out.push(key.final_byte);
+    out.into_bytes()
+}
+
+/// `CSI code[:shifted] ; modifiers[:event] ; text u`
+fn csi_u(code: u32, shifted: Option<u32>, modifiers: u32, event: Option<u8>, text: Option<char>) -> Vec<u8> {
+    let mut out = format!("\x1b[{}", code);
+    if let Some(shifted) = shifted {
+        out.push_str(&format!(":{}", shifted));
+    }
+    if modifiers > 1 || event.is_some() || text.is_some() {
+        out.push_str(&format!(";{}", modifiers));
+        if let Some(event) = event {
+            out.push_str(&format!(":{}", event));
+        }
+    }
+    if let Some(text) = text {
+        out.push_str(&format!(";{}", u32::from(text)));
+    }
+    out.push('u');
+    out.into_bytes()
+}
+
+/// The legacy Ctrl+key byte, e.g. Ctrl+A is 0x01 and Ctrl+[ is ESC.
+fn control_byte(ch: char) -> Option<u8> {
+    match ch {
+        'a'..='z' | 'A'..='Z' => Some(ch.to_ascii_lowercase() as u8 & 0x1f),
+        '@' | ' ' | '2' => Some(0x00),
+        '[' | '3' => Some(0x1b),
+        '\\' | '4' => Some(0x1c),
+        ']' | '5' => Some(0x1d),
+        '^' | '6' => Some(0x1e),
+        '_' | '/' | '7' => Some(0x1f),
+        '?' | '8' => Some(0x7f),
+        _ => None,
+    }
+}
+
+/// Final byte of the `SS3` sequence a keypad key sends in application keypad mode.
+fn keypad_final(keyval: gdk::Key) -> Option<char> {
+    Some(match keyval {
+        gdk::Key::KP_Enter => 'M',
+        gdk::Key::KP_Multiply => 'j',
+        gdk::Key::KP_Add => 'k',
+        gdk::Key::KP_Separator => 'l',
+        gdk::Key::KP_Subtract => 'm',
+        gdk::Key::KP_Decimal => 'n',
+        gdk::Key::KP_Divide => 'o',
+        gdk::Key::KP_0 => 'p',
+        gdk::Key::KP_1 => 'q',
+        gdk::Key::KP_2 => 'r',
+        gdk::Key::KP_3 => 's',
+        gdk::Key::KP_4 => 't',
+        gdk::Key::KP_5 => 'u',
+        gdk::Key::KP_6 => 'v',
+        gdk::Key::KP_7 => 'w',
+        gdk::Key::KP_8 => 'x',
+        gdk::Key::KP_9 => 'y',
+        gdk::Key::KP_Equal => 'X',
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHIFT: gdk::ModifierType = gdk::ModifierType::SHIFT_MASK;
+    const CTRL: gdk::ModifierType = gdk::ModifierType::CONTROL_MASK;
+    const ALT: gdk::ModifierType = gdk::ModifierType::ALT_MASK;
+
+    fn input(keyval: gdk::Key, state: gdk::ModifierType, kind: KeyEventKind) -> KeyInput {
+        KeyInput { keyval, base: keyval, state, kind }
+    }
+
+    fn press(keyval: gdk::Key, state: gdk::ModifierType, modes: &KeyboardModes) -> Option<Vec<u8>> {
+        encode(&input(keyval, state, KeyEventKind::Press), modes)
+    }
+
+    fn kitty(flags: u8) -> KeyboardModes {
+        KeyboardModes { kitty_flags: flags, ..KeyboardModes::default() }
+    }
+
+    fn bytes(bytes: &[u8]) -> Option<Vec<u8>> {
+        Some(bytes.to_vec())
+    }
+
+    #[test]
+    fn legacy_characters() {
+        let modes = KeyboardModes::default();
+        let none = gdk::ModifierType::empty();
+        assert_eq!(press(gdk::Key::a, none, &modes), bytes(b"a"));
+        assert_eq!(press(gdk::Key::A, SHIFT, &modes), bytes(b"A"));
+        assert_eq!(press(gdk::Key::a, CTRL, &modes), bytes(b"\x01"));
+        assert_eq!(press(gdk::Key::bracketleft, CTRL, &modes), bytes(b"\x1b"));
+        assert_eq!(press(gdk::Key::a, ALT, &modes), bytes(b"\x1ba"));
+        assert_eq!(press(gdk::Key::a, CTRL | ALT, &modes), bytes(b"\x1b\x01"));
+        // Ctrl with a key that has no control byte is left to the UI, and so
+        // are Super shortcuts.
+        assert_eq!(press(gdk::Key::semicolon, CTRL, &modes), None);
+        assert_eq!(press(gdk::Key::c, gdk::ModifierType::SUPER_MASK, &modes), None);
+        assert_eq!(encode(&input(gdk::Key::a, none, KeyEventKind::Release), &modes), None);
+    }
+
+    #[test]
+    fn legacy_control_keys() {
+        let modes = KeyboardModes::default();
+        let none = gdk::ModifierType::empty();
+        assert_eq!(press(gdk::Key::Return, none, &modes), bytes(b"\r"));
+        assert_eq!(press(gdk::Key::Escape, none, &modes), bytes(b"\x1b"));
+        assert_eq!(press(gdk::Key::BackSpace, none, &modes), bytes(b"\x7f"));
+        assert_eq!(press(gdk::Key::BackSpace, CTRL, &modes), bytes(b"\x08"));
+        assert_eq!(press(gdk::Key::BackSpace, ALT, &modes), bytes(b"\x1b\x7f"));
+        assert_eq!(press(gdk::Key::ISO_Left_Tab, SHIFT, &modes), bytes(b"\x1b[Z"));
+    }
+
+    #[test]
+    fn legacy_functional_keys() {
+        let mut modes = KeyboardModes::default();
+        let none = gdk::ModifierType::empty();
+        assert_eq!(press(gdk::Key::Up, none, &modes), bytes(b"\x1b[A"));
+        assert_eq!(press(gdk::Key::Up, SHIFT, &modes), bytes(b"\x1b[1;2A"));
+        assert_eq!(press(gdk::Key::Home, CTRL | ALT, &modes), bytes(b"\x1b[1;7H"));
+        assert_eq!(press(gdk::Key::Delete, none, &modes), bytes(b"\x1b[3~"));
+        assert_eq!(press(gdk::Key::Page_Up, CTRL, &modes), bytes(b"\x1b[5;5~"));
+        assert_eq!(press(gdk::Key::F1, none, &modes), bytes(b"\x1bOP"));
+        assert_eq!(press(gdk::Key::F1, SHIFT, &modes), bytes(b"\x1b[1;2P"));
+        assert_eq!(press(gdk::Key::F5, none, &modes), bytes(b"\x1b[15~"));
+        assert_eq!(press(gdk::Key::F12, CTRL, &modes), bytes(b"\x1b[24;5~"));
+
+        modes.application_cursor = true;
+        assert_eq!(press(gdk::Key::Up, none, &modes), bytes(b"\x1bOA"));
+        assert_eq!(press(gdk::Key::Up, SHIFT, &modes), bytes(b"\x1b[1;2A"));
+    }
+
+    #[test]
+    fn application_keypad() {
+        let mut modes = KeyboardModes::default();
+        let none = gdk::ModifierType::empty();
+        assert_eq!(press(gdk::Key::KP_Enter, none, &modes), bytes(b"\r"));
+        modes.application_keypad = true;
+        assert_eq!(press(gdk::Key::KP_Enter, none, &modes), bytes(b"\x1bOM"));
+        assert_eq!(press(gdk::Key::KP_1, none, &modes), bytes(b"\x1bOq"));
+        assert_eq!(press(gdk::Key::KP_Add, none, &modes), bytes(b"\x1bOk"));
+    }
+
+    #[test]
+    fn modify_other_keys() {
+        let mut modes = KeyboardModes { modify_other_keys: 1, ..KeyboardModes::default() };
+        let none = gdk::ModifierType::empty();
+        assert_eq!(press(gdk::Key::Return, CTRL, &modes), bytes(b"\x1b[27;5;13~"));
+        assert_eq!(press(gdk::Key::Tab, CTRL, &modes), bytes(b"\x1b[27;5;9~"));
+        // Shift+Tab keeps its back-tab sequence.
+        assert_eq!(press(gdk::Key::ISO_Left_Tab, SHIFT, &modes), bytes(b"\x1b[Z"));
+        // Level 1 leaves keys with a well-known control byte alone.
+        assert_eq!(press(gdk::Key::a, CTRL, &modes), bytes(b"\x01"));
+        assert_eq!(press(gdk::Key::semicolon, CTRL, &modes), bytes(b"\x1b[27;5;59~"));
+        assert_eq!(press(gdk::Key::a, none, &modes), bytes(b"a"));
+
+        modes.modify_other_keys = 2;
+        assert_eq!(press(gdk::Key::a, CTRL, &modes), bytes(b"\x1b[27;5;97~"));
+        assert_eq!(press(gdk::Key::a, ALT, &modes), bytes(b"\x1b[27;3;97~"));
+        assert_eq!(press(gdk::Key::A, SHIFT, &modes), bytes(b"A"));
+    }
+
+    #[test]
+    fn kitty_disambiguates() {
+        let modes = kitty(1);
+        let none = gdk::ModifierType::empty();
+        assert_eq!(press(gdk::Key::a, none, &modes), bytes(b"a"));
+        assert_eq!(press(gdk::Key::A, SHIFT, &modes), bytes(b"A"));
+        assert_eq!(press(gdk::Key::a, CTRL, &modes), bytes(b"\x1b[97;5u"));
+        assert_eq!(press(gdk::Key::a, ALT, &modes), bytes(b"\x1b[97;3u"));
+        assert_eq!(press(gdk::Key::Escape, none, &modes), bytes(b"\x1b[27u"));
+        assert_eq!(press(gdk::Key::Return, none, &modes), bytes(b"\r"));
+        assert_eq!(press(gdk::Key::Return, SHIFT, &modes), bytes(b"\x1b[13;2u"));
+        assert_eq!(press(gdk::Key::F3, none, &modes), bytes(b"\x1b[13~"));
+        assert_eq!(press(gdk::Key::Left, CTRL, &modes), bytes(b"\x1b[1;5D"));
+        assert_eq!(press(gdk::Key::Shift_L, none, &modes), None);
+        assert_eq!(encode(&input(gdk::Key::a, CTRL, KeyEventKind::Release), &modes), None);
+    }
+
+    #[test]
+    fn kitty_event_types() {
+        let modes = kitty(1 | REPORT_EVENT_TYPES);
+        let none = gdk::ModifierType::empty();
+        let event = |keyval, state, kind| encode(&input(keyval, state, kind), &modes);
+        assert_eq!(event(gdk::Key::a, CTRL, KeyEventKind::Repeat), bytes(b"\x1b[97;5:2u"));
+        assert_eq!(event(gdk::Key::a, CTRL, KeyEventKind::Release), bytes(b"\x1b[97;5:3u"));
+        assert_eq!(event(gdk::Key::Up, none, KeyEventKind::Release), bytes(b"\x1b[1;1:3A"));
+        assert_eq!(event(gdk::Key::Escape, none, KeyEventKind::Release), bytes(b"\x1b[27;1:3u"));
+        // Plain text and legacy control keys are never reported as released.
+        assert_eq!(event(gdk::Key::a, none, KeyEventKind::Release), None);
+        assert_eq!(event(gdk::Key::Return, none, KeyEventKind::Release), None);
+    }
+
+    #[test]
+    fn kitty_all_keys_and_alternates() {
+        let modes = kitty(1 | REPORT_ALTERNATE_KEYS | REPORT_ALL_KEYS);
+        let none = gdk::ModifierType::empty();
+        assert_eq!(press(gdk::Key::a, none, &modes), bytes(b"\x1b[97u"));
+        assert_eq!(press(gdk::Key::Return, none, &modes), bytes(b"\x1b[13u"));
+        assert_eq!(press(gdk::Key::Shift_L, none, &modes), bytes(b"\x1b[57441u"));
+        let shifted = KeyInput { keyval: gdk::Key::A, base: gdk::Key::a, state: SHIFT, kind: KeyEventKind::Press };
+        assert_eq!(encode(&shifted, &modes), bytes(b"\x1b[97:65;2u"));
+    }
+
+    #[test]
+    fn kitty_associated_text() {
+        let modes = kitty(REPORT_ALL_KEYS | REPORT_TEXT);
+        let none = gdk::ModifierType::empty();
+        assert_eq!(press(gdk::Key::a, none, &modes), bytes(b"\x1b[97;1;97u"));
+        assert_eq!(press(gdk::Key::a, CTRL, &modes), bytes(b"\x1b[97;5u"));
+    }
+}
@@ -1,7 +1,27 @@
+mod config;
+mod keyboard;
+mod open;
+mod paste;
+mod pty;
+mod search_bar;
+mod shell_integration;
+mod terminal;
+mod view;
+
 use gtk4::prelude::*;
-use gtk4::{glib, Application, ApplicationWindow, Box, Button, ColorButton, Label, Orientation, Scale, ScrolledWindow};
+use gtk4::{glib, Application, ApplicationWindow, Box, Button, ColorButton, Label, Orientation, Scale};
+use config::Config;
+use pty::{PtyEvent, PtySession};
+use search_bar::SearchBar;
+use view::TerminalView;
+use std::ffi::OsString;
+use std::path::Path;
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+/// Longest the main loop spends feeding shell output before handling events.
+const OUTPUT_TIME_SLICE: Duration = Duration::from_millis(8);
 
 // External C functions from our bridge
 unsafe extern "C" {
@@ -62,17 +82,19 @@ fn load_css() {
             font-family: 'SF Mono', Monaco, Menlo, 'DejaVu Sans Mono', 'Bitstream Vera Sans Mono', monospace;
             font-size: 14px;
         }
-        .terminal-text.gray {
-            color: #888;
-        }
-        .terminal-text.white {
-            color: #fff;
-        }
-        .terminal-text.cyan {
-            color: #00ffff;
+        .resize-overlay {
+            background: rgba(38, 38, 51, 0.85);
+            color: white;
+            border-radius: 8px;
+            padding: 8px 16px;
+            font-family: 'SF Mono', Monaco, Menlo, 'DejaVu Sans Mono', 'Bitstream Vera Sans Mono', monospace;
+            font-size: 18px;
+            font-weight: bold;
         }
-        .terminal-text.green {
-            color: #00ff00;
+        .search-bar {
+            background: rgba(38, 38, 51, 0.92);
+            border-radius: 8px;
+            padding: 6px;
         }
         .controls-panel {
             background: rgba(38, 38, 51, 1.0);
@@ -126,35 +148,11 @@ fn load_css() {
     );
 }
 
-fn create_terminal_line(text: &str, color: &str) -> Label {
-    let label = Label::new(Some(text));
-    label.set_halign(gtk4::Align::Start);
-    label.add_css_class("terminal-text");
-    label.add_css_class(color);
-    label
-}
-
-fn create_terminal_prompt() -> Box {
-    let prompt_box = Box::new(Orientation::Horizontal, 6);
-    
-    let tilde = Label::new(Some("~"));
-    tilde.add_css_class("terminal-text");
-    tilde.add_css_class("cyan");
-    
-    let arrow = Label::new(Some("$"));
-    arrow.add_css_class("terminal-text");
-    arrow.add_css_class("green");
-    
-    prompt_box.append(&tilde);
-    prompt_box.append(&arrow);
-    
-    prompt_box
-}
-
-fn build_ui(app: &Application) {
+/// Opens a terminal window running the login shell, or `program` when given.
+fn build_ui(app: &Application, working_directory: Option<&Path>, program: Option<&[OsString]>) {
     let window = ApplicationWindow::builder()
         .application(app)
-        .title("Terminal - Custom Blur API")
+        .title("Terminal")
         .default_width(800)
         .default_height(700)
         .build();
@@ -183,35 +181,105 @@ fn build_ui(app: &Application) {
     let terminal_background = Box::new(Orientation::Vertical, 0);
     terminal_background.add_css_class("terminal-background");
     
-    let terminal_scroll = ScrolledWindow::builder()
-        .vexpand(true)
-        .hexpand(true)
-        .build();
+    let terminal_scroll = Box::new(Orientation::Horizontal, 0);
+    terminal_scroll.set_vexpand(true);
+    terminal_scroll.set_hexpand(true);
     terminal_scroll.add_css_class("terminal-viewport");
-    
-    let terminal_content = Box::new(Orientation::Vertical, 6);
-    terminal_content.set_margin_start(12);
-    terminal_content.set_margin_end(12);
-    terminal_content.set_margin_top(12);
-    terminal_content.set_margin_bottom(12);
-    
-    terminal_content.append(&create_terminal_line("Last login: Sun Oct 19 14:23:45 on ttys001", "gray"));
-    terminal_content.append(&create_terminal_prompt());
-    
-    for i in 0..20 {
-        terminal_content.append(&create_terminal_prompt());
-        terminal_content.append(&create_terminal_line(
-            &format!("echo 'Testing blur radius: {}'", i * 5),
-            "white"
-        ));
-        terminal_content.append(&create_terminal_line(
-            &format!("Testing blur radius: {}", i * 5),
-            "cyan"
-        ));
-    }
-    
-    terminal_content.append(&create_terminal_prompt());
-    terminal_scroll.set_child(Some(&terminal_content));
+
+    let config = Rc::new(Config::load());
+    let terminal_view = TerminalView::new(config.clone());
+    terminal_view.set_hexpand(true);
+    terminal_view.set_vexpand(true);
+    terminal_view.set_margin_start(12);
+    terminal_view.set_margin_end(12);
+    terminal_view.set_margin_top(12);
+    terminal_view.set_margin_bottom(12);
+
+    // Grid size badge shown over the glass while the window is being resized
+    let terminal_overlay = gtk4::Overlay::new();
+    terminal_overlay.set_hexpand(true);
+    terminal_overlay.set_vexpand(true);
+    terminal_overlay.set_child(Some(&terminal_view));
+    let resize_label = Label::new(None);
+    resize_label.add_css_class("resize-overlay");
+    resize_label.set_halign(gtk4::Align::Center);
+    resize_label.set_valign(gtk4::Align::Center);
+    resize_label.set_can_target(false);
+    resize_label.set_visible(false);
+    terminal_overlay.add_overlay(&resize_label);
+    let search_bar = SearchBar::new(&terminal_view);
+    terminal_overlay.add_overlay(search_bar.widget());
+    terminal_scroll.append(&terminal_overlay);
+
+    let resize_label_timeout: Rc<RefCell<Option<glib::SourceId>>> = Rc::new(RefCell::new(None));
+    terminal_view.connect_grid_resized(move |_, cols, rows| {
+        resize_label.set_text(&format!("{}×{}", cols, rows));
+        resize_label.set_visible(true);
+        if let Some(source) = resize_label_timeout.borrow_mut().take() {
+            source.remove();
+        }
+        let label = resize_label.clone();
+        let timeout = resize_label_timeout.clone();
+        let source = glib::timeout_add_local_once(std::time::Duration::from_millis(1000), move || {
+            label.set_visible(false);
+            timeout.borrow_mut().take();
+        });
+        *resize_label_timeout.borrow_mut() = Some(source);
+    });
+
+    // Search matches are marked with ticks drawn over the scrollbar
+    let terminal_scrollbar = gtk4::Scrollbar::new(Orientation::Vertical, Some(&terminal_view.adjustment()));
+    let scrollbar_overlay = gtk4::Overlay::new();
+    scrollbar_overlay.set_child(Some(&terminal_scrollbar));
+    scrollbar_overlay.add_overlay(search_bar.ticks());
+    terminal_scroll.append(&scrollbar_overlay);
+
+    // Ctrl+Shift+F opens the search bar, caught before the terminal sees it
+    let shortcuts = gtk4::ShortcutController::new();
+    shortcuts.set_propagation_phase(gtk4::PropagationPhase::Capture);
+    shortcuts.add_shortcut(gtk4::Shortcut::new(
+        gtk4::ShortcutTrigger::parse_string("<Control><Shift>f"),
+        Some(gtk4::CallbackAction::new({
+            let search_bar = search_bar.clone();
+            move |_, _| {
+                search_bar.show();
+                glib::Propagation::Stop
+            }
+        })),
+    ));
+    // Ctrl+Shift+N opens another window in the shell's current directory and
+    // Ctrl+Shift+E shows that directory in the file manager
+    shortcuts.add_shortcut(gtk4::Shortcut::new(
+        gtk4::ShortcutTrigger::parse_string("<Control><Shift>n"),
+        Some(gtk4::CallbackAction::new({
+            let app = app.clone();
+            let terminal_view = terminal_view.clone();
+            move |_, _| {
+                build_ui(&app, Some(&terminal_view.working_directory()), None);
+                glib::Propagation::Stop
+            }
+        })),
+    ));
+    shortcuts.add_shortcut(gtk4::Shortcut::new(
+        gtk4::ShortcutTrigger::parse_string("<Control><Shift>e"),
+        Some(gtk4::CallbackAction::new({
+            let terminal_view = terminal_view.clone();
+            move |_, _| {
+                open::open_uri(&gtk4::gio::File::for_path(terminal_view.working_directory()).uri());
+                glib::Propagation::Stop
+            }
+        })),
+    ));
+    window.add_controller(shortcuts);
+
+    let session = match PtySession::spawn(80, 24, program, working_directory, &config.term, config.shell_integration) {
+        Ok(session) => Some(Rc::new(session)),
+        Err(err) => {
+            eprintln!("❌ Failed to spawn shell: {}", err);
+            terminal_view.feed(format!("Failed to spawn shell: {}\r\n", err).as_bytes());
+            None
+        }
+    };
     
     // Add the scroll window to the background box using append()
     terminal_background.append(&terminal_scroll);
@@ -360,6 +428,13 @@ fn build_ui(app: &Application) {
             update_background(&color, opacity);
         });
     }
+
+    // The tint follows the background applications set through OSC 11,
+    // keeping the chosen opacity
+    terminal_view.connect_background_changed({
+        let color_button = color_button.clone();
+        move |_, color| color_button.set_rgba(color)
+    });
     
     // Preset colors
     let presets_box = Box::new(Orientation::Horizontal, 8);
@@ -452,6 +527,66 @@ fn build_ui(app: &Application) {
     main_box.append(&controls);
     
     window.set_child(Some(&main_box));
+
+    if let Some(session) = session {
+        terminal_view.set_session(session.clone());
+        window.set_title(Some(&terminal_view.title()));
+        terminal_view.connect_title_changed({
+            let window_weak = window.downgrade();
+            move |_, title| {
+                if let Some(win) = window_weak.upgrade() {
+                    win.set_title(Some(title));
+                }
+            }
+        });
+
+        // Drain shell output when the reader thread says there is some. The
+        // watch runs below redraw priority so a flood of output cannot starve
+        // drawing, and each pass takes a few milliseconds at most so input
+        // still gets its turn; the view redraws once per frame however many
+        // reads it was fed.
+        let window_weak = window.downgrade();
+        let terminal_view_weak = terminal_view.downgrade();
+        let output_watch: Rc<RefCell<Option<glib::SourceId>>> = Rc::new(RefCell::new(None));
+        let output_watch_clone = output_watch.clone();
+        let (wakeup, priority) = (session.wakeup_fd(), glib::Priority::DEFAULT_IDLE);
+        let watch = glib::unix_fd_add_local_full(wakeup, priority, glib::IOCondition::IN, move |_, _| {
+            session.clear_wakeup();
+            let deadline = Instant::now() + OUTPUT_TIME_SLICE;
+            while Instant::now() < deadline
+                && let Some(event) = session.try_recv()
+            {
+                match event {
+                    PtyEvent::Output(bytes) => {
+                        if let Some(view) = terminal_view_weak.upgrade() {
+                            view.feed(&bytes);
+                        }
+                    }
+                    PtyEvent::Exited => {
+                        output_watch_clone.borrow_mut().take();
+                        if let Some(win) = window_weak.upgrade() {
+                            win.close();
+                        }
+                        return glib::ControlFlow::Break;
+                    }
+                }
+            }
+            if Instant::now() >= deadline {
+                // Come back for whatever is still queued.
+                session.wake();
+            }
+            glib::ControlFlow::Continue
+        });
+        *output_watch.borrow_mut() = Some(watch);
+
+        // The watch holds on to the session; removing it with the window drops
+        // the session, which hangs up on the shell.
+        window.connect_destroy(move |_| {
+            if let Some(watch) = output_watch.borrow_mut().take() {
+                watch.remove();
+            }
+        });
+    }
     
     // Handle window close to avoid GTK warnings
     window.connect_close_request(|_| {
@@ -479,6 +614,7 @@ fn build_ui(app: &Application) {
     
     load_css();
     window.present();
+    terminal_view.grab_focus();
 }
 
 fn main() -> glib::ExitCode {
@@ -486,6 +622,6 @@ fn main() -> glib::ExitCode {
         .application_id("com.example.macos-term")
         .build();
 
-    app.connect_activate(build_ui);
+    app.connect_activate(|app| build_ui(app, None, None));
     app.run()
 }
\ No newline at end of file
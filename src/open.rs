@@ -0,0 +1,78 @@
+//! Opening links clicked in the terminal with other applications.
+
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use gtk4::gio;
+use gtk4::prelude::*;
+
+/// Opens `uri` with the desktop's handler for its scheme.
+pub fn open_uri(uri: &str) {
+    match gio::AppInfo::launch_default_for_uri(uri, None::<&gio::AppLaunchContext>) {
+        Ok(()) => println!("✅ Opened {}", uri),
+        Err(err) => eprintln!("❌ Failed to open {}: {}", uri, err),
+    }
+}
+
+/// Resolves a path printed in the terminal against `cwd`, expanding `~`.
+/// Returns `None` if nothing exists there.
+pub fn resolve_path(path: &str, cwd: &Path) -> Option<PathBuf> {
+    let path = match path.strip_prefix("~/") {
+        Some(rest) => PathBuf::from(env::var_os("HOME")?).join(rest),
+        None => cwd.join(path),
+    };
+    path.exists().then_some(path)
+}
+
+/// Editors that open a window of their own. Any other editor is taken to
+/// need a terminal.
+const GUI_EDITORS: &[&str] = &[
+    "atom", "bbedit", "code", "codium", "cursor", "gedit", "gnome-text-editor", "gvim", "kate", "mate", "mvim",
+    "subl", "zed",
+];
+
+/// Opens a file in `$VISUAL` or `$EDITOR`, at `line` when given (as
+/// `+line`, which most editors understand), or with the desktop's handler
+/// when neither is set. The variable goes through `sh` the way git runs it,
+/// so quoted arguments in it work. Editors with a window of their own are
+/// started directly; terminal editors get a new terminal window in `cwd`.
+pub fn open_file(path: &Path, line: Option<u32>, cwd: &Path) {
+    let editor = env::var("VISUAL").or_else(|_| env::var("EDITOR")).unwrap_or_default();
+    let Some(program) = editor.split_whitespace().next() else {
+        open_uri(&gio::File::for_path(path).uri());
+        return;
+    };
+    let name = program.rsplit('/').next().unwrap_or(program);
+
+    let script = format!("exec {} \"$@\"", editor);
+    let mut argv: Vec<OsString> = vec!["/bin/sh".into(), "-c".into(), script.into(), name.into()];
+    if let Some(line) = line {
+        argv.push(format!("+{}", line).into());
+    }
+    argv.push(path.into());
+
+    if !GUI_EDITORS.contains(&name) {
+        match gio::Application::default().and_downcast::<gtk4::Application>() {
+            Some(app) => {
+                crate::build_ui(&app, Some(cwd), Some(&argv));
+                println!("✅ Opened {} in {}", path.display(), name);
+            }
+            None => open_uri(&gio::File::for_path(path).uri()),
+        }
+        return;
+    }
+
+    let mut command = Command::new(&argv[0]);
+    command.args(&argv[1..]).stdin(Stdio::null());
+    match command.spawn() {
+        Ok(mut child) => {
+            println!("✅ Opened {} in {}", path.display(), name);
+            // Reap the editor whenever it exits
+            thread::spawn(move || child.wait());
+        }
+        Err(err) => eprintln!("❌ Failed to run {}: {}", program, err),
+    }
+}
@@ -0,0 +1,77 @@
+//! Preparing clipboard text for the shell.
+//!
+//! With bracketed paste (mode 2004) the text is wrapped in `ESC [200~` /
+//! `ESC [201~` so the application can tell it apart from typing. Without it,
+//! every line break in the text is an Enter key press, so pastes that would
+//! run commands are confirmed first.
+
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// Whether `text` should be confirmed before it is pasted: it would run one or
+/// more commands, or escalate privileges, in a shell that cannot tell a paste
+/// from typing.
+pub fn needs_confirmation(text: &str, bracketed: bool) -> bool {
+    if bracketed {
+        return false;
+    }
+    let multi_line = text.contains(['\n', '\r']);
+    let sudo = text
+        .split(|ch: char| !(ch.is_alphanumeric() || ch == '_' || ch == '-'))
+        .any(|word| word == "sudo");
+    multi_line || sudo
+}
+
+/// Converts `text` into the bytes to write to the PTY. Line breaks become
+/// carriage returns, as typed, and control characters are dropped so a paste
+/// cannot smuggle in escape sequences (including a premature end marker).
+pub fn encode(text: &str, bracketed: bool) -> Vec<u8> {
+    let text = text.replace("\r\n", "\r").replace('\n', "\r");
+    let filtered: String = text
+        .chars()
+        .filter(|&ch| !ch.is_control() || ch == '\t' || ch == '\r')
+        .collect();
+
+    if !bracketed {
+        return filtered.into_bytes();
+    }
+    [PASTE_START, filtered.as_bytes(), PASTE_END].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn confirms_commands_only_without_bracketed_paste() {
+        assert!(!needs_confirmation("ls -l", false));
+        assert!(needs_confirmation("ls\n", false));
+        assert!(needs_confirmation("ls\rrm -rf build", false));
+        assert!(needs_confirmation("sudo make install", false));
+        assert!(needs_confirmation("echo hi; sudo -s", false));
+        assert!(!needs_confirmation("pseudo sudoers visudo", false));
+        assert!(!needs_confirmation("ls\nsudo reboot\n", true));
+    }
+
+    #[test]
+    fn line_breaks_become_carriage_returns() {
+        assert_eq!(encode("a\nb\r\nc\rd", false), b"a\rb\rc\rd");
+        assert_eq!(encode("a\tb", false), b"a\tb");
+        assert_eq!(encode("héllo", false), "héllo".as_bytes());
+    }
+
+    #[test]
+    fn wraps_bracketed_pastes() {
+        assert_eq!(encode("ls\n", true), b"\x1b[200~ls\r\x1b[201~");
+        assert_eq!(encode("", true), b"\x1b[200~\x1b[201~");
+    }
+
+    #[test]
+    fn drops_control_characters() {
+        assert_eq!(encode("a\x1b[31mb\x07c\x00d\u{9b}e", false), b"a[31mbcde");
+        // An end marker in the text cannot close the paste early.
+        let bytes = encode("x\x1b[201~; rm -rf ~\n", true);
+        assert_eq!(bytes, b"\x1b[200~x[201~; rm -rf ~\r\x1b[201~");
+        assert_eq!(bytes.windows(PASTE_END.len()).filter(|window| *window == PASTE_END).count(), 1);
+    }
+}
@@ -0,0 +1,389 @@
+//! PTY-backed shell sessions.
+//!
+//! A `PtySession` forks the user's login shell on a pseudo-terminal, pumps the
+//! shell's output through a background reader thread and exposes the master
+//! side for writing keystrokes back. The reader rings a socket the main loop
+//! watches whenever it queues something, so the UI never has to poll.
+//! Writes never block either: input the shell is not reading yet waits in a
+//! queue that the main loop flushes as the PTY makes room for it.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::env;
+use std::ffi::{CStr, OsString};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::ptr;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+
+/// Reads queued for the UI thread before the reader stops reading, which in
+/// turn blocks the child's writes once the kernel buffer fills up.
+const PENDING_READS: usize = 16;
+
+/// Messages sent from the reader thread to the UI thread.
+pub enum PtyEvent {
+    /// A chunk of raw bytes written by the child.
+    Output(Vec<u8>),
+    /// The slave side was closed, usually because the shell exited.
+    Exited,
+}
+
+/// Input the shell has not taken yet.
+#[derive(Default)]
+struct PendingInput {
+    bytes: VecDeque<u8>,
+    /// Writes `bytes` out once the PTY has room; set while they are waiting.
+    watch: Option<glib::SourceId>,
+}
+
+pub struct PtySession {
+    master: File,
+    child: Child,
+    events: Receiver<PtyEvent>,
+    /// Readable once the reader thread has queued events; see `wakeup_fd`.
+    wakeup: UnixStream,
+    /// The other end of `wakeup`, shared with the reader thread.
+    doorbell: UnixStream,
+    input: Rc<RefCell<PendingInput>>,
+}
+
+impl PtySession {
+    /// Spawns the user's login shell on a new PTY of the given size, in
+    /// `working_directory` or `$HOME` and with `TERM` set to `term`, loading
+    /// the shell integration scripts into shells that have one if asked to.
+    /// `program`, a command and its arguments, runs instead of the shell when
+    /// given.
+    pub fn spawn(
+        cols: u16,
+        rows: u16,
+        program: Option<&[OsString]>,
+        working_directory: Option<&Path>,
+        term: &str,
+        shell_integration: bool,
+    ) -> io::Result<Self> {
+        let (master, slave) = open_pty(cols, rows)?;
+        let shell = login_shell();
+        let shell_name = shell.rsplit('/').next().unwrap_or("sh").to_string();
+
+        let mut command = match program {
+            Some([program, args @ ..]) => {
+                let mut command = Command::new(program);
+                command.args(args);
+                command
+            }
+            _ => {
+                let mut command = Command::new(&shell);
+                // A leading dash in argv[0] asks the shell to behave as a login shell.
+                command.arg0(format!("-{}", shell_name));
+                command
+            }
+        };
+        command
+            .env("TERM", term)
+            .env("COLORTERM", "truecolor")
+            .stdin(Stdio::from(slave.try_clone()?))
+            .stdout(Stdio::from(slave.try_clone()?))
+            .stderr(Stdio::from(slave));
+        let working_directory = working_directory.map(PathBuf::from).or_else(|| env::var_os("HOME").map(PathBuf::from));
+        if let Some(dir) = working_directory {
+            command.current_dir(dir);
+        }
+        if shell_integration && program.is_none() {
+            match crate::shell_integration::inject(&mut command, &shell_name) {
+                Ok(true) => println!("✅ Shell integration enabled for {}", shell_name),
+                Ok(false) => {}
+                Err(err) => eprintln!("❌ Failed to set up shell integration: {}", err),
+            }
+        }
+
+        // SAFETY: only async-signal-safe calls are made between fork and exec.
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                if libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let child = command.spawn()?;
+        println!("✅ Spawned {} (pid {})", command.get_program().to_string_lossy(), child.id());
+
+        let (wakeup, doorbell) = UnixStream::pair()?;
+        wakeup.set_nonblocking(true)?;
+        doorbell.set_nonblocking(true)?;
+        let reader = master.try_clone()?;
+        let reader_doorbell = doorbell.try_clone()?;
+        let (sender, events) = mpsc::sync_channel(PENDING_READS);
+        thread::Builder::new()
+            .name("pty-reader".into())
+            .spawn(move || read_loop(reader, sender, reader_doorbell))?;
+
+        Ok(Self { master, child, events, wakeup, doorbell, input: Rc::default() })
+    }
+
+    /// Writes bytes to the shell as if they were typed. Whatever the PTY
+    /// cannot take right away is queued and written from the main loop, after
+    /// anything queued before it.
+    pub fn write(&self, mut bytes: &[u8]) -> io::Result<()> {
+        let mut input = self.input.borrow_mut();
+        if input.bytes.is_empty() {
+            bytes = &bytes[write_some(&self.master, bytes)?..];
+            if bytes.is_empty() {
+                return Ok(());
+            }
+        }
+        input.bytes.extend(bytes);
+        if input.watch.is_none() {
+            let master = self.master.try_clone()?;
+            let pending = Rc::clone(&self.input);
+            input.watch = Some(glib::unix_fd_add_local(master.as_raw_fd(), glib::IOCondition::OUT, move |_, _| {
+                let mut input = pending.borrow_mut();
+                match write_some(&master, input.bytes.make_contiguous()) {
+                    Ok(written) => {
+                        input.bytes.drain(..written);
+                    }
+                    Err(err) => {
+                        eprintln!("❌ Failed to write to shell: {}", err);
+                        input.bytes.clear();
+                    }
+                }
+                if !input.bytes.is_empty() {
+                    return glib::ControlFlow::Continue;
+                }
+                input.watch = None;
+                glib::ControlFlow::Break
+            }));
+        }
+        Ok(())
+    }
+
+    /// Tells the PTY about a new window size. The kernel delivers SIGWINCH to
+    /// the foreground process group when the size actually changes.
+    pub fn resize(&self, cols: u16, rows: u16, width: u16, height: u16) -> io::Result<()> {
+        let size = libc::winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: width,
+            ws_ypixel: height,
+        };
+        if unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Name of the terminal's foreground process: the shell, or the command
+    /// it is running.
+    pub fn foreground_process_name(&self) -> Option<String> {
+        let pgrp = unsafe { libc::tcgetpgrp(self.master.as_raw_fd()) };
+        if pgrp <= 0 {
+            return None;
+        }
+        process_name(pgrp)
+    }
+
+    /// Working directory of the terminal's foreground process.
+    pub fn foreground_working_directory(&self) -> Option<PathBuf> {
+        let pgrp = unsafe { libc::tcgetpgrp(self.master.as_raw_fd()) };
+        if pgrp <= 0 {
+            return None;
+        }
+        process_working_directory(pgrp)
+    }
+
+    /// A descriptor that becomes readable when events are waiting. Call
+    /// `clear_wakeup` before draining them with `try_recv`, and `wake` if
+    /// some are left for later.
+    pub fn wakeup_fd(&self) -> RawFd {
+        self.wakeup.as_raw_fd()
+    }
+
+    /// Empties the wakeup socket, so it only becomes readable again once the
+    /// reader queues something new.
+    pub fn clear_wakeup(&self) {
+        let mut buf = [0u8; 256];
+        while matches!((&self.wakeup).read(&mut buf), Ok(n) if n > 0) {}
+    }
+
+    /// Makes the wakeup socket readable again, for events left in the queue.
+    pub fn wake(&self) {
+        ring(&self.doorbell);
+    }
+
+    /// Returns the next pending event without blocking.
+    pub fn try_recv(&self) -> Option<PtyEvent> {
+        self.events.try_recv().ok()
+    }
+}
+
+impl Drop for PtySession {
+    fn drop(&mut self) {
+        // Hang up on the shell the same way closing a real terminal would.
+        unsafe {
+            libc::kill(self.child.id() as libc::pid_t, libc::SIGHUP);
+        }
+        let _ = self.child.try_wait();
+        if let Some(watch) = self.input.borrow_mut().watch.take() {
+            watch.remove();
+        }
+    }
+}
+
+/// Writes as much of `bytes` as the PTY takes without blocking, returning
+/// how much that was.
+fn write_some(mut master: &File, bytes: &[u8]) -> io::Result<usize> {
+    let mut written = 0;
+    while written < bytes.len() {
+        match master.write(&bytes[written..]) {
+            Ok(n) => written += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(written)
+}
+
+fn read_loop(mut reader: File, sender: SyncSender<PtyEvent>, doorbell: UnixStream) {
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                if sender.send(PtyEvent::Output(buf[..n].to_vec())).is_err() {
+                    return;
+                }
+                ring(&doorbell);
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            // The master is non-blocking for the sake of writes, so wait for
+            // output here instead.
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                let mut poll = libc::pollfd { fd: reader.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+                unsafe { libc::poll(&mut poll, 1, -1) };
+            }
+            // EIO is how the master reports that every slave fd has been closed.
+            Err(_) => break,
+        }
+    }
+    if sender.send(PtyEvent::Exited).is_ok() {
+        ring(&doorbell);
+    }
+}
+
+/// Makes the wakeup socket readable. A full socket is readable already, so
+/// a write that would block is as good as one that went through.
+fn ring(mut doorbell: &UnixStream) {
+    let _ = doorbell.write(&[1]);
+}
+
+fn open_pty(cols: u16, rows: u16) -> io::Result<(File, OwnedFd)> {
+    let mut master: RawFd = -1;
+    let mut slave: RawFd = -1;
+    let mut size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+
+    let result = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &raw mut size,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: openpty succeeded, so both descriptors are open and owned by us.
+    let (master, slave) = unsafe { (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+    set_cloexec(master.as_raw_fd())?;
+    set_cloexec(slave.as_raw_fd())?;
+    set_nonblocking(master.as_raw_fd())?;
+    Ok((master, slave))
+}
+
+fn set_nonblocking(fd: RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn set_cloexec(fd: RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn process_name(pid: libc::pid_t) -> Option<String> {
+    let mut name = [0u8; 256];
+    let len = unsafe { libc::proc_name(pid, name.as_mut_ptr().cast(), name.len() as u32) };
+    (len > 0).then(|| String::from_utf8_lossy(&name[..len as usize]).into_owned())
+}
+
+#[cfg(not(target_os = "macos"))]
+fn process_name(pid: libc::pid_t) -> Option<String> {
+    let name = std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+    Some(name.trim_end().to_string())
+}
+
+#[cfg(target_os = "macos")]
+fn process_working_directory(pid: libc::pid_t) -> Option<PathBuf> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut info = std::mem::MaybeUninit::<libc::proc_vnodepathinfo>::zeroed();
+    let size = std::mem::size_of::<libc::proc_vnodepathinfo>() as libc::c_int;
+    let written = unsafe { libc::proc_pidinfo(pid, libc::PROC_PIDVNODEPATHINFO, 0, info.as_mut_ptr().cast(), size) };
+    if written != size {
+        return None;
+    }
+    // SAFETY: proc_pidinfo filled in the whole struct.
+    let info = unsafe { info.assume_init() };
+    let path = unsafe { CStr::from_ptr(info.pvi_cdir.vip_path.as_ptr().cast()) };
+    Some(PathBuf::from(OsStr::from_bytes(path.to_bytes())))
+}
+
+#[cfg(not(target_os = "macos"))]
+fn process_working_directory(pid: libc::pid_t) -> Option<PathBuf> {
+    std::fs::read_link(format!("/proc/{}/cwd", pid)).ok()
+}
+
+/// Resolves the user's shell from `$SHELL`, falling back to the passwd entry.
+fn login_shell() -> String {
+    if let Ok(shell) = env::var("SHELL")
+        && !shell.is_empty()
+    {
+        return shell;
+    }
+
+    unsafe {
+        let passwd = libc::getpwuid(libc::getuid());
+        if !passwd.is_null() && !(*passwd).pw_shell.is_null() {
+            return CStr::from_ptr((*passwd).pw_shell).to_string_lossy().into_owned();
+        }
+    }
+
+    "/bin/sh".to_string()
+}
@@ -0,0 +1,251 @@
+//! Find bar shown over the terminal with Ctrl+Shift+F.
+//!
+//! The bar compiles what is typed into a regex and hands it to the
+//! `TerminalView`, which highlights the matches. Enter, Ctrl+G and the up
+//! arrow move to older matches; Ctrl+Shift+G and the down arrow to newer
+//! ones. A strip drawn over the scrollbar marks where the matches are.
+
+use std::rc::Rc;
+
+use gtk4::prelude::*;
+use gtk4::{Box, Button, DrawingArea, Label, Orientation, SearchEntry, ToggleButton};
+
+use crate::terminal::search;
+use crate::view::TerminalView;
+
+pub struct SearchBar {
+    root: Box,
+    entry: SearchEntry,
+    regex: ToggleButton,
+    match_case: ToggleButton,
+    status: Label,
+    ticks: DrawingArea,
+    view: TerminalView,
+}
+
+impl SearchBar {
+    pub fn new(view: &TerminalView) -> Rc<Self> {
+        let root = Box::new(Orientation::Horizontal, 4);
+        root.add_css_class("search-bar");
+        root.set_halign(gtk4::Align::End);
+        root.set_valign(gtk4::Align::Start);
+        root.set_margin_top(12);
+        root.set_margin_end(12);
+        root.set_visible(false);
+
+        let entry = SearchEntry::new();
+        entry.set_placeholder_text(Some("Find"));
+        entry.set_width_chars(24);
+
+        let regex = ToggleButton::with_label(".*");
+        regex.set_tooltip_text(Some("Regular expression"));
+        let match_case = ToggleButton::with_label("Aa");
+        match_case.set_tooltip_text(Some("Match case"));
+
+        let status = Label::new(None);
+        status.add_css_class("control-label");
+        status.set_width_chars(9);
+
+        let older = Button::with_label("↑");
+        older.set_tooltip_text(Some("Older match (Enter)"));
+        let newer = Button::with_label("↓");
+        newer.set_tooltip_text(Some("Newer match"));
+        let close = Button::with_label("✕");
+        close.set_tooltip_text(Some("Close (Esc)"));
+
+        root.append(&entry);
+        root.append(&regex);
+        root.append(&match_case);
+        root.append(&status);
+        root.append(&older);
+        root.append(&newer);
+        root.append(&close);
+
+        let ticks = DrawingArea::new();
+        ticks.set_can_target(false);
+
+        let bar = Rc::new(Self {
+            root,
+            entry,
+            regex,
+            match_case,
+            status,
+            ticks,
+            view: view.clone(),
+        });
+
+        bar.ticks.set_draw_func({
+            let view = view.downgrade();
+            move |_, cr, width, height| {
+                let Some(view) = view.upgrade() else { return };
+                for (position, current) in view.search_ticks() {
+                    if current {
+                        cr.set_source_rgba(1.0, 0.5, 0.1, 1.0);
+                    } else {
+                        cr.set_source_rgba(1.0, 0.8, 0.2, 0.8);
+                    }
+                    cr.rectangle(0.0, (position * f64::from(height)).floor(), f64::from(width), 2.0);
+                    if let Err(err) = cr.fill() {
+                        eprintln!("❌ Failed to draw search ticks: {}", err);
+                        return;
+                    }
+                }
+            }
+        });
+
+        let weak = Rc::downgrade(&bar);
+        bar.entry.connect_search_changed({
+            let weak = weak.clone();
+            move |_| {
+                if let Some(bar) = weak.upgrade() {
+                    bar.search();
+                }
+            }
+        });
+        for toggle in [&bar.regex, &bar.match_case] {
+            toggle.connect_toggled({
+                let weak = weak.clone();
+                move |_| {
+                    if let Some(bar) = weak.upgrade() {
+                        bar.search();
+                    }
+                }
+            });
+        }
+        for (to_older, button) in [(true, &older), (false, &newer)] {
+            button.connect_clicked({
+                let weak = weak.clone();
+                move |_| {
+                    if let Some(bar) = weak.upgrade() {
+                        bar.step(to_older);
+                    }
+                }
+            });
+        }
+        bar.entry.connect_activate({
+            let weak = weak.clone();
+            move |_| {
+                if let Some(bar) = weak.upgrade() {
+                    bar.step(true);
+                }
+            }
+        });
+        bar.entry.connect_next_match({
+            let weak = weak.clone();
+            move |_| {
+                if let Some(bar) = weak.upgrade() {
+                    bar.step(true);
+                }
+            }
+        });
+        bar.entry.connect_previous_match({
+            let weak = weak.clone();
+            move |_| {
+                if let Some(bar) = weak.upgrade() {
+                    bar.step(false);
+                }
+            }
+        });
+        bar.entry.connect_stop_search({
+            let weak = weak.clone();
+            move |_| {
+                if let Some(bar) = weak.upgrade() {
+                    bar.hide();
+                }
+            }
+        });
+        close.connect_clicked({
+            let weak = weak.clone();
+            move |_| {
+                if let Some(bar) = weak.upgrade() {
+                    bar.hide();
+                }
+            }
+        });
+
+        // Ticks move as history grows; reflow drops the matches entirely.
+        view.adjustment().connect_changed({
+            let weak = weak.clone();
+            move |_| {
+                if let Some(bar) = weak.upgrade() {
+                    bar.ticks.queue_draw();
+                }
+            }
+        });
+        view.connect_grid_resized(move |_, _, _| {
+            if let Some(bar) = weak.upgrade()
+                && bar.root.is_visible()
+            {
+                bar.search();
+            }
+        });
+
+        bar
+    }
+
+    /// The bar itself, to be placed over the terminal.
+    pub fn widget(&self) -> &Box {
+        &self.root
+    }
+
+    /// The match markers, to be placed over the scrollbar.
+    pub fn ticks(&self) -> &DrawingArea {
+        &self.ticks
+    }
+
+    /// Shows the bar and focuses the pattern, searching again for it if one
+    /// is left from last time.
+    pub fn show(&self) {
+        self.root.set_visible(true);
+        self.entry.grab_focus();
+        if !self.entry.text().is_empty() {
+            self.search();
+        }
+    }
+
+    pub fn hide(&self) {
+        self.root.set_visible(false);
+        self.view.clear_search();
+        self.ticks.queue_draw();
+        self.view.grab_focus();
+    }
+
+    fn search(&self) {
+        let pattern = self.entry.text();
+        self.entry.remove_css_class("error");
+        if pattern.is_empty() {
+            self.view.clear_search();
+            self.status.set_text("");
+            self.ticks.queue_draw();
+            return;
+        }
+        match search::compile(&pattern, self.regex.is_active(), self.match_case.is_active()) {
+            Ok(regex) => {
+                let position = self.view.search(&regex);
+                self.show_position(position);
+            }
+            Err(_) => {
+                self.view.clear_search();
+                self.entry.add_css_class("error");
+                self.status.set_text("Invalid");
+                self.ticks.queue_draw();
+            }
+        }
+    }
+
+    fn step(&self, older: bool) {
+        if self.entry.text().is_empty() {
+            return;
+        }
+        let position = self.view.search_step(older);
+        self.show_position(position);
+    }
+
+    fn show_position(&self, position: Option<(usize, usize)>) {
+        match position {
+            Some((current, count)) => self.status.set_text(&format!("{} of {}", current + 1, count)),
+            None => self.status.set_text("No matches"),
+        }
+        self.ticks.queue_draw();
+    }
+}
@@ -0,0 +1,177 @@
+//! Automatic shell integration.
+//!
+//! The scripts under `shell-integration/` make bash, zsh and fish report
+//! their prompts (OSC 133) and working directory (OSC 7). They are built into
+//! the binary, written to the cache directory and loaded through whatever
+//! hook each shell offers before it reads the user's startup files, so no rc
+//! file needs editing:
+//!
+//! - bash started with `--posix` reads `$ENV` instead of its startup files;
+//!   the script leaves POSIX mode and reads them itself.
+//! - zsh reads `.zshenv` from `$ZDOTDIR` first; the script puts the user's
+//!   `ZDOTDIR` back before anything else.
+//! - fish loads `vendor_conf.d` from every directory in `$XDG_DATA_DIRS`.
+
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const SCRIPTS: [(&str, &str); 3] = [
+    ("bash/macos-term.bash", include_str!("../shell-integration/bash/macos-term.bash")),
+    ("zsh/.zshenv", include_str!("../shell-integration/zsh/.zshenv")),
+    ("fish/vendor_conf.d/macos-term.fish", include_str!("../shell-integration/fish/vendor_conf.d/macos-term.fish")),
+];
+
+/// Where fish looks for data when `XDG_DATA_DIRS` is unset.
+const DEFAULT_XDG_DATA_DIRS: &str = "/usr/local/share:/usr/share";
+
+/// Sets up `command`, which runs the shell called `shell_name`, to load its
+/// integration script. Returns `false` for shells without one.
+pub fn inject(command: &mut Command, shell_name: &str) -> io::Result<bool> {
+    if !matches!(shell_name, "bash" | "zsh" | "fish") {
+        return Ok(false);
+    }
+    let dir = install()?;
+    configure(command, shell_name, &dir, |name| env::var_os(name));
+    Ok(true)
+}
+
+/// Points the shell at the scripts installed in `dir`, keeping the user's
+/// values of the variables that hook replaces, as `user_var` looks them up,
+/// for the script to put back.
+fn configure(command: &mut Command, shell_name: &str, dir: &Path, user_var: impl Fn(&str) -> Option<OsString>) {
+    match shell_name {
+        "bash" => {
+            if let Some(user_env) = user_var("ENV") {
+                command.env("MACOS_TERM_BASH_ENV", user_env);
+            }
+            command
+                .arg("--posix")
+                .env("ENV", dir.join("bash/macos-term.bash"))
+                .env("MACOS_TERM_BASH_INJECT", "1");
+        }
+        "zsh" => {
+            if let Some(zdotdir) = user_var("ZDOTDIR") {
+                command.env("MACOS_TERM_ZSH_ZDOTDIR", zdotdir);
+            }
+            command.env("ZDOTDIR", dir.join("zsh")).env("MACOS_TERM_ZSH_INJECT", "1");
+        }
+        "fish" => {
+            let data_dirs = user_var("XDG_DATA_DIRS").filter(|dirs| !dirs.is_empty());
+            if let Some(data_dirs) = &data_dirs {
+                command.env("MACOS_TERM_FISH_XDG_DATA_DIRS", data_dirs);
+            }
+            let mut injected = OsString::from(dir.join("fish"));
+            injected.push(":");
+            injected.push(data_dirs.unwrap_or_else(|| DEFAULT_XDG_DATA_DIRS.into()));
+            command.env("XDG_DATA_DIRS", injected).env("MACOS_TERM_FISH_INJECT", "1");
+        }
+        _ => {}
+    }
+}
+
+/// Writes the scripts out, replacing copies left by an older build.
+fn install() -> io::Result<PathBuf> {
+    let dir = cache_dir()?.join("macos-term").join("shell-integration");
+    for (name, script) in SCRIPTS {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap_or(Path::new("/")))?;
+        if fs::read_to_string(&path).ok().as_deref() != Some(script) {
+            fs::write(&path, script)?;
+        }
+    }
+    Ok(dir)
+}
+
+fn cache_dir() -> io::Result<PathBuf> {
+    match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir)),
+        _ => env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".cache"))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "neither XDG_CACHE_HOME nor HOME is set")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The command for `shell` with its integration set up, as if the user's
+    /// environment held `vars`.
+    fn configured(shell: &str, vars: &[(&str, &str)]) -> Command {
+        let mut command = Command::new(shell);
+        let user_var = |name: &str| vars.iter().find(|(var, _)| *var == name).map(|(_, value)| value.into());
+        configure(&mut command, shell, Path::new("/cache/shell-integration"), user_var);
+        command
+    }
+
+    /// What `command` sets `name` to; `None` when it leaves it alone.
+    fn env(command: &Command, name: &str) -> Option<String> {
+        command
+            .get_envs()
+            .find(|(var, _)| *var == name)
+            .and_then(|(_, value)| value)
+            .map(|value| value.to_string_lossy().into_owned())
+    }
+
+    #[test]
+    fn bash_reads_the_script_in_posix_mode() {
+        let command = configured("bash", &[]);
+        assert_eq!(command.get_args().collect::<Vec<_>>(), ["--posix"]);
+        assert_eq!(env(&command, "ENV").as_deref(), Some("/cache/shell-integration/bash/macos-term.bash"));
+        assert_eq!(env(&command, "MACOS_TERM_BASH_INJECT").as_deref(), Some("1"));
+        assert_eq!(env(&command, "MACOS_TERM_BASH_ENV"), None);
+    }
+
+    #[test]
+    fn bash_keeps_the_users_env() {
+        let command = configured("bash", &[("ENV", "/home/user/.shrc")]);
+        assert_eq!(env(&command, "MACOS_TERM_BASH_ENV").as_deref(), Some("/home/user/.shrc"));
+        assert_eq!(env(&command, "ENV").as_deref(), Some("/cache/shell-integration/bash/macos-term.bash"));
+    }
+
+    #[test]
+    fn zsh_reads_the_scripts_zdotdir() {
+        let command = configured("zsh", &[]);
+        assert_eq!(command.get_args().count(), 0);
+        assert_eq!(env(&command, "ZDOTDIR").as_deref(), Some("/cache/shell-integration/zsh"));
+        assert_eq!(env(&command, "MACOS_TERM_ZSH_INJECT").as_deref(), Some("1"));
+        assert_eq!(env(&command, "MACOS_TERM_ZSH_ZDOTDIR"), None);
+
+        let command = configured("zsh", &[("ZDOTDIR", "/home/user/.zsh")]);
+        assert_eq!(env(&command, "MACOS_TERM_ZSH_ZDOTDIR").as_deref(), Some("/home/user/.zsh"));
+        assert_eq!(env(&command, "ZDOTDIR").as_deref(), Some("/cache/shell-integration/zsh"));
+    }
+
+    #[test]
+    fn fish_gets_the_script_directory_first_in_xdg_data_dirs() {
+        let command = configured("fish", &[("XDG_DATA_DIRS", "/opt/share:/usr/share")]);
+        let expected = "/cache/shell-integration/fish:/opt/share:/usr/share";
+        assert_eq!(env(&command, "XDG_DATA_DIRS").as_deref(), Some(expected));
+        assert_eq!(env(&command, "MACOS_TERM_FISH_XDG_DATA_DIRS").as_deref(), Some("/opt/share:/usr/share"));
+        assert_eq!(env(&command, "MACOS_TERM_FISH_INJECT").as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn fish_falls_back_to_the_default_data_dirs() {
+        for vars in [&[][..], &[("XDG_DATA_DIRS", "")]] {
+            let command = configured("fish", vars);
+            let expected = "/cache/shell-integration/fish:/usr/local/share:/usr/share";
+            assert_eq!(env(&command, "XDG_DATA_DIRS").as_deref(), Some(expected));
+            assert_eq!(env(&command, "MACOS_TERM_FISH_XDG_DATA_DIRS"), None);
+        }
+    }
+
+    #[test]
+    fn other_shells_are_left_alone() {
+        for shell in ["sh", "dash", "tcsh", "nu"] {
+            let mut command = Command::new(shell);
+            assert!(!inject(&mut command, shell).unwrap());
+            assert_eq!(command.get_args().count(), 0);
+            assert_eq!(command.get_envs().count(), 0);
+        }
+    }
+}
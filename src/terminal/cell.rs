@@ -0,0 +1,123 @@
+//! Grid cells and their rendition attributes.
+
+use super::color::Rgb;
+
+/// A color as selected by SGR, resolved against the palette at draw time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Color {
+    #[default]
+    Default,
+    /// An entry of the 256-color palette.
+    Indexed(u8),
+    /// A 24-bit truecolor value.
+    Rgb(Rgb),
+}
+
+/// Rendition flags set by SGR, plus the layout flags of wide characters.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Flags(u16);
+
+impl Flags {
+    pub const BOLD: Flags = Flags(1 << 0);
+    pub const DIM: Flags = Flags(1 << 1);
+    pub const ITALIC: Flags = Flags(1 << 2);
+    pub const UNDERLINE: Flags = Flags(1 << 3);
+    pub const BLINK: Flags = Flags(1 << 4);
+    pub const INVERSE: Flags = Flags(1 << 5);
+    pub const HIDDEN: Flags = Flags(1 << 6);
+    pub const STRIKETHROUGH: Flags = Flags(1 << 7);
+    /// The cell holds a double-width character that also covers the next cell.
+    pub const WIDE: Flags = Flags(1 << 8);
+    /// The right half of a double-width character.
+    pub const WIDE_SPACER: Flags = Flags(1 << 9);
+    /// Padding left in the last column when a wide character wrapped early.
+    pub const LEADING_SPACER: Flags = Flags(1 << 10);
+
+    pub fn contains(self, other: Flags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Flags) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Flags) {
+        self.0 &= !other.0;
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Attrs {
+    pub fg: Color,
+    pub bg: Color,
+    pub flags: Flags,
+    /// OSC 8 hyperlink the cell belongs to (see `Screen::hyperlink`); 0 for none.
+    pub link: u32,
+}
+
+/// One grid position holding a grapheme cluster: a base character plus any
+/// combining marks, variation selectors or ZWJ sequence members after it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+    /// Zero-width characters appended to `ch`; `None` for the common case so
+    /// plain cells stay allocation free.
+    pub extra: Option<Box<str>>,
+    pub attrs: Attrs,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self::new(' ', Attrs::default())
+    }
+}
+
+impl Cell {
+    pub fn new(ch: char, attrs: Attrs) -> Self {
+        Self { ch, extra: None, attrs }
+    }
+
+    /// An empty cell that keeps the background of `attrs` (background color erase).
+    pub fn blank(attrs: Attrs) -> Self {
+        Self::new(' ', Attrs { bg: attrs.bg, ..Attrs::default() })
+    }
+
+    /// Appends a character to the cell's grapheme cluster.
+    pub fn push_extra(&mut self, ch: char) {
+        let mut extra = self.extra.take().map(String::from).unwrap_or_default();
+        extra.push(ch);
+        self.extra = Some(extra.into_boxed_str());
+    }
+
+    /// The last character of the cluster.
+    pub fn last_char(&self) -> char {
+        self.extra.as_deref().and_then(|extra| extra.chars().next_back()).unwrap_or(self.ch)
+    }
+
+    /// Appends the whole cluster to `text`.
+    pub fn push_to(&self, text: &mut String) {
+        text.push(self.ch);
+        if let Some(extra) = &self.extra {
+            text.push_str(extra);
+        }
+    }
+
+    /// Whether the cell is the right half or padding of a wide character.
+    pub fn is_spacer(&self) -> bool {
+        self.attrs.flags.contains(Flags::WIDE_SPACER) || self.attrs.flags.contains(Flags::LEADING_SPACER)
+    }
+}
+
+/// One line of the grid.
+#[derive(Clone, Debug)]
+pub struct Row {
+    pub cells: Vec<Cell>,
+    /// Set when the line continues on the next row because of autowrap.
+    pub wrapped: bool,
+}
+
+impl Row {
+    pub fn new(cols: usize, blank: Cell) -> Self {
+        Self { cells: vec![blank; cols], wrapped: false }
+    }
+}
@@ -0,0 +1,99 @@
+//! OSC 52: applications setting and reading the clipboard.
+//!
+//! The screen only queues requests; whether they are honoured is up to the
+//! frontend's clipboard policy.
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClipboardKind {
+    Clipboard,
+    Primary,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClipboardRequest {
+    /// Replace the selection with `text`.
+    Store { kind: ClipboardKind, text: String },
+    /// Reply with the selection's contents, ending the reply with BEL if the
+    /// query did.
+    Load { kind: ClipboardKind, bell_terminated: bool },
+}
+
+/// Parses `OSC 52 ; selection ; data`. `data` is base64 text to store, `?`
+/// to query, or empty to clear the selection. Data that is not base64 of
+/// UTF-8 text is ignored rather than wiping the selection.
+pub fn parse(selection: &[u8], data: &[u8], bell_terminated: bool) -> Option<ClipboardRequest> {
+    // `c`, `s` and cut buffers all mean the clipboard here; an empty
+    // selection defaults to `s 0`.
+    let kind = if selection.contains(&b'p') { ClipboardKind::Primary } else { ClipboardKind::Clipboard };
+    if data == b"?" {
+        return Some(ClipboardRequest::Load { kind, bell_terminated });
+    }
+    let text = String::from_utf8(STANDARD.decode(data).ok()?).ok()?;
+    Some(ClipboardRequest::Store { kind, text })
+}
+
+/// The reply to a `Load` request.
+pub fn encode_reply(kind: ClipboardKind, text: &str, bell_terminated: bool) -> Vec<u8> {
+    let selection = match kind {
+        ClipboardKind::Clipboard => 'c',
+        ClipboardKind::Primary => 'p',
+    };
+    let end = if bell_terminated { "\x07" } else { "\x1b\\" };
+    format!("\x1b]52;{};{}{}", selection, STANDARD.encode(text), end).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::Terminal;
+
+    fn store(kind: ClipboardKind, text: &str) -> Option<ClipboardRequest> {
+        Some(ClipboardRequest::Store { kind, text: text.to_string() })
+    }
+
+    #[test]
+    fn parses_stores() {
+        assert_eq!(parse(b"c", b"aGVsbG8=", true), store(ClipboardKind::Clipboard, "hello"));
+        assert_eq!(parse(b"", b"aGVsbG8=", true), store(ClipboardKind::Clipboard, "hello"));
+        assert_eq!(parse(b"s0", b"aGVsbG8=", true), store(ClipboardKind::Clipboard, "hello"));
+        assert_eq!(parse(b"p", b"aGVsbG8=", true), store(ClipboardKind::Primary, "hello"));
+        assert_eq!(parse(b"c", b"", true), store(ClipboardKind::Clipboard, ""));
+    }
+
+    #[test]
+    fn ignores_malformed_data() {
+        assert_eq!(parse(b"c", b"not base64!", true), None);
+        assert_eq!(parse(b"c", b"aGVsbG8", true), None);
+        // Valid base64 of invalid UTF-8.
+        assert_eq!(parse(b"c", b"/w==", true), None);
+    }
+
+    #[test]
+    fn parses_queries() {
+        let request = parse(b"p", b"?", false);
+        assert_eq!(request, Some(ClipboardRequest::Load { kind: ClipboardKind::Primary, bell_terminated: false }));
+    }
+
+    #[test]
+    fn encodes_replies() {
+        assert_eq!(encode_reply(ClipboardKind::Clipboard, "hello", true), b"\x1b]52;c;aGVsbG8=\x07");
+        assert_eq!(encode_reply(ClipboardKind::Primary, "", false), b"\x1b]52;p;\x1b\\");
+    }
+
+    #[test]
+    fn queues_requests_from_the_terminal() {
+        let mut terminal = Terminal::new(10, 2);
+        terminal.feed(b"\x1b]52;c;aGk=\x07\x1b]52;c;!!\x07\x1b]52;c;?\x1b\\");
+        let requests = terminal.screen_mut().take_clipboard_requests();
+        assert_eq!(
+            requests,
+            [
+                ClipboardRequest::Store { kind: ClipboardKind::Clipboard, text: "hi".to_string() },
+                ClipboardRequest::Load { kind: ClipboardKind::Clipboard, bell_terminated: false },
+            ]
+        );
+    }
+}
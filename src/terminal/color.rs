@@ -0,0 +1,241 @@
+//! RGB colors and the 256-entry palette SGR colors resolve against.
+
+use super::cell::Color;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// Parses `#rrggbb`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let hex = text.trim().strip_prefix('#')?;
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |range: std::ops::Range<usize>| u8::from_str_radix(&hex[range], 16).ok();
+        Some(Self::new(channel(0..2)?, channel(2..4)?, channel(4..6)?))
+    }
+
+    /// Parses the X11 color specs OSC color sequences use: `rgb:r/g/b` with
+    /// one to four hex digits per channel, scaled to the full range, and
+    /// `#rgb` through `#rrrrggggbbbb`, which keep the most significant bits.
+    pub fn parse_x11(spec: &str) -> Option<Self> {
+        let hex_channel = |digits: &str| {
+            if digits.is_empty() || digits.len() > 4 || !digits.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                return None;
+            }
+            u32::from_str_radix(digits, 16).ok()
+        };
+        if let Some(channels) = spec.strip_prefix("rgb:") {
+            let mut scaled = channels.split('/').map(|digits| {
+                hex_channel(digits).map(|value| {
+                    let max = (1u32 << (4 * digits.len())) - 1;
+                    ((value * 255 + max / 2) / max) as u8
+                })
+            });
+            let color = Self::new(scaled.next()??, scaled.next()??, scaled.next()??);
+            return scaled.next().is_none().then_some(color);
+        }
+        let hex = spec.strip_prefix('#')?;
+        if hex.is_empty() || hex.len() % 3 != 0 || hex.len() > 12 || !hex.is_ascii() {
+            return None;
+        }
+        let width = hex.len() / 3;
+        let channel = |index: usize| {
+            let value = hex_channel(&hex[index * width..(index + 1) * width])?;
+            Some(if width == 1 { value << 4 } else { value >> (4 * (width - 2)) } as u8)
+        };
+        Some(Self::new(channel(0)?, channel(1)?, channel(2)?))
+    }
+
+    /// Formats the color the way xterm answers color queries.
+    pub fn to_x11(self) -> String {
+        format!("rgb:{0:02x}{0:02x}/{1:02x}{1:02x}/{2:02x}{2:02x}", self.r, self.g, self.b)
+    }
+}
+
+/// A color OSC sequences can query and change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSlot {
+    Indexed(u8),
+    Foreground,
+    Background,
+    Cursor,
+}
+
+const ANSI_COLORS: [Rgb; 16] = [
+    Rgb::new(0, 0, 0),
+    Rgb::new(205, 49, 49),
+    Rgb::new(13, 188, 121),
+    Rgb::new(229, 229, 16),
+    Rgb::new(36, 114, 200),
+    Rgb::new(188, 63, 188),
+    Rgb::new(17, 168, 205),
+    Rgb::new(229, 229, 229),
+    Rgb::new(102, 102, 102),
+    Rgb::new(241, 76, 76),
+    Rgb::new(35, 209, 139),
+    Rgb::new(245, 245, 67),
+    Rgb::new(59, 142, 234),
+    Rgb::new(214, 112, 214),
+    Rgb::new(41, 184, 219),
+    Rgb::new(255, 255, 255),
+];
+
+/// The 256 indexed colors plus the default foreground, background and cursor.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    colors: [Rgb; 256],
+    pub foreground: Rgb,
+    pub background: Rgb,
+    pub cursor: Rgb,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        let mut colors = [Rgb::default(); 256];
+        colors[..16].copy_from_slice(&ANSI_COLORS);
+
+        // 6x6x6 color cube
+        let level = |value: usize| if value == 0 { 0 } else { (55 + value * 40) as u8 };
+        for index in 0..216 {
+            colors[16 + index] = Rgb::new(level(index / 36), level(index / 6 % 6), level(index % 6));
+        }
+
+        // 24-step grayscale ramp
+        for index in 0..24 {
+            let gray = (8 + index * 10) as u8;
+            colors[232 + index] = Rgb::new(gray, gray, gray);
+        }
+
+        Self {
+            colors,
+            foreground: Rgb::new(255, 255, 255),
+            background: Rgb::new(0, 0, 0),
+            cursor: Rgb::new(255, 255, 255),
+        }
+    }
+}
+
+impl Palette {
+    pub fn get(&self, index: u8) -> Rgb {
+        self.colors[usize::from(index)]
+    }
+
+    pub fn set(&mut self, index: u8, color: Rgb) {
+        self.colors[usize::from(index)] = color;
+    }
+
+    pub fn color(&self, slot: ColorSlot) -> Rgb {
+        match slot {
+            ColorSlot::Indexed(index) => self.get(index),
+            ColorSlot::Foreground => self.foreground,
+            ColorSlot::Background => self.background,
+            ColorSlot::Cursor => self.cursor,
+        }
+    }
+
+    pub fn set_color(&mut self, slot: ColorSlot, color: Rgb) {
+        match slot {
+            ColorSlot::Indexed(index) => self.set(index, color),
+            ColorSlot::Foreground => self.foreground = color,
+            ColorSlot::Background => self.background = color,
+            ColorSlot::Cursor => self.cursor = color,
+        }
+    }
+
+    /// Resolves an SGR color; `None` means the default for the layer.
+    pub fn resolve(&self, color: Color) -> Option<Rgb> {
+        match color {
+            Color::Default => None,
+            Color::Indexed(index) => Some(self.get(index)),
+            Color::Rgb(rgb) => Some(rgb),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::Terminal;
+
+    #[test]
+    fn parses_hex_colors() {
+        assert_eq!(Rgb::parse("#1a2B3c"), Some(Rgb::new(0x1a, 0x2b, 0x3c)));
+        assert_eq!(Rgb::parse(" #ffffff "), Some(Rgb::new(255, 255, 255)));
+        assert_eq!(Rgb::parse("1a2b3c"), None);
+        assert_eq!(Rgb::parse("#fff"), None);
+        assert_eq!(Rgb::parse("#gggggg"), None);
+        assert_eq!(Rgb::parse("#ééé"), None);
+    }
+
+    #[test]
+    fn scales_rgb_channels_of_any_width() {
+        assert_eq!(Rgb::parse_x11("rgb:f/8/0"), Some(Rgb::new(255, 136, 0)));
+        assert_eq!(Rgb::parse_x11("rgb:ff/80/00"), Some(Rgb::new(255, 128, 0)));
+        assert_eq!(Rgb::parse_x11("rgb:fff/800/000"), Some(Rgb::new(255, 128, 0)));
+        assert_eq!(Rgb::parse_x11("rgb:ffff/8000/0000"), Some(Rgb::new(255, 128, 0)));
+        assert_eq!(Rgb::parse_x11("rgb:F/aB/c00"), Some(Rgb::new(255, 171, 191)));
+    }
+
+    #[test]
+    fn rejects_malformed_rgb_specs() {
+        assert_eq!(Rgb::parse_x11("rgb:00000/0/0"), None);
+        assert_eq!(Rgb::parse_x11("rgb:00000000/0/0"), None);
+        assert_eq!(Rgb::parse_x11("rgb:0/0/00000000000000000"), None);
+        assert_eq!(Rgb::parse_x11("rgb://0"), None);
+        assert_eq!(Rgb::parse_x11("rgb:0/0"), None);
+        assert_eq!(Rgb::parse_x11("rgb:0/0/0/0"), None);
+        assert_eq!(Rgb::parse_x11("rgb:0/0/g"), None);
+        assert_eq!(Rgb::parse_x11("rgb:+f/0/0"), None);
+        assert_eq!(Rgb::parse_x11("red"), None);
+    }
+
+    #[test]
+    fn keeps_the_high_bits_of_hash_specs() {
+        assert_eq!(Rgb::parse_x11("#f80"), Some(Rgb::new(0xf0, 0x80, 0x00)));
+        assert_eq!(Rgb::parse_x11("#ff8800"), Some(Rgb::new(0xff, 0x88, 0x00)));
+        assert_eq!(Rgb::parse_x11("#fff888000"), Some(Rgb::new(0xff, 0x88, 0x00)));
+        assert_eq!(Rgb::parse_x11("#ffff88880000"), Some(Rgb::new(0xff, 0x88, 0x00)));
+        assert_eq!(Rgb::parse_x11("#"), None);
+        assert_eq!(Rgb::parse_x11("#ffff"), None);
+        assert_eq!(Rgb::parse_x11("#fffff8888800000"), None);
+        assert_eq!(Rgb::parse_x11("#ééé"), None);
+    }
+
+    #[test]
+    fn formats_queries_like_xterm() {
+        assert_eq!(Rgb::new(0x12, 0xab, 0).to_x11(), "rgb:1212/abab/0000");
+        assert_eq!(Rgb::parse_x11(&Rgb::new(1, 2, 3).to_x11()), Some(Rgb::new(1, 2, 3)));
+    }
+
+    #[test]
+    fn default_palette() {
+        let palette = Palette::default();
+        assert_eq!(palette.get(1), Rgb::new(205, 49, 49));
+        assert_eq!(palette.get(16), Rgb::new(0, 0, 0));
+        assert_eq!(palette.get(196), Rgb::new(255, 0, 0));
+        assert_eq!(palette.get(231), Rgb::new(255, 255, 255));
+        assert_eq!(palette.get(232), Rgb::new(8, 8, 8));
+        assert_eq!(palette.get(255), Rgb::new(238, 238, 238));
+        assert_eq!(palette.resolve(Color::Default), None);
+        assert_eq!(palette.resolve(Color::Indexed(196)), Some(Rgb::new(255, 0, 0)));
+    }
+
+    #[test]
+    fn overlong_osc_color_channels_are_ignored() {
+        let mut terminal = Terminal::new(10, 2);
+        terminal.feed(b"\x1b]11;rgb:00000000/0/0\x07\x1b]11;rgb:12/34/56\x07");
+        assert_eq!(terminal.screen().palette.background, Rgb::new(0x12, 0x34, 0x56));
+        terminal.feed(b"\x1b]11;rgb:00000000/0/0\x07");
+        assert_eq!(terminal.screen().palette.background, Rgb::new(0x12, 0x34, 0x56));
+    }
+}
@@ -0,0 +1,102 @@
+//! OSC 7: the shell reporting its working directory as a `file://` URI.
+
+use std::ffi::{CStr, OsString};
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+
+/// The local path an OSC 7 URI names. URIs for another host, as a shell
+/// reports them over ssh, give `None`.
+pub fn parse(uri: &[u8]) -> Option<PathBuf> {
+    let rest = uri.strip_prefix(b"file://")?;
+    let slash = rest.iter().position(|&byte| byte == b'/')?;
+    let (host, path) = rest.split_at(slash);
+    if !is_local_host(host) {
+        return None;
+    }
+    Some(PathBuf::from(OsString::from_vec(percent_decode(path))))
+}
+
+/// Whether `host` is this machine. Shells report either the full or the
+/// short host name.
+fn is_local_host(host: &[u8]) -> bool {
+    if host.is_empty() || host.eq_ignore_ascii_case(b"localhost") {
+        return true;
+    }
+    let mut name = [0u8; 256];
+    if unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) } < 0 {
+        return false;
+    }
+    let Ok(name) = CStr::from_bytes_until_nul(&name) else {
+        return false;
+    };
+    let name = name.to_bytes();
+    let short = name.split(|&byte| byte == b'.').next().unwrap_or(name);
+    host.eq_ignore_ascii_case(name) || host.eq_ignore_ascii_case(short)
+}
+
+/// Decodes `%XX` escapes; malformed ones are kept as they are.
+fn percent_decode(bytes: &[u8]) -> Vec<u8> {
+    let hex = |byte: u8| (byte as char).to_digit(16).map(|digit| digit as u8);
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(high) = bytes.get(i + 1).copied().and_then(hex)
+            && let Some(low) = bytes.get(i + 2).copied().and_then(hex)
+        {
+            decoded.push(high << 4 | low);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::Terminal;
+
+    fn hostname() -> String {
+        let mut name = [0u8; 256];
+        assert!(unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) } >= 0);
+        CStr::from_bytes_until_nul(&name).unwrap().to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn parses_local_uris() {
+        assert_eq!(parse(b"file:///home/me"), Some(PathBuf::from("/home/me")));
+        assert_eq!(parse(b"file://localhost/tmp"), Some(PathBuf::from("/tmp")));
+        assert_eq!(parse(b"file://LOCALHOST/tmp"), Some(PathBuf::from("/tmp")));
+        let uri = format!("file://{}/srv", hostname());
+        assert_eq!(parse(uri.as_bytes()), Some(PathBuf::from("/srv")));
+    }
+
+    #[test]
+    fn rejects_other_hosts_and_schemes() {
+        assert_eq!(parse(b"file://elsewhere.invalid/home/me"), None);
+        assert_eq!(parse(b"http://localhost/home"), None);
+        assert_eq!(parse(b"file://localhost"), None);
+        assert_eq!(parse(b"/home/me"), None);
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(parse(b"file:///My%20Files/%e4%b8%ad"), Some(PathBuf::from("/My Files/中")));
+        assert_eq!(percent_decode(b"100%"), b"100%");
+        assert_eq!(percent_decode(b"%zz%4"), b"%zz%4");
+        // Paths need not be UTF-8.
+        assert_eq!(percent_decode(b"/a%ff"), b"/a\xff");
+    }
+
+    #[test]
+    fn osc7_sets_the_working_directory() {
+        let mut terminal = Terminal::new(10, 2);
+        terminal.feed(b"\x1b]7;file:///tmp/a%3Bb;c\x07");
+        assert_eq!(terminal.screen().working_directory(), Some(PathBuf::from("/tmp/a;b;c").as_path()));
+        terminal.feed(b"\x1b]7;file://elsewhere.invalid/tmp\x1b\\");
+        assert_eq!(terminal.screen().working_directory(), None);
+    }
+}
@@ -0,0 +1,450 @@
+//! Images shown in the grid.
+//!
+//! Images are stored once in `Images` and placed at absolute lines, like
+//! selections and command marks, so they scroll with the text and into the
+//! scrollback. Pixels are kept as straight RGBA, or as the image file for
+//! the frontend to decode; pixels an image never sets stay transparent, so
+//! the glass shows through them the same way it does through cells with the
+//! default background.
+
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+/// Pixel memory kept for images until the configuration says otherwise.
+pub const DEFAULT_IMAGE_MEMORY_LIMIT: usize = 320 * 1024 * 1024;
+
+pub enum ImageData {
+    /// Straight (not premultiplied) RGBA, row by row.
+    Rgba(Vec<u8>),
+    /// A PNG, JPEG, GIF, BMP or WebP file; the size comes from its header.
+    Encoded(Vec<u8>),
+}
+
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub data: ImageData,
+    /// Whether the image outlives its placements, as kitty images do until
+    /// they are deleted. Others go with their last placement.
+    pub persistent: bool,
+}
+
+impl Image {
+    pub fn rgba(width: u32, height: u32, pixels: Vec<u8>) -> Self {
+        Self { width, height, data: ImageData::Rgba(pixels), persistent: false }
+    }
+
+    /// An image file, or `None` if `data` starts like none of the formats
+    /// `ImageData::Encoded` takes.
+    pub fn encoded(data: Vec<u8>) -> Option<Self> {
+        let (width, height) = encoded_size(&data)?;
+        if width == 0 || height == 0 {
+            return None;
+        }
+        Some(Self { width, height, data: ImageData::Encoded(data), persistent: false })
+    }
+
+    /// Bytes the decoded pixels take, which is what the memory limit counts.
+    pub fn memory(&self) -> usize {
+        self.width as usize * self.height as usize * 4
+    }
+}
+
+/// Where an image is drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Placement {
+    pub image: u32,
+    /// Kitty placement id; 0 for none.
+    pub id: u32,
+    /// Absolute line and column of the top-left cell.
+    pub line: usize,
+    pub col: usize,
+    /// Offset from the top-left corner of that cell, in pixels.
+    pub offset: (u32, u32),
+    /// Part of the image shown, in image pixels: x, y, width and height.
+    pub source: (u32, u32, u32, u32),
+    /// Size drawn, in pixels.
+    pub width: u32,
+    pub height: u32,
+    /// Cells the image covers, for deleting by position and telling when it
+    /// has left the history.
+    pub rows: usize,
+    pub cols: usize,
+    /// Stacking order; negative values go under the text.
+    pub z: i32,
+    /// Whether it was placed on the alternate screen.
+    pub alternate: bool,
+}
+
+impl Placement {
+    pub fn last_line(&self) -> usize {
+        self.line + self.rows.max(1) - 1
+    }
+
+    /// Whether the placement covers the cell at `line` and `col`.
+    pub fn covers(&self, line: usize, col: usize) -> bool {
+        (self.line..=self.last_line()).contains(&line) && (self.col..self.col + self.cols.max(1)).contains(&col)
+    }
+}
+
+/// A kitty placement made with `U=1`: it is not drawn by itself but through
+/// Unicode placeholder cells, which may sit anywhere in the grid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VirtualPlacement {
+    pub image: u32,
+    /// Kitty placement id; 0 for none.
+    pub id: u32,
+    /// Cells the image is fitted into.
+    pub cols: usize,
+    pub rows: usize,
+}
+
+pub struct Images {
+    /// Keyed by id, which only grows, so the first image is the oldest.
+    images: BTreeMap<u32, Image>,
+    placements: Vec<Placement>,
+    virtual_placements: Vec<VirtualPlacement>,
+    next_id: u32,
+    /// Bytes of pixel data held.
+    memory: usize,
+    limit: usize,
+}
+
+impl Default for Images {
+    fn default() -> Self {
+        Self {
+            images: BTreeMap::new(),
+            placements: Vec::new(),
+            virtual_placements: Vec::new(),
+            next_id: 0,
+            memory: 0,
+            limit: DEFAULT_IMAGE_MEMORY_LIMIT,
+        }
+    }
+}
+
+impl Images {
+    pub fn image(&self, id: u32) -> Option<&Image> {
+        self.images.get(&id)
+    }
+
+    pub fn placements(&self) -> &[Placement] {
+        &self.placements
+    }
+
+    pub fn virtual_placements(&self) -> &[VirtualPlacement] {
+        &self.virtual_placements
+    }
+
+    /// Most bytes of pixels kept; decoders stop at this size too.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        self.evict(0);
+    }
+
+    /// Stores an image, dropping the oldest ones (and their placements) to
+    /// make room. Returns `None` if it alone is over the limit.
+    pub fn insert(&mut self, image: Image) -> Option<u32> {
+        let size = image.memory();
+        if size > self.limit {
+            return None;
+        }
+        self.evict(size);
+        self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+        self.memory += size;
+        self.images.insert(self.next_id, image);
+        Some(self.next_id)
+    }
+
+    /// Drops an image together with its placements.
+    pub fn remove(&mut self, id: u32) {
+        if let Some(image) = self.images.remove(&id) {
+            self.memory -= image.memory();
+        }
+        self.placements.retain(|placement| placement.image != id);
+        self.virtual_placements.retain(|placement| placement.image != id);
+    }
+
+    /// Drops every image, persistent or not.
+    pub fn remove_all(&mut self) {
+        self.images.clear();
+        self.placements.clear();
+        self.virtual_placements.clear();
+        self.memory = 0;
+    }
+
+    /// Adds a placement, replacing the image's placement with the same id.
+    pub fn place(&mut self, placement: Placement) {
+        if placement.id != 0 {
+            self.placements.retain(|old| old.image != placement.image || old.id != placement.id);
+        }
+        self.placements.push(placement);
+    }
+
+    pub fn place_virtual(&mut self, placement: VirtualPlacement) {
+        if placement.id != 0 {
+            self.virtual_placements.retain(|old| old.image != placement.image || old.id != placement.id);
+        }
+        self.virtual_placements.push(placement);
+    }
+
+    /// Whether anything still shows the image.
+    pub fn is_placed(&self, id: u32) -> bool {
+        self.placements.iter().any(|placement| placement.image == id)
+            || self.virtual_placements.iter().any(|placement| placement.image == id)
+    }
+
+    /// Keeps the placements `keep` accepts and forgets images no longer
+    /// placed anywhere, unless they are persistent.
+    pub fn retain(&mut self, keep: impl FnMut(&Placement) -> bool) {
+        self.placements.retain(keep);
+        let unplaced: Vec<u32> = self
+            .images
+            .iter()
+            .filter(|(id, image)| !image.persistent && !self.is_placed(**id))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in unplaced {
+            self.remove(id);
+        }
+    }
+
+    pub fn retain_virtual(&mut self, keep: impl FnMut(&VirtualPlacement) -> bool) {
+        self.virtual_placements.retain(keep);
+        self.retain(|_| true);
+    }
+
+    /// Renumbers the lines placements sit on; those `map` drops are removed.
+    pub fn remap(&mut self, alternate: bool, map: impl Fn(usize) -> Option<usize>) {
+        let mut placements = std::mem::take(&mut self.placements);
+        placements.retain_mut(|placement| {
+            if placement.alternate != alternate {
+                return true;
+            }
+            match map(placement.line) {
+                Some(line) => {
+                    placement.line = line;
+                    true
+                }
+                None => false,
+            }
+        });
+        self.placements = placements;
+        self.retain(|_| true);
+    }
+
+    /// Moves placements starting within `region` by `delta` lines, as the
+    /// text scrolls inside a scroll region; those pushed out of it go.
+    pub fn scroll(&mut self, alternate: bool, region: RangeInclusive<usize>, delta: isize) {
+        self.remap(alternate, |line| {
+            if !region.contains(&line) {
+                return Some(line);
+            }
+            line.checked_add_signed(delta).filter(|line| region.contains(line))
+        });
+    }
+
+    /// Removes placements of one screen starting within `lines`.
+    pub fn clear(&mut self, alternate: bool, lines: RangeInclusive<usize>) {
+        self.retain(|placement| placement.alternate != alternate || !lines.contains(&placement.line));
+    }
+
+    fn evict(&mut self, incoming: usize) {
+        while self.memory + incoming > self.limit {
+            let Some(&id) = self.images.keys().next() else { break };
+            self.remove(id);
+        }
+    }
+}
+
+/// Width and height from an image file's header.
+fn encoded_size(data: &[u8]) -> Option<(u32, u32)> {
+    let u16_le = |at: usize| Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?));
+    let u16_be = |at: usize| Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?));
+    let u32_be = |at: usize| Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?));
+    let u24_le = |at: usize| Some(u32::from_le_bytes([*data.get(at)?, *data.get(at + 1)?, *data.get(at + 2)?, 0]));
+    let i32_le = |at: usize| Some(i32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?));
+
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        // IHDR has to be the first chunk.
+        (data.get(12..16)? == b"IHDR").then_some(())?;
+        Some((u32_be(16)?, u32_be(20)?))
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some((u32::from(u16_le(6)?), u32::from(u16_le(8)?)))
+    } else if data.starts_with(b"BM") {
+        // Negative heights mean the rows are stored top down.
+        Some((i32_le(18)?.unsigned_abs(), i32_le(22)?.unsigned_abs()))
+    } else if data.starts_with(b"RIFF") && data.get(8..12)? == b"WEBP" {
+        match data.get(12..16)? {
+            b"VP8 " => Some((u32::from(u16_le(26)? & 0x3fff), u32::from(u16_le(28)? & 0x3fff))),
+            b"VP8L" => {
+                let bits = u32::from_le_bytes(data.get(21..25)?.try_into().ok()?);
+                Some(((bits & 0x3fff) + 1, (bits >> 14 & 0x3fff) + 1))
+            }
+            b"VP8X" => Some((u24_le(24)? + 1, u24_le(27)? + 1)),
+            _ => None,
+        }
+    } else if data.starts_with(&[0xff, 0xd8]) {
+        // Walk the JPEG segments up to the start of frame.
+        let mut at = 2;
+        loop {
+            while *data.get(at)? != 0xff {
+                at += 1;
+            }
+            let marker = *data.get(at + 1)?;
+            match marker {
+                0xff => at += 1,
+                0xd0..=0xd9 | 0x01 => at += 2,
+                0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                    return Some((u32::from(u16_be(at + 7)?), u32::from(u16_be(at + 5)?)));
+                }
+                _ => at += 2 + usize::from(u16_be(at + 2)?),
+            }
+        }
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32) -> Image {
+        Image::rgba(width, height, vec![0; (width * height * 4) as usize])
+    }
+
+    fn placement(image: u32, id: u32, line: usize) -> Placement {
+        Placement {
+            image,
+            id,
+            line,
+            col: 2,
+            offset: (0, 0),
+            source: (0, 0, 8, 8),
+            width: 8,
+            height: 8,
+            rows: 2,
+            cols: 3,
+            z: 0,
+            alternate: false,
+        }
+    }
+
+    #[test]
+    fn placements_cover_their_cells() {
+        let placement = placement(1, 0, 4);
+        assert_eq!(placement.last_line(), 5);
+        assert!(placement.covers(4, 2));
+        assert!(placement.covers(5, 4));
+        assert!(!placement.covers(6, 2));
+        assert!(!placement.covers(4, 5));
+        assert!(!placement.covers(4, 1));
+    }
+
+    #[test]
+    fn evicts_the_oldest_images() {
+        let mut images = Images::default();
+        images.set_limit(2 * 16 * 4);
+        let first = images.insert(image(4, 4)).unwrap();
+        let second = images.insert(image(4, 4)).unwrap();
+        let third = images.insert(image(4, 4)).unwrap();
+        assert!(images.image(first).is_none());
+        assert!(images.image(second).is_some() && images.image(third).is_some());
+        assert_eq!(images.insert(image(8, 8)), None);
+
+        images.set_limit(16 * 4);
+        assert!(images.image(second).is_none());
+        assert!(images.image(third).is_some());
+    }
+
+    #[test]
+    fn placements_with_an_id_replace_each_other() {
+        let mut images = Images::default();
+        let id = images.insert(image(1, 1)).unwrap();
+        images.place(placement(id, 7, 0));
+        images.place(placement(id, 7, 3));
+        images.place(placement(id, 0, 1));
+        images.place(placement(id, 0, 2));
+        let lines: Vec<usize> = images.placements().iter().map(|placement| placement.line).collect();
+        assert_eq!(lines, [3, 1, 2]);
+    }
+
+    #[test]
+    fn unplaced_images_go_unless_persistent() {
+        let mut images = Images::default();
+        let transient = images.insert(image(1, 1)).unwrap();
+        let mut kept = image(1, 1);
+        kept.persistent = true;
+        let persistent = images.insert(kept).unwrap();
+        images.place(placement(transient, 0, 0));
+        images.place(placement(persistent, 0, 0));
+        images.retain(|_| false);
+        assert!(images.image(transient).is_none());
+        assert!(images.image(persistent).is_some());
+        images.remove(persistent);
+        assert!(images.image(persistent).is_none());
+    }
+
+    #[test]
+    fn scrolls_and_clears_placements() {
+        let mut images = Images::default();
+        let id = images.insert(image(1, 1)).unwrap();
+        for line in [0, 5, 9] {
+            images.place(placement(id, 0, line));
+        }
+        let mut alternate = placement(id, 0, 5);
+        alternate.alternate = true;
+        images.place(alternate);
+
+        images.scroll(false, 5..=9, -3);
+        let lines: Vec<(usize, bool)> =
+            images.placements().iter().map(|placement| (placement.line, placement.alternate)).collect();
+        assert_eq!(lines, [(0, false), (6, false), (5, true)]);
+
+        images.clear(true, 0..=9);
+        assert_eq!(images.placements().len(), 2);
+        images.remap(false, |line| line.checked_sub(1));
+        assert_eq!(images.placements()[0].line, 5);
+        images.clear(false, 0..=9);
+        assert!(images.placements().is_empty());
+        assert!(images.image(id).is_none());
+    }
+
+    #[test]
+    fn reads_sizes_from_image_headers() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&[0, 0, 1, 0, 0, 0, 0, 32]);
+        assert_eq!(encoded_size(&png), Some((256, 32)));
+
+        assert_eq!(encoded_size(b"GIF89a\x0a\x00\x14\x00"), Some((10, 20)));
+
+        let mut bmp = vec![0; 26];
+        bmp[..2].copy_from_slice(b"BM");
+        bmp[18..22].copy_from_slice(&640i32.to_le_bytes());
+        bmp[22..26].copy_from_slice(&(-480i32).to_le_bytes());
+        assert_eq!(encoded_size(&bmp), Some((640, 480)));
+
+        let jpeg = [0xff, 0xd8, 0xff, 0xe0, 0, 4, 0, 0, 0xff, 0xc0, 0, 17, 8, 0, 50, 0, 100];
+        assert_eq!(encoded_size(&jpeg), Some((100, 50)));
+
+        let mut webp = b"RIFF\0\0\0\0WEBPVP8X".to_vec();
+        webp.extend_from_slice(&[0; 8]);
+        webp.extend_from_slice(&[99, 0, 0, 49, 0, 0]);
+        assert_eq!(encoded_size(&webp), Some((100, 50)));
+    }
+
+    #[test]
+    fn rejects_unknown_or_truncated_files() {
+        assert_eq!(encoded_size(b"hello"), None);
+        assert_eq!(encoded_size(b"\x89PNG\r\n\x1a\n"), None);
+        assert_eq!(encoded_size(&[0xff, 0xd8, 0xff, 0xe0, 0, 4]), None);
+        assert!(Image::encoded(b"GIF89a\x00\x00\x14\x00".to_vec()).is_none());
+        let gif = Image::encoded(b"GIF89a\x02\x00\x03\x00".to_vec()).unwrap();
+        assert_eq!((gif.width, gif.height, gif.memory()), (2, 3, 24));
+    }
+}
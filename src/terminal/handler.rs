@@ -0,0 +1,563 @@
+//! Maps parsed control functions onto screen operations.
+
+use super::cell::{Attrs, Color, Flags};
+use super::clipboard;
+use super::cwd;
+use super::color::{ColorSlot, Rgb};
+use super::mouse::{MouseEncoding, MouseMode};
+use super::parser::{Params, Perform};
+use super::screen::{Charset, CursorShape, Screen};
+
+/// DA1: a VT220 with sixel graphics and ANSI color.
+const PRIMARY_DEVICE_ATTRIBUTES: &[u8] = b"\x1b[?62;4;22c";
+
+/// DA3: the unit id, which this terminal has none of.
+const TERTIARY_DEVICE_ATTRIBUTES: &[u8] = b"\x1bP!|00000000\x1b\\";
+
+/// DECRQM answers.
+const MODE_UNKNOWN: u8 = 0;
+const MODE_SET: u8 = 1;
+const MODE_RESET: u8 = 2;
+
+impl Perform for Screen {
+    fn print(&mut self, ch: char) {
+        Screen::print(self, ch);
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            0x08 => self.backspace(),
+            0x09 => self.tab(1),
+            0x0a..=0x0c => self.linefeed(),
+            0x0d => self.carriage_return(),
+            0x0e => self.shift_charset(1),
+            0x0f => self.shift_charset(0),
+            0x84 => self.index(),
+            0x85 => self.next_line(),
+            0x88 => self.set_tab_stop(),
+            0x8d => self.reverse_index(),
+            _ => {}
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], ignore: bool, byte: u8) {
+        if ignore {
+            return;
+        }
+        match (intermediates, byte) {
+            ([], b'7') => self.save_cursor(),
+            ([], b'8') => self.restore_cursor(),
+            ([], b'D') => self.index(),
+            ([], b'E') => self.next_line(),
+            ([], b'H') => self.set_tab_stop(),
+            ([], b'M') => self.reverse_index(),
+            ([], b'c') => self.reset(),
+            ([], b'=') => self.modes.application_keypad = true,
+            ([], b'>') => self.modes.application_keypad = false,
+            ([b'#'], b'8') => self.alignment_test(),
+            ([slot @ (b'(' | b')')], designator) => {
+                let charset = match designator {
+                    b'0' => Charset::DecSpecialGraphics,
+                    _ => Charset::Ascii,
+                };
+                self.designate_charset(usize::from(*slot == b')'), charset);
+            }
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        if ignore {
+            return;
+        }
+        let count = |index: usize| usize::from(params.get(index, 1));
+
+        match (intermediates, action) {
+            ([], '@') => self.insert_chars(count(0)),
+            ([], 'A') => self.move_up(count(0)),
+            ([], 'B' | 'e') => self.move_down(count(0)),
+            ([], 'C' | 'a') => self.move_forward(count(0)),
+            ([], 'D') => self.move_backward(count(0)),
+            ([], 'E') => {
+                self.move_down(count(0));
+                self.carriage_return();
+            }
+            ([], 'F') => {
+                self.move_up(count(0));
+                self.carriage_return();
+            }
+            ([], 'G' | '`') => self.goto_col(count(0) - 1),
+            ([], 'H' | 'f') => self.goto(count(0) - 1, count(1) - 1),
+            ([], 'I') => self.tab(count(0)),
+            ([], 'J') => self.erase_in_display(params.get(0, 0)),
+            ([], 'K') => self.erase_in_line(params.get(0, 0)),
+            ([], 'L') => self.insert_lines(count(0)),
+            ([], 'M') => self.delete_lines(count(0)),
+            ([], 'P') => self.delete_chars(count(0)),
+            ([], 'S') => self.scroll_up(count(0)),
+            ([], 'T') => self.scroll_down(count(0)),
+            ([], 'X') => self.erase_chars(count(0)),
+            ([], 'Z') => self.back_tab(count(0)),
+            ([], 'b') => self.repeat_last(count(0)),
+            ([], 'c') if params.get(0, 0) == 0 => self.respond(PRIMARY_DEVICE_ATTRIBUTES),
+            ([b'>'], 'c') if params.get(0, 0) == 0 => self.report_secondary_device_attributes(),
+            ([b'='], 'c') if params.get(0, 0) == 0 => self.respond(TERTIARY_DEVICE_ATTRIBUTES),
+            ([], 'd') => self.goto_row(count(0) - 1),
+            ([], 'g') => self.clear_tab_stops(params.get(0, 0)),
+            ([], 'h') => self.set_ansi_modes(params, true),
+            ([], 'l') => self.set_ansi_modes(params, false),
+            ([b'?'], 'h') => self.set_private_modes(params, true),
+            ([b'?'], 'l') => self.set_private_modes(params, false),
+            ([], 'm') => self.select_graphic_rendition(params),
+            ([], 'n') => self.report_status(params.get(0, 0), false),
+            ([b'?'], 'n') => self.report_status(params.get(0, 0), true),
+            ([b'$'], 'p') => self.report_mode(params.get(0, 0), false),
+            ([b'?', b'$'], 'p') => self.report_mode(params.get(0, 0), true),
+            ([b'>'], 'm') => self.set_modify_keys(params.get(0, 0), params.get(1, 0)),
+            ([b'>'], 'n') => self.set_modify_keys(params.get(0, 0), 0),
+            ([], 'r') => {
+                let bottom = usize::from(params.get(1, self.rows() as u16));
+                self.set_scroll_region(count(0) - 1, bottom - 1);
+            }
+            ([], 's') => self.save_cursor(),
+            ([], 'u') => self.restore_cursor(),
+            ([b'>'], 'u') => self.push_keyboard_flags(params.get(0, 0) as u8),
+            ([b'<'], 'u') => self.pop_keyboard_flags(count(0)),
+            ([b'='], 'u') => self.set_keyboard_flags(params.get(0, 0) as u8, params.get(1, 1)),
+            ([b'?'], 'u') => {
+                let reply = format!("\x1b[?{}u", self.keyboard_flags());
+                self.respond(reply.as_bytes());
+            }
+            ([b' '], 'q') => self.set_cursor_style(params.get(0, 0)),
+            ([b'>'], 'q') if params.get(0, 0) == 0 => {
+                let reply = format!("\x1bP>|{}({})\x1b\\", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+                self.respond(reply.as_bytes());
+            }
+            ([], 't') => match params.get(0, 0) {
+                22 => self.push_title(params.get(1, 0)),
+                23 => self.pop_title(params.get(1, 0)),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn hook(&mut self, _params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        if !ignore {
+            self.hook_dcs(intermediates, action);
+        }
+    }
+
+    fn put(&mut self, byte: u8) {
+        self.put_dcs(byte);
+    }
+
+    fn unhook(&mut self) {
+        self.unhook_dcs();
+    }
+
+    fn apc_dispatch(&mut self, data: &[u8]) {
+        if let Some(command) = data.strip_prefix(b"G") {
+            self.kitty_graphics(command);
+        }
+    }
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool) {
+        match params {
+            // OSC 0/1/2 ; text — the text may contain semicolons.
+            [kind @ (b"0" | b"1" | b"2"), text @ ..] => {
+                let text = String::from_utf8_lossy(&text.join(&b';')).into_owned();
+                let title = (*kind != b"1").then_some(text.as_str());
+                let icon_name = (*kind != b"2").then_some(text.as_str());
+                self.set_title(title, icon_name);
+            }
+            // OSC 4 ; index ; spec [; index ; spec …]
+            [b"4", pairs @ ..] => {
+                for pair in pairs.chunks_exact(2) {
+                    let Some(index) = std::str::from_utf8(pair[0]).ok().and_then(|index| index.parse().ok()) else {
+                        continue;
+                    };
+                    self.set_or_query_color(&format!("4;{}", index), ColorSlot::Indexed(index), pair[1], bell_terminated);
+                }
+            }
+            [b"7", uri @ ..] => self.set_working_directory(cwd::parse(&uri.join(&b';'))),
+            // OSC 8 ; params ; URI — the URI itself may contain semicolons.
+            [b"8", link_params, uri @ ..] => {
+                let uri = String::from_utf8_lossy(&uri.join(&b';')).into_owned();
+                let id = String::from_utf8_lossy(link_params)
+                    .split(':')
+                    .find_map(|param| param.strip_prefix("id=").map(str::to_string));
+                self.set_hyperlink(id.as_deref(), &uri);
+            }
+            [b"52", selection, data] => {
+                if let Some(request) = clipboard::parse(selection, data, bell_terminated) {
+                    self.request_clipboard(request);
+                }
+            }
+            // OSC 10 ; spec [; spec …] — further specs go on to the next color,
+            // as in `OSC 10 ; fg ; bg`.
+            [code @ (b"10" | b"11" | b"12"), specs @ ..] => {
+                let slots = [ColorSlot::Foreground, ColorSlot::Background, ColorSlot::Cursor];
+                let first = usize::from(code[1] - b'0');
+                for (number, (slot, spec)) in (10 + first..).zip(slots[first..].iter().zip(specs)) {
+                    self.set_or_query_color(&number.to_string(), *slot, spec, bell_terminated);
+                }
+            }
+            // OSC 104 [; index …] — no index resets the whole palette.
+            [b"104", indices @ ..] => {
+                if indices.iter().all(|index| index.is_empty()) {
+                    (0..=255).for_each(|index| self.reset_color(ColorSlot::Indexed(index)));
+                }
+                for index in indices.iter().filter_map(|index| std::str::from_utf8(index).ok()?.parse().ok()) {
+                    self.reset_color(ColorSlot::Indexed(index));
+                }
+            }
+            [b"110", ..] => self.reset_color(ColorSlot::Foreground),
+            [b"111", ..] => self.reset_color(ColorSlot::Background),
+            [b"112", ..] => self.reset_color(ColorSlot::Cursor),
+            // OSC 133 ; A|B|C|D [; exit status]; `B` (end of the prompt) is
+            // not needed to find commands.
+            [b"133", kind, rest @ ..] => match kind.first() {
+                Some(b'A') => self.mark_prompt(),
+                Some(b'C') => self.mark_output(),
+                Some(b'D') => {
+                    let status = rest.first().and_then(|status| std::str::from_utf8(status).ok()?.parse().ok());
+                    self.mark_command_end(status);
+                }
+                _ => {}
+            },
+            // OSC 1337 ; File=args : base64 — the arguments are separated by
+            // semicolons too.
+            [b"1337", command @ ..] => {
+                let command = command.join(&b';');
+                if let Some(file) = command.strip_prefix(b"File=") {
+                    let colon = file.iter().position(|&byte| byte == b':').unwrap_or(file.len());
+                    self.iterm_file(&file[..colon], file.get(colon + 1..).unwrap_or_default());
+                } else if let Some(args) = command.strip_prefix(b"MultipartFile=") {
+                    self.begin_multipart_file(args);
+                } else if let Some(part) = command.strip_prefix(b"FilePart=") {
+                    self.multipart_file_part(part);
+                } else if command == b"FileEnd" {
+                    self.end_multipart_file();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Screen {
+    /// OSC 4 and 10–12: `?` asks for the color, answered under `number`;
+    /// anything else is a color to set. Unknown colors are ignored.
+    fn set_or_query_color(&mut self, number: &str, slot: ColorSlot, spec: &[u8], bell_terminated: bool) {
+        if spec == b"?" {
+            let end = if bell_terminated { "\x07" } else { "\x1b\\" };
+            let reply = format!("\x1b]{};{}{}", number, self.palette.color(slot).to_x11(), end);
+            self.respond(reply.as_bytes());
+        } else if let Some(color) = std::str::from_utf8(spec).ok().and_then(Rgb::parse_x11) {
+            self.set_color(slot, color);
+        }
+    }
+
+    fn set_ansi_modes(&mut self, params: &Params, enable: bool) {
+        for mode in params.iter().filter_map(|group| group.first()) {
+            match mode {
+                4 => self.modes.insert = enable,
+                20 => self.modes.linefeed_newline = enable,
+                _ => {}
+            }
+        }
+    }
+
+    fn set_private_modes(&mut self, params: &Params, enable: bool) {
+        for mode in params.iter().filter_map(|group| group.first()) {
+            match mode {
+                1 => self.modes.application_cursor = enable,
+                6 => {
+                    self.modes.origin = enable;
+                    self.goto(0, 0);
+                }
+                7 => self.modes.autowrap = enable,
+                9 => self.set_mouse_mode(MouseMode::X10, enable),
+                12 => self.cursor_blinking = enable,
+                25 => self.modes.cursor_visible = enable,
+                47 if enable => self.enter_alternate_screen(false, false),
+                47 => self.leave_alternate_screen(false, false),
+                1000 => self.set_mouse_mode(MouseMode::Normal, enable),
+                1002 => self.set_mouse_mode(MouseMode::ButtonEvent, enable),
+                1003 => self.set_mouse_mode(MouseMode::AnyEvent, enable),
+                1005 => self.set_mouse_encoding(MouseEncoding::Utf8, enable),
+                1006 => self.set_mouse_encoding(MouseEncoding::Sgr, enable),
+                1015 => self.set_mouse_encoding(MouseEncoding::Urxvt, enable),
+                1047 if enable => self.enter_alternate_screen(false, false),
+                1047 => self.leave_alternate_screen(false, true),
+                1049 if enable => self.enter_alternate_screen(true, true),
+                1049 => self.leave_alternate_screen(true, false),
+                2004 => self.modes.bracketed_paste = enable,
+                2026 => self.modes.synchronized_output = enable,
+                _ => {}
+            }
+        }
+    }
+
+    /// DA2: a VT220 (1) with the version as `major * 10000 + minor * 100 +
+    /// patch`, and no ROM cartridge.
+    fn report_secondary_device_attributes(&mut self) {
+        let version = [env!("CARGO_PKG_VERSION_MAJOR"), env!("CARGO_PKG_VERSION_MINOR"), env!("CARGO_PKG_VERSION_PATCH")]
+            .iter()
+            .fold(0u32, |version, part| version * 100 + part.parse::<u32>().unwrap_or(0));
+        let reply = format!("\x1b[>1;{};0c", version);
+        self.respond(reply.as_bytes());
+    }
+
+    /// DSR: 5 asks whether the terminal is working, 6 where the cursor is.
+    fn report_status(&mut self, kind: u16, private: bool) {
+        match kind {
+            5 if !private => self.respond(b"\x1b[0n"),
+            6 => self.report_cursor_position(private),
+            _ => {}
+        }
+    }
+
+    /// DECRQM: whether `mode` is set, for every mode `set_ansi_modes` and
+    /// `set_private_modes` know.
+    fn report_mode(&mut self, mode: u16, private: bool) {
+        let state = if private {
+            match mode {
+                1 => Some(self.modes.application_cursor),
+                6 => Some(self.modes.origin),
+                7 => Some(self.modes.autowrap),
+                9 => Some(self.modes.mouse == MouseMode::X10),
+                12 => Some(self.cursor_blinking),
+                25 => Some(self.modes.cursor_visible),
+                47 | 1047 | 1049 => Some(self.is_alternate_screen()),
+                1000 => Some(self.modes.mouse == MouseMode::Normal),
+                1002 => Some(self.modes.mouse == MouseMode::ButtonEvent),
+                1003 => Some(self.modes.mouse == MouseMode::AnyEvent),
+                1005 => Some(self.modes.mouse_encoding == MouseEncoding::Utf8),
+                1006 => Some(self.modes.mouse_encoding == MouseEncoding::Sgr),
+                1015 => Some(self.modes.mouse_encoding == MouseEncoding::Urxvt),
+                2004 => Some(self.modes.bracketed_paste),
+                2026 => Some(self.modes.synchronized_output),
+                _ => None,
+            }
+        } else {
+            match mode {
+                4 => Some(self.modes.insert),
+                20 => Some(self.modes.linefeed_newline),
+                _ => None,
+            }
+        };
+        let state = match state {
+            Some(true) => MODE_SET,
+            Some(false) => MODE_RESET,
+            None => MODE_UNKNOWN,
+        };
+        let reply = format!("\x1b[{}{};{}$y", if private { "?" } else { "" }, mode, state);
+        self.respond(reply.as_bytes());
+    }
+
+    /// XTMODKEYS: only resource 4, `modifyOtherKeys`, is supported; an
+    /// omitted value resets it.
+    fn set_modify_keys(&mut self, resource: u16, value: u16) {
+        if resource == 4 {
+            self.modes.modify_other_keys = value.min(2) as u8;
+        }
+    }
+
+    /// Tracking modes replace each other; resetting one that is not active
+    /// leaves the current mode alone.
+    fn set_mouse_mode(&mut self, mode: MouseMode, enable: bool) {
+        if enable {
+            self.modes.mouse = mode;
+        } else if self.modes.mouse == mode {
+            self.modes.mouse = MouseMode::Off;
+        }
+    }
+
+    fn set_mouse_encoding(&mut self, encoding: MouseEncoding, enable: bool) {
+        if enable {
+            self.modes.mouse_encoding = encoding;
+        } else if self.modes.mouse_encoding == encoding {
+            self.modes.mouse_encoding = MouseEncoding::Default;
+        }
+    }
+
+    fn set_cursor_style(&mut self, style: u16) {
+        let (shape, blinking) = match style {
+            0 | 1 => (CursorShape::Block, true),
+            2 => (CursorShape::Block, false),
+            3 => (CursorShape::Underline, true),
+            4 => (CursorShape::Underline, false),
+            5 => (CursorShape::Bar, true),
+            6 => (CursorShape::Bar, false),
+            _ => return,
+        };
+        self.cursor_shape = shape;
+        self.cursor_blinking = blinking;
+    }
+
+    /// SGR: updates the pen used for subsequently printed characters.
+    fn select_graphic_rendition(&mut self, params: &Params) {
+        // A reset keeps the hyperlink, which only OSC 8 ends.
+        let reset = |pen: &mut Attrs| *pen = Attrs { link: pen.link, ..Default::default() };
+        if params.is_empty() {
+            reset(self.pen_mut());
+            return;
+        }
+
+        let pen = self.pen_mut();
+        let mut groups = params.iter();
+        while let Some(group) = groups.next() {
+            let Some(&code) = group.first() else { continue };
+            match code {
+                0 => reset(pen),
+                1 => pen.flags.insert(Flags::BOLD),
+                2 => pen.flags.insert(Flags::DIM),
+                3 => pen.flags.insert(Flags::ITALIC),
+                // `4:0` is the subparameter form of "no underline".
+                4 if group.get(1) == Some(&0) => pen.flags.remove(Flags::UNDERLINE),
+                4 => pen.flags.insert(Flags::UNDERLINE),
+                5 | 6 => pen.flags.insert(Flags::BLINK),
+                7 => pen.flags.insert(Flags::INVERSE),
+                8 => pen.flags.insert(Flags::HIDDEN),
+                9 => pen.flags.insert(Flags::STRIKETHROUGH),
+                21 => pen.flags.insert(Flags::UNDERLINE),
+                22 => {
+                    pen.flags.remove(Flags::BOLD);
+                    pen.flags.remove(Flags::DIM);
+                }
+                23 => pen.flags.remove(Flags::ITALIC),
+                24 => pen.flags.remove(Flags::UNDERLINE),
+                25 => pen.flags.remove(Flags::BLINK),
+                27 => pen.flags.remove(Flags::INVERSE),
+                28 => pen.flags.remove(Flags::HIDDEN),
+                29 => pen.flags.remove(Flags::STRIKETHROUGH),
+                30..=37 => pen.fg = Color::Indexed((code - 30) as u8),
+                38 => {
+                    if let Some(color) = extended_color(group, &mut groups) {
+                        pen.fg = color;
+                    }
+                }
+                39 => pen.fg = Color::Default,
+                40..=47 => pen.bg = Color::Indexed((code - 40) as u8),
+                48 => {
+                    if let Some(color) = extended_color(group, &mut groups) {
+                        pen.bg = color;
+                    }
+                }
+                49 => pen.bg = Color::Default,
+                90..=97 => pen.fg = Color::Indexed((code - 90 + 8) as u8),
+                100..=107 => pen.bg = Color::Indexed((code - 100 + 8) as u8),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Parses the color following SGR 38/48, in either the `38;5;n` / `38;2;r;g;b`
+/// form or the colon form `38:5:n` / `38:2::r:g:b` (with or without the
+/// color space id).
+fn extended_color<'a>(group: &[u16], rest: &mut impl Iterator<Item = &'a [u16]>) -> Option<Color> {
+    let byte = |value: u16| value.min(255) as u8;
+
+    if group.len() > 1 {
+        return match group[1..] {
+            [5, index, ..] => Some(Color::Indexed(byte(index))),
+            [2, _, r, g, b, ..] | [2, r, g, b] => Some(Color::Rgb(Rgb::new(byte(r), byte(g), byte(b)))),
+            _ => None,
+        };
+    }
+
+    let mut next = || rest.next().and_then(|group| group.first().copied());
+    match next()? {
+        5 => Some(Color::Indexed(byte(next()?))),
+        2 => {
+            let (r, g, b) = (next()?, next()?, next()?);
+            Some(Color::Rgb(Rgb::new(byte(r), byte(g), byte(b))))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::Terminal;
+
+    /// What the terminal answers to `input`.
+    fn replies(terminal: &mut Terminal, input: &str) -> String {
+        terminal.feed(input.as_bytes());
+        String::from_utf8(terminal.screen_mut().take_responses()).unwrap()
+    }
+
+    #[test]
+    fn device_attributes() {
+        let mut terminal = Terminal::new(20, 5);
+        assert_eq!(replies(&mut terminal, "\x1b[c\x1b[0c").as_bytes(), [PRIMARY_DEVICE_ATTRIBUTES; 2].concat());
+        assert_eq!(replies(&mut terminal, "\x1b[=c").as_bytes(), TERTIARY_DEVICE_ATTRIBUTES);
+        assert_eq!(replies(&mut terminal, "\x1b[1c\x1b[>1c"), "");
+
+        let secondary = replies(&mut terminal, "\x1b[>c");
+        let version = secondary.strip_prefix("\x1b[>1;").and_then(|rest| rest.strip_suffix(";0c")).unwrap();
+        let [major, minor, patch] =
+            [env!("CARGO_PKG_VERSION_MAJOR"), env!("CARGO_PKG_VERSION_MINOR"), env!("CARGO_PKG_VERSION_PATCH")]
+                .map(|part| part.parse::<u32>().unwrap());
+        assert_eq!(version.parse::<u32>().unwrap(), major * 10_000 + minor * 100 + patch);
+    }
+
+    #[test]
+    fn device_status() {
+        let mut terminal = Terminal::new(20, 5);
+        assert_eq!(replies(&mut terminal, "\x1b[5n"), "\x1b[0n");
+        assert_eq!(replies(&mut terminal, "\x1b[3;7H\x1b[6n"), "\x1b[3;7R");
+        assert_eq!(replies(&mut terminal, "\x1b[?6n"), "\x1b[?3;7R");
+        assert_eq!(replies(&mut terminal, "\x1b[2;4r\x1b[?6h\x1b[2;2H\x1b[6n"), "\x1b[2;2R");
+        assert_eq!(replies(&mut terminal, "\x1b[?5n\x1b[7n"), "");
+    }
+
+    #[test]
+    fn private_modes_are_reported() {
+        let mut terminal = Terminal::new(20, 5);
+        assert_eq!(replies(&mut terminal, "\x1b[?25$p"), "\x1b[?25;1$y");
+        assert_eq!(replies(&mut terminal, "\x1b[?25l\x1b[?25$p"), "\x1b[?25;2$y");
+        assert_eq!(replies(&mut terminal, "\x1b[?2004$p"), "\x1b[?2004;2$y");
+        assert_eq!(replies(&mut terminal, "\x1b[?2004h\x1b[?2004$p"), "\x1b[?2004;1$y");
+        assert_eq!(replies(&mut terminal, "\x1b[?1002h\x1b[?1000$p\x1b[?1002$p"), "\x1b[?1000;2$y\x1b[?1002;1$y");
+        assert_eq!(replies(&mut terminal, "\x1b[?1006h\x1b[?1006$p\x1b[?1005$p"), "\x1b[?1006;1$y\x1b[?1005;2$y");
+        assert_eq!(replies(&mut terminal, "\x1b[?1049h\x1b[?47$p\x1b[?1049$p"), "\x1b[?47;1$y\x1b[?1049;1$y");
+        assert_eq!(replies(&mut terminal, "\x1b[?2026$p"), "\x1b[?2026;2$y");
+        assert_eq!(replies(&mut terminal, "\x1b[?2026h\x1b[?2026$p"), "\x1b[?2026;1$y");
+        assert!(terminal.screen().modes.synchronized_output);
+        assert_eq!(replies(&mut terminal, "\x1b[?2026l\x1b[?2026$p"), "\x1b[?2026;2$y");
+        assert!(!terminal.screen().modes.synchronized_output);
+        assert_eq!(replies(&mut terminal, "\x1b[?9999$p"), "\x1b[?9999;0$y");
+    }
+
+    #[test]
+    fn ansi_modes_are_reported() {
+        let mut terminal = Terminal::new(20, 5);
+        assert_eq!(replies(&mut terminal, "\x1b[4$p\x1b[20$p"), "\x1b[4;2$y\x1b[20;2$y");
+        assert_eq!(replies(&mut terminal, "\x1b[4h\x1b[20h\x1b[4$p\x1b[20$p"), "\x1b[4;1$y\x1b[20;1$y");
+        // Private mode numbers are not ANSI modes.
+        assert_eq!(replies(&mut terminal, "\x1b[25$p"), "\x1b[25;0$y");
+    }
+
+    #[test]
+    fn version_is_reported() {
+        let mut terminal = Terminal::new(20, 5);
+        let expected = format!("\x1bP>|{}({})\x1b\\", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        assert_eq!(replies(&mut terminal, "\x1b[>q"), expected);
+        assert_eq!(replies(&mut terminal, "\x1b[>1q"), "");
+    }
+
+    #[test]
+    fn capabilities_are_reported() {
+        let mut terminal = Terminal::new(20, 5);
+        let name = "\x1bP1+r544E=787465726D2D6D61636F732D7465726D\x1b\\";
+        assert_eq!(replies(&mut terminal, "\x1bP+q544E;78\x1b\\"), format!("{name}\x1bP0+r78\x1b\\"));
+        assert_eq!(replies(&mut terminal, "\x1bP+q626F6C64\x1b\\"), "\x1bP1+r626F6C64=1B5B316D\x1b\\");
+        // Other device control strings get no answer.
+        assert_eq!(replies(&mut terminal, "\x1bP$q626F6C64\x1b\\"), "");
+    }
+}
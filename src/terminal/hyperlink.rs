@@ -0,0 +1,207 @@
+//! Hyperlinks: explicit OSC 8 links and URLs or file locations recognised in
+//! the text.
+//!
+//! OSC 8 links are stored once in `Hyperlinks` and cells refer to them by id
+//! through `Attrs::link`, so they survive the trip through the scrollback.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use regex::Regex;
+
+use super::screen::Screen;
+use super::search::LogicalLine;
+use super::selection::Point;
+
+/// Links kept before unreferenced ones are dropped.
+const HYPERLINK_LIMIT: usize = 1024;
+
+struct Hyperlink {
+    /// The `id=` parameter; cells with the same id and URI form one link
+    /// even when printed separately.
+    id: Option<String>,
+    uri: String,
+}
+
+#[derive(Default)]
+pub struct Hyperlinks {
+    links: HashMap<u32, Hyperlink>,
+    next_id: u32,
+}
+
+impl Hyperlinks {
+    pub fn uri(&self, link: u32) -> Option<&str> {
+        self.links.get(&link).map(|link| link.uri.as_str())
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.links.len() >= HYPERLINK_LIMIT
+    }
+
+    /// Returns the id for a link, reusing an existing one with the same
+    /// `id=` and URI. `None` means there was no room.
+    pub fn insert(&mut self, id: Option<&str>, uri: &str) -> Option<u32> {
+        if let Some(id) = id
+            && let Some((&existing, _)) =
+                self.links.iter().find(|(_, link)| link.id.as_deref() == Some(id) && link.uri == uri)
+        {
+            return Some(existing);
+        }
+        if self.is_full() {
+            return None;
+        }
+        self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+        self.links.insert(self.next_id, Hyperlink { id: id.map(str::to_string), uri: uri.to_string() });
+        Some(self.next_id)
+    }
+
+    /// Forgets every link not in `used`.
+    pub fn retain(&mut self, used: &HashSet<u32>) {
+        self.links.retain(|link, _| used.contains(link));
+    }
+}
+
+/// What a link points at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LinkTarget {
+    Uri(String),
+    /// A path as printed, possibly relative, with an optional position.
+    File { path: String, line: Option<u32>, col: Option<u32> },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Link {
+    pub start: Point,
+    pub end: Point,
+    pub target: LinkTarget,
+}
+
+impl Link {
+    /// The columns of absolute line `line` the link covers, if any.
+    pub fn columns(&self, line: usize, cols: usize) -> Option<Range<usize>> {
+        if line < self.start.line || line > self.end.line {
+            return None;
+        }
+        let first = if line == self.start.line { self.start.col } else { 0 };
+        let last = if line == self.end.line { self.end.col + 1 } else { cols };
+        Some(first..last)
+    }
+}
+
+/// The link under `point`: an OSC 8 link if the cell has one, otherwise the
+/// first match of `url_regex` or `file_regex` covering it. `file_regex` may
+/// name `path`, `line` and `col` groups.
+pub fn link_at(screen: &Screen, point: Point, url_regex: &Regex, file_regex: &Regex) -> Option<Link> {
+    let mut first = point.line;
+    while first > screen.first_line() && screen.absolute_row(first - 1).is_some_and(|row| row.wrapped) {
+        first -= 1;
+    }
+    let (logical, _) = LogicalLine::read(screen, first);
+
+    let link = screen.absolute_row(point.line)?.cells.get(point.col)?.attrs.link;
+    if let Some(uri) = screen.hyperlink(link) {
+        let (start, end) = logical.link_cells(point, link)?;
+        return Some(Link { start, end, target: LinkTarget::Uri(uri.to_string()) });
+    }
+
+    let covers = |range| {
+        let (start, end) = logical.cells(range);
+        (start <= point && point <= end).then_some((start, end))
+    };
+    for found in url_regex.find_iter(&logical.text).filter(|found| !found.is_empty()) {
+        if let Some((start, end)) = covers(found.range()) {
+            return Some(Link { start, end, target: LinkTarget::Uri(found.as_str().to_string()) });
+        }
+    }
+    for captures in file_regex.captures_iter(&logical.text) {
+        let Some(found) = captures.get(0).filter(|found| !found.is_empty()) else { continue };
+        let Some((start, end)) = covers(found.range()) else { continue };
+        let number = |name| captures.name(name).and_then(|value| value.as_str().parse().ok());
+        let path = captures.name("path").unwrap_or(found).as_str().to_string();
+        return Some(Link { start, end, target: LinkTarget::File { path, line: number("line"), col: number("col") } });
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DEFAULT_FILE_REGEX, DEFAULT_URL_REGEX};
+    use crate::terminal::Terminal;
+
+    fn link_at_point(terminal: &Terminal, line: usize, col: usize) -> Option<Link> {
+        let url_regex = Regex::new(DEFAULT_URL_REGEX).unwrap();
+        let file_regex = Regex::new(DEFAULT_FILE_REGEX).unwrap();
+        link_at(terminal.screen(), Point { line, col }, &url_regex, &file_regex)
+    }
+
+    fn point(line: usize, col: usize) -> Point {
+        Point { line, col }
+    }
+
+    #[test]
+    fn links_with_the_same_id_share_an_entry() {
+        let mut links = Hyperlinks::default();
+        let first = links.insert(Some("a"), "https://one").unwrap();
+        assert_eq!(links.insert(Some("a"), "https://one"), Some(first));
+        assert_ne!(links.insert(Some("a"), "https://two"), Some(first));
+        assert_ne!(links.insert(None, "https://one"), Some(first));
+        assert_ne!(links.insert(None, "https://one"), links.insert(None, "https://one"));
+        assert_eq!(links.uri(first), Some("https://one"));
+        assert_eq!(links.uri(0), None);
+    }
+
+    #[test]
+    fn the_table_is_bounded() {
+        let mut links = Hyperlinks::default();
+        let kept = links.insert(None, "https://kept").unwrap();
+        for _ in 1..HYPERLINK_LIMIT {
+            assert!(links.insert(None, "https://x").is_some());
+        }
+        assert!(links.is_full());
+        assert_eq!(links.insert(None, "https://y"), None);
+        links.retain(&HashSet::from([kept]));
+        assert_eq!(links.uri(kept), Some("https://kept"));
+        assert!(links.insert(None, "https://y").is_some());
+    }
+
+    #[test]
+    fn osc8_links() {
+        let terminal = Terminal::with_output(10, 2, "a \x1b]8;;https://x\x1b\\link\x1b]8;;\x07 b");
+        let link = link_at_point(&terminal, 0, 3).unwrap();
+        assert_eq!(link.start, point(0, 2));
+        assert_eq!(link.end, point(0, 5));
+        assert_eq!(link.target, LinkTarget::Uri("https://x".to_string()));
+        assert_eq!(link_at_point(&terminal, 0, 7), None);
+    }
+
+    #[test]
+    fn osc8_links_span_wraps() {
+        let terminal = Terminal::with_output(4, 3, "ab\x1b]8;id=1;https://x\x07cdef\x1b]8;;\x07g");
+        let link = link_at_point(&terminal, 1, 1).unwrap();
+        assert_eq!((link.start, link.end), (point(0, 2), point(1, 1)));
+        assert_eq!(link.columns(0, 4), Some(2..4));
+        assert_eq!(link.columns(1, 4), Some(0..2));
+        assert_eq!(link.columns(2, 4), None);
+    }
+
+    #[test]
+    fn recognises_urls() {
+        let terminal = Terminal::with_output(40, 2, "see https://example.com/a?b=1 now");
+        let link = link_at_point(&terminal, 0, 10).unwrap();
+        assert_eq!((link.start, link.end), (point(0, 4), point(0, 28)));
+        assert_eq!(link.target, LinkTarget::Uri("https://example.com/a?b=1".to_string()));
+        assert_eq!(link_at_point(&terminal, 0, 1), None);
+    }
+
+    #[test]
+    fn recognises_file_locations() {
+        let terminal = Terminal::with_output(40, 2, "error at src/main.rs:12:5: oops\r\nlib.rs:7");
+        let link = link_at_point(&terminal, 0, 12).unwrap();
+        let path = "src/main.rs".to_string();
+        assert_eq!(link.target, LinkTarget::File { path, line: Some(12), col: Some(5) });
+        assert_eq!((link.start, link.end), (point(0, 9), point(0, 24)));
+        let link = link_at_point(&terminal, 1, 0).unwrap();
+        assert_eq!(link.target, LinkTarget::File { path: "lib.rs".to_string(), line: Some(7), col: None });
+    }
+}
@@ -0,0 +1,353 @@
+//! zlib decompression (RFC 1950 around RFC 1951 deflate), for kitty graphics
+//! payloads sent with `o=z`.
+//!
+//! Huffman codes are decoded a bit at a time the way zlib's `puff` does it:
+//! slow next to a table-driven decoder, but small, and payloads are at most
+//! a few screens of pixels. The Adler-32 trailer is checked, so a payload
+//! damaged on the way is refused rather than shown as garbage.
+
+/// Code length code lengths are sent in this order.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] =
+    [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+const MAX_BITS: usize = 15;
+
+/// Inflates a zlib stream. Returns `None` if it is malformed, fails its
+/// checksum or would grow past `max_len` bytes.
+pub fn zlib(data: &[u8], max_len: usize) -> Option<Vec<u8>> {
+    let [method, flags, ..] = *data else { return None };
+    // Deflate, no preset dictionary, and the header check.
+    if method & 0x0f != 8 || flags & 0x20 != 0 || (u16::from(method) << 8 | u16::from(flags)) % 31 != 0 {
+        return None;
+    }
+    let mut inflater = Inflater { data: &data[2..], pos: 0, bits: 0, bit_count: 0, out: Vec::new(), max_len };
+    inflater.run()?;
+    // The trailer starts on the byte after the last block.
+    let trailer = inflater.data.get(inflater.pos..inflater.pos + 4)?;
+    (u32::from_be_bytes(trailer.try_into().ok()?) == adler32(&inflater.out)).then_some(inflater.out)
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MODULUS: u32 = 65521;
+    // The largest run that cannot overflow `b` before reducing.
+    const CHUNK: usize = 5552;
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(CHUNK) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= MODULUS;
+        b %= MODULUS;
+    }
+    b << 16 | a
+}
+
+/// A canonical Huffman code: how many codes there are of each length and
+/// the symbols ordered by code.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Builds the code, or returns `None` if the lengths ask for more codes
+    /// than there are. Incomplete codes are accepted; decoding fails if
+    /// the stream uses a missing code.
+    fn new(lengths: &[u8]) -> Option<Self> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[usize::from(length)] += 1;
+        }
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return None;
+            }
+        }
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for length in 1..=MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; usize::from(offsets[MAX_BITS + 1])];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                let offset = &mut offsets[usize::from(length)];
+                symbols[usize::from(*offset)] = symbol as u16;
+                *offset += 1;
+            }
+        }
+        counts[0] = 0;
+        Some(Self { counts, symbols })
+    }
+}
+
+struct Inflater<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u32,
+    bit_count: u32,
+    out: Vec<u8>,
+    max_len: usize,
+}
+
+impl Inflater<'_> {
+    fn run(&mut self) -> Option<()> {
+        loop {
+            let last = self.read_bits(1)? == 1;
+            match self.read_bits(2)? {
+                0 => self.stored()?,
+                1 => {
+                    let mut lengths = [0u8; 288];
+                    lengths[..144].fill(8);
+                    lengths[144..256].fill(9);
+                    lengths[256..280].fill(7);
+                    lengths[280..].fill(8);
+                    self.codes(&Huffman::new(&lengths)?, &Huffman::new(&[5; 30])?)?;
+                }
+                2 => self.dynamic()?,
+                _ => return None,
+            }
+            if last {
+                return Some(());
+            }
+        }
+    }
+
+    fn read_bits(&mut self, count: u32) -> Option<u32> {
+        while self.bit_count < count {
+            let byte = *self.data.get(self.pos)?;
+            self.pos += 1;
+            self.bits |= u32::from(byte) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bits & ((1 << count) - 1);
+        self.bits >>= count;
+        self.bit_count -= count;
+        Some(value)
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> Option<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..=MAX_BITS {
+            code |= self.read_bits(1)? as i32;
+            let count = i32::from(huffman.counts[length]);
+            if code - count < first {
+                return huffman.symbols.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+
+    fn stored(&mut self) -> Option<()> {
+        self.bits = 0;
+        self.bit_count = 0;
+        let header = self.data.get(self.pos..self.pos + 4)?;
+        let len = u16::from_le_bytes([header[0], header[1]]);
+        if len != !u16::from_le_bytes([header[2], header[3]]) {
+            return None;
+        }
+        self.pos += 4;
+        let block = self.data.get(self.pos..self.pos + usize::from(len))?;
+        self.pos += usize::from(len);
+        if self.out.len() + block.len() > self.max_len {
+            return None;
+        }
+        self.out.extend_from_slice(block);
+        Some(())
+    }
+
+    fn dynamic(&mut self) -> Option<()> {
+        let literals = self.read_bits(5)? as usize + 257;
+        let distances = self.read_bits(5)? as usize + 1;
+        let code_lengths = self.read_bits(4)? as usize + 4;
+        if literals > 286 || distances > 30 {
+            return None;
+        }
+        let mut lengths = [0u8; 19];
+        for &index in &CODE_LENGTH_ORDER[..code_lengths] {
+            lengths[index] = self.read_bits(3)? as u8;
+        }
+        let code_length_code = Huffman::new(&lengths)?;
+
+        let mut lengths = vec![0u8; literals + distances];
+        let mut index = 0;
+        while index < lengths.len() {
+            let symbol = self.decode(&code_length_code)?;
+            let (value, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 => (*lengths.get(index.checked_sub(1)?)?, 3 + self.read_bits(2)?),
+                17 => (0, 3 + self.read_bits(3)?),
+                _ => (0, 11 + self.read_bits(7)?),
+            };
+            let end = index + repeat as usize;
+            lengths.get_mut(index..end)?.fill(value);
+            index = end;
+        }
+        if lengths[256] == 0 {
+            return None;
+        }
+        self.codes(&Huffman::new(&lengths[..literals])?, &Huffman::new(&lengths[literals..])?)
+    }
+
+    fn codes(&mut self, literal: &Huffman, distance: &Huffman) -> Option<()> {
+        loop {
+            let symbol = usize::from(self.decode(literal)?);
+            match symbol {
+                0..=255 => {
+                    if self.out.len() >= self.max_len {
+                        return None;
+                    }
+                    self.out.push(symbol as u8);
+                }
+                256 => return Some(()),
+                _ => {
+                    let index = symbol - 257;
+                    let length = usize::from(*LENGTH_BASE.get(index)?)
+                        + self.read_bits(u32::from(LENGTH_EXTRA[index]))? as usize;
+                    let index = usize::from(self.decode(distance)?);
+                    let distance = usize::from(*DISTANCE_BASE.get(index)?)
+                        + self.read_bits(u32::from(DISTANCE_EXTRA[index]))? as usize;
+                    if distance > self.out.len() || self.out.len() + length > self.max_len {
+                        return None;
+                    }
+                    // Copies may overlap what they produce, so go byte by byte.
+                    let start = self.out.len() - distance;
+                    for offset in 0..length {
+                        self.out.push(self.out[start + offset]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STORED: &[u8] = b"\
+        \x78\x01\x01\x14\x00\xeb\xff\x68\x65\x6c\x6c\x6f\x2c\x20\x68\x65\x6c\x6c\x6f\x2c\
+        \x20\x68\x65\x6c\x6c\x6f\x21\x4b\x1e\x06\xf6";
+    const FIXED: &[u8] = b"\x78\xda\xcb\x48\xcd\xc9\xc9\xd7\x51\xc8\x40\xa2\x14\x01\x4b\x1e\x06\xf6";
+    const DYNAMIC: &[u8] = b"\
+        \x78\xda\x95\xcb\xc9\x0d\x80\x30\x10\x04\xc1\x3f\x51\x4c\x08\xdc\x57\x36\x0b\xf6\
+        \x03\xbc\x78\x11\x08\x21\x67\x8f\x09\x61\xbe\xad\x2e\xdd\xa2\x47\x39\x43\x54\xf1\
+        \xda\x15\x20\xd1\x21\x1a\x4e\x95\x84\x43\x82\xbf\xb1\xcb\x9a\x33\xdc\x93\x97\xc5\
+        \x52\xa1\x3f\xa9\x78\x52\xf3\xa4\xe1\x49\xcb\x93\x8e\x27\x3d\x4f\x06\x9e\x8c\x3c\
+        \x99\x28\xf2\x01\x79\x48\xac\xd6";
+    /// Two blocks, the second copying from the first.
+    const ACROSS_BLOCKS: &[u8] = b"\
+        \x78\xda\x4a\x4c\x4a\x4e\x49\x4d\x4b\xcf\xc8\xcc\xca\xce\xc9\xcd\xcb\x2f\x48\x44\
+        \xe3\x03\x00\x00\x00\xff\xff\x23\xc4\x07\x00\x4c\x2d\x1a\x21";
+
+    fn dynamic_text() -> Vec<u8> {
+        (0..10).flat_map(|i| format!("line {}: all work and no play makes jack a dull boy\n", i).into_bytes()).collect()
+    }
+
+    /// A zlib header followed by `fields` of `(value, bits)`, packed the way
+    /// deflate reads them.
+    fn stream(fields: &[(u32, u32)]) -> Vec<u8> {
+        let mut bytes = vec![0x78, 0x01];
+        let (mut buffer, mut count) = (0u64, 0);
+        for &(value, bits) in fields {
+            buffer |= u64::from(value) << count;
+            count += bits;
+            while count >= 8 {
+                bytes.push(buffer as u8);
+                buffer >>= 8;
+                count -= 8;
+            }
+        }
+        bytes.push(buffer as u8);
+        bytes.extend_from_slice(&[0; 8]);
+        bytes
+    }
+
+    #[test]
+    fn stored_blocks() {
+        assert_eq!(zlib(STORED, 1024).as_deref(), Some(&b"hello, hello, hello!"[..]));
+        let mut corrupt = STORED.to_vec();
+        corrupt[5] ^= 1;
+        assert_eq!(zlib(&corrupt, 1024), None);
+    }
+
+    #[test]
+    fn fixed_blocks() {
+        assert_eq!(zlib(FIXED, 1024).as_deref(), Some(&b"hello, hello, hello!"[..]));
+    }
+
+    #[test]
+    fn dynamic_blocks() {
+        assert_eq!(zlib(DYNAMIC, 1024), Some(dynamic_text()));
+    }
+
+    #[test]
+    fn back_references_cross_blocks() {
+        assert_eq!(zlib(ACROSS_BLOCKS, 1024), Some(b"abcdefghijklmnop".repeat(4)));
+    }
+
+    #[test]
+    fn checks_the_header() {
+        let mut data = FIXED.to_vec();
+        data[0] = 0x79;
+        assert_eq!(zlib(&data, 1024), None);
+        // A preset dictionary.
+        assert_eq!(zlib(b"\x78\xbb\0\0\0\0", 1024), None);
+        assert_eq!(zlib(b"\x78", 1024), None);
+    }
+
+    #[test]
+    fn checks_the_adler32_trailer() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        assert_eq!(adler32(&vec![0xff; 100_000]), 0x149a_302c);
+        let mut data = DYNAMIC.to_vec();
+        *data.last_mut().unwrap() ^= 1;
+        assert_eq!(zlib(&data, 1024), None);
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        for stream in [STORED, FIXED, DYNAMIC, ACROSS_BLOCKS] {
+            for len in 0..stream.len() {
+                assert_eq!(zlib(&stream[..len], 1024), None, "{} of {} bytes", len, stream.len());
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_bad_huffman_tables() {
+        // Block type 3 does not exist.
+        assert_eq!(zlib(&stream(&[(1, 1), (3, 2)]), 1024), None);
+        // A code length code with no codes at all.
+        assert_eq!(zlib(&stream(&[(1, 1), (2, 2), (0, 5), (0, 5), (0, 4), (0, 12)]), 1024), None);
+        // Nineteen codes of one bit.
+        let mut fields = vec![(1, 1), (2, 2), (0, 5), (0, 5), (15, 4)];
+        fields.extend([(1, 3); 19]);
+        assert_eq!(zlib(&stream(&fields), 1024), None);
+        // Too many literal/length codes.
+        assert_eq!(zlib(&stream(&[(1, 1), (2, 2), (30, 5), (0, 5), (0, 4)]), 1024), None);
+    }
+
+    #[test]
+    fn stops_at_the_output_limit() {
+        let len = dynamic_text().len();
+        assert_eq!(zlib(DYNAMIC, len).map(|out| out.len()), Some(len));
+        assert_eq!(zlib(DYNAMIC, len - 1), None);
+        assert_eq!(zlib(STORED, 19), None);
+        assert_eq!(zlib(ACROSS_BLOCKS, 63), None);
+    }
+}
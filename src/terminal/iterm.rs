@@ -0,0 +1,238 @@
+//! iTerm2 inline images: `OSC 1337 ; File=key=value;… : base64 ST`, as
+//! `imgcat` sends them.
+//!
+//! The payload is the image file itself, in any format GTK can decode. Large
+//! files may also come in parts: `MultipartFile=` with the arguments, then
+//! `FilePart=` chunks of base64 and `FileEnd`. `width` and `height` are
+//! given in cells (`N`), pixels (`Npx`), percent of the terminal (`N%`) or
+//! `auto`, the image's own size; `preserveAspectRatio=0` lets the image
+//! stretch to fill both. Files sent without `inline=1` are downloads, which
+//! are not supported.
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+use super::graphics::Image;
+
+/// Largest length accepted, in cells or pixels.
+const MAX_LENGTH: u32 = 10_000;
+
+#[derive(Clone, Copy)]
+enum Length {
+    Auto,
+    Cells(u32),
+    Pixels(u32),
+    Percent(u32),
+}
+
+impl Length {
+    fn parse(value: &str) -> Self {
+        let number = |digits: &str| digits.parse::<u32>().ok().map(|number| number.min(MAX_LENGTH));
+        if let Some(pixels) = value.strip_suffix("px").and_then(number) {
+            Self::Pixels(pixels)
+        } else if let Some(percent) = value.strip_suffix('%').and_then(number) {
+            Self::Percent(percent.min(100))
+        } else if let Some(cells) = number(value) {
+            Self::Cells(cells)
+        } else {
+            Self::Auto
+        }
+    }
+
+    /// In pixels, given the size of a cell and of the grid along this axis.
+    fn resolve(self, cell: u32, grid: u32) -> Option<u32> {
+        match self {
+            Self::Auto => None,
+            Self::Cells(cells) => Some(cells * cell),
+            Self::Pixels(pixels) => Some(pixels),
+            Self::Percent(percent) => Some(grid * percent / 100),
+        }
+        .map(|length| length.max(1))
+    }
+}
+
+/// The arguments before the `:`.
+pub struct Args {
+    width: Length,
+    height: Length,
+    preserve_aspect_ratio: bool,
+    inline: bool,
+}
+
+impl Args {
+    pub fn parse(args: &[u8]) -> Self {
+        let mut parsed = Self { width: Length::Auto, height: Length::Auto, preserve_aspect_ratio: true, inline: false };
+        for arg in String::from_utf8_lossy(args).split(';') {
+            let Some((key, value)) = arg.split_once('=') else { continue };
+            match key {
+                "width" => parsed.width = Length::parse(value),
+                "height" => parsed.height = Length::parse(value),
+                "preserveAspectRatio" => parsed.preserve_aspect_ratio = value != "0",
+                "inline" => parsed.inline = value == "1",
+                _ => {}
+            }
+        }
+        parsed
+    }
+
+    /// The image the base64 `payload` holds, if it is to be shown.
+    pub fn image(&self, payload: &[u8]) -> Option<Image> {
+        if !self.inline {
+            return None;
+        }
+        let payload: Vec<u8> = payload.iter().copied().filter(|byte| !byte.is_ascii_whitespace()).collect();
+        Image::encoded(STANDARD.decode(payload).ok()?)
+    }
+
+    /// Size to draw an `image_width` × `image_height` image at, in pixels.
+    /// Images never reach past the right edge of the grid; wider ones are
+    /// scaled down to fit.
+    pub fn size(&self, image_width: u32, image_height: u32, cell: (u32, u32), grid: (u32, u32)) -> (u32, u32) {
+        let (image_width, image_height) = (image_width as f64, image_height as f64);
+        let width = self.width.resolve(cell.0, grid.0).map(f64::from);
+        let height = self.height.resolve(cell.1, grid.1).map(f64::from);
+        let keep = self.preserve_aspect_ratio;
+        let (mut width, mut height) = match (width, height) {
+            (None, None) => (image_width, image_height),
+            (Some(width), None) => (width, if keep { image_height * width / image_width } else { image_height }),
+            (None, Some(height)) => (if keep { image_width * height / image_height } else { image_width }, height),
+            (Some(width), Some(height)) if keep => {
+                let scale = (width / image_width).min(height / image_height);
+                (image_width * scale, image_height * scale)
+            }
+            (Some(width), Some(height)) => (width, height),
+        };
+        let grid_width = f64::from(grid.0);
+        if width > grid_width {
+            if keep {
+                height *= grid_width / width;
+            }
+            width = grid_width;
+        }
+        let clamp = |length: f64| length.round().clamp(1.0, f64::from(MAX_LENGTH)) as u32;
+        (clamp(width), clamp(height))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::Terminal;
+
+    const CELL: (u32, u32) = (10, 20);
+    const GRID: (u32, u32) = (200, 100);
+
+    /// The start of a PNG file, which is all the size is read from.
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[8, 6, 0, 0, 0]);
+        data
+    }
+
+    fn size(args: &str, image_width: u32, image_height: u32) -> (u32, u32) {
+        Args::parse(args.as_bytes()).size(image_width, image_height, CELL, GRID)
+    }
+
+    #[test]
+    fn auto_keeps_the_image_size() {
+        assert_eq!(size("inline=1", 40, 20), (40, 20));
+        assert_eq!(size("width=auto;height=auto", 40, 20), (40, 20));
+        assert_eq!(size("width=wide", 40, 20), (40, 20));
+    }
+
+    #[test]
+    fn lengths_in_cells_pixels_and_percent() {
+        assert_eq!(size("width=10", 40, 20), (100, 50));
+        assert_eq!(size("height=50px", 40, 20), (100, 50));
+        assert_eq!(size("width=50%", 40, 20), (100, 50));
+        assert_eq!(size("height=1", 40, 20), (40, 20));
+        assert_eq!(size("width=0", 40, 20), (1, 1));
+    }
+
+    #[test]
+    fn aspect_ratio_is_kept_unless_disabled() {
+        assert_eq!(size("width=100px;height=100px", 40, 20), (100, 50));
+        assert_eq!(size("width=100px;height=100px;preserveAspectRatio=0", 40, 20), (100, 100));
+        assert_eq!(size("width=100px;preserveAspectRatio=0", 40, 20), (100, 20));
+        assert_eq!(size("height=50px;preserveAspectRatio=0", 40, 20), (40, 50));
+    }
+
+    #[test]
+    fn images_fit_the_grid_width() {
+        assert_eq!(size("width=400px", 40, 20), (200, 100));
+        assert_eq!(size("width=150%", 40, 20), (200, 100));
+        assert_eq!(size("width=99999px;height=10px;preserveAspectRatio=0", 40, 20), (200, 10));
+        assert_eq!(size("inline=1", 1000, 10), (200, 2));
+    }
+
+    #[test]
+    fn only_inline_files_decode() {
+        let payload = STANDARD.encode(png(3, 4));
+        assert!(Args::parse(b"name=eA==").image(payload.as_bytes()).is_none());
+        assert!(Args::parse(b"inline=0").image(payload.as_bytes()).is_none());
+        let image = Args::parse(b"inline=1").image(payload.as_bytes()).unwrap();
+        assert_eq!((image.width, image.height), (3, 4));
+    }
+
+    #[test]
+    fn payload_whitespace_is_ignored() {
+        let payload = STANDARD.encode(png(3, 4));
+        let (start, end) = payload.split_at(10);
+        let image = Args::parse(b"inline=1").image(format!("{start}\r\n {end}\n").as_bytes()).unwrap();
+        assert_eq!((image.width, image.height), (3, 4));
+    }
+
+    #[test]
+    fn bad_payloads_are_ignored() {
+        let args = Args::parse(b"inline=1");
+        assert!(args.image(b"not base64!").is_none());
+        assert!(args.image(STANDARD.encode(b"plain text").as_bytes()).is_none());
+        assert!(args.image(STANDARD.encode(png(0, 4)).as_bytes()).is_none());
+    }
+
+    fn terminal() -> Terminal {
+        let mut terminal = Terminal::new(20, 5);
+        terminal.screen_mut().set_cell_pixel_size(CELL.0, CELL.1);
+        terminal
+    }
+
+    #[test]
+    fn files_are_shown_at_the_cursor() {
+        let mut terminal = terminal();
+        let payload = STANDARD.encode(png(20, 40));
+        terminal.feed(format!("ab\x1b]1337;File=name=eA==;inline=1;width=2:{payload}\x07").as_bytes());
+        let screen = terminal.screen_mut();
+        let placement = screen.images().placements()[0];
+        assert_eq!((placement.line, placement.col, placement.rows, placement.cols), (0, 2, 2, 2));
+        assert_eq!((placement.width, placement.height), (20, 40));
+        assert_eq!((screen.cursor().row, screen.cursor().col), (1, 4));
+    }
+
+    #[test]
+    fn downloads_are_not_shown() {
+        let mut terminal = terminal();
+        let payload = STANDARD.encode(png(20, 40));
+        terminal.feed(format!("\x1b]1337;File=name=eA==:{payload}\x07").as_bytes());
+        assert!(terminal.screen_mut().images().placements().is_empty());
+        assert_eq!(terminal.screen_mut().cursor().col, 0);
+    }
+
+    #[test]
+    fn multipart_files_are_joined() {
+        let mut terminal = terminal();
+        let payload = STANDARD.encode(png(20, 40));
+        let (start, end) = payload.split_at(8);
+        terminal.feed(b"\x1b]1337;MultipartFile=inline=1;width=3\x07");
+        terminal.feed(format!("\x1b]1337;FilePart={start}\x07\x1b]1337;FilePart={end}\x07").as_bytes());
+        assert!(terminal.screen_mut().images().placements().is_empty());
+        terminal.feed(b"\x1b]1337;FileEnd\x07");
+        let placement = terminal.screen_mut().images().placements()[0];
+        assert_eq!((placement.width, placement.height, placement.cols, placement.rows), (30, 60, 3, 3));
+
+        // Without a MultipartFile the parts go nowhere.
+        terminal.feed(format!("\x1b]1337;FilePart={payload}\x07\x1b]1337;FileEnd\x07").as_bytes());
+        assert_eq!(terminal.screen_mut().images().placements().len(), 1);
+    }
+}
@@ -0,0 +1,1057 @@
+//! Kitty graphics protocol: `APC G control ; payload ST`.
+//!
+//! The control data is a comma separated list of `key=value` pairs and the
+//! payload is base64. The payload either is the image, sent in chunks of
+//! `m=1` commands, or names a file, a temporary file the terminal deletes
+//! once read, or a POSIX shared memory object. Pixels come as 24 or 32 bit
+//! RGB(A) or as PNG, optionally zlib compressed.
+//!
+//! Clients refer to images by their own ids (`i`), or by numbers (`I`) the
+//! terminal picks an id for. Images stay until they are deleted; showing
+//! them is up to placements, which sit at the cursor with a z-index saying
+//! whether they go under or over the text. Virtual placements (`U=1`) are
+//! drawn wherever the client prints Unicode placeholders instead: U+10EEEE
+//! with diacritics giving the row and column of the image to show, and the
+//! foreground color giving the image id.
+
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{CString, OsStr};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::str::FromStr;
+
+use base64::Engine;
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+
+use super::cell::{Cell, Color};
+use super::graphics::{Image, Images, Placement, VirtualPlacement};
+use super::inflate;
+
+/// The character standing in for one cell of a virtual placement.
+pub const PLACEHOLDER: char = '\u{10EEEE}';
+
+/// Combining marks that encode row and column numbers in placeholder cells:
+/// the n-th one stands for n. Ranges are inclusive.
+const DIACRITICS: &[(u32, u32)] = &[
+    (0x0305, 0x0305),
+    (0x030D, 0x030E),
+    (0x0310, 0x0310),
+    (0x0312, 0x0312),
+    (0x033D, 0x033F),
+    (0x0346, 0x0346),
+    (0x034A, 0x034C),
+    (0x0350, 0x0352),
+    (0x0357, 0x0357),
+    (0x035B, 0x035B),
+    (0x0363, 0x036F),
+    (0x0483, 0x0487),
+    (0x0592, 0x0595),
+    (0x0597, 0x0599),
+    (0x059C, 0x05A1),
+    (0x05A8, 0x05A9),
+    (0x05AB, 0x05AC),
+    (0x05AF, 0x05AF),
+    (0x05C4, 0x05C4),
+    (0x0610, 0x0617),
+    (0x0657, 0x065B),
+    (0x065D, 0x065E),
+    (0x06D6, 0x06DC),
+    (0x06DF, 0x06E2),
+    (0x06E4, 0x06E4),
+    (0x06E7, 0x06E8),
+    (0x06EB, 0x06EC),
+    (0x0730, 0x0730),
+    (0x0732, 0x0733),
+    (0x0735, 0x0736),
+    (0x073A, 0x073A),
+    (0x073D, 0x073D),
+    (0x073F, 0x0741),
+    (0x0743, 0x0743),
+    (0x0745, 0x0745),
+    (0x0747, 0x0747),
+    (0x0749, 0x074A),
+    (0x07EB, 0x07F1),
+    (0x07F3, 0x07F3),
+    (0x0816, 0x0819),
+    (0x081B, 0x0823),
+    (0x0825, 0x0827),
+    (0x0829, 0x082D),
+    (0x0951, 0x0951),
+    (0x0953, 0x0954),
+    (0x0F82, 0x0F83),
+    (0x0F86, 0x0F87),
+    (0x135D, 0x135F),
+    (0x17DD, 0x17DD),
+    (0x193A, 0x193A),
+    (0x1A17, 0x1A17),
+    (0x1A75, 0x1A7C),
+    (0x1B6B, 0x1B6B),
+    (0x1B6D, 0x1B73),
+    (0x1CD0, 0x1CD2),
+    (0x1CDA, 0x1CDB),
+    (0x1CE0, 0x1CE0),
+    (0x1DC0, 0x1DC1),
+    (0x1DC3, 0x1DC9),
+    (0x1DCB, 0x1DCC),
+    (0x1DD1, 0x1DE6),
+    (0x1DFE, 0x1DFE),
+    (0x20D0, 0x20D1),
+    (0x20D4, 0x20D7),
+    (0x20DB, 0x20DC),
+    (0x20E1, 0x20E1),
+    (0x20E7, 0x20E7),
+    (0x20E9, 0x20E9),
+    (0x20F0, 0x20F0),
+    (0x2CEF, 0x2CF1),
+    (0x2DE0, 0x2DFF),
+    (0xA66F, 0xA66F),
+    (0xA67C, 0xA67D),
+    (0xA6F0, 0xA6F1),
+    (0xA8E0, 0xA8F1),
+    (0xAAB0, 0xAAB0),
+    (0xAAB2, 0xAAB3),
+    (0xAAB7, 0xAAB8),
+    (0xAABE, 0xAABF),
+    (0xAAC1, 0xAAC1),
+    (0xFE20, 0xFE26),
+    (0x10A0F, 0x10A0F),
+    (0x10A38, 0x10A38),
+    (0x1D185, 0x1D189),
+    (0x1D1AA, 0x1D1AD),
+    (0x1D242, 0x1D244),
+];
+
+/// Largest width or height accepted for raw pixels.
+const MAX_DIMENSION: u32 = 10_000;
+/// Most rows or columns a placement may be stretched over.
+const MAX_CELLS: u32 = 1000;
+
+/// Base64 with or without padding; chunked payloads are joined before
+/// decoding, and clients leave the padding off the last chunk.
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// A cell of the grid a placeholder asks for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlaceholderCell {
+    pub image_id: u32,
+    pub row: u32,
+    pub col: u32,
+}
+
+/// Reads a Unicode placeholder cell. `left` is what the cell to its left
+/// read as; whatever the diacritics leave out continues from it when it is
+/// part of the same row of the same image.
+pub fn placeholder(cell: &Cell, left: Option<&PlaceholderCell>) -> Option<PlaceholderCell> {
+    if cell.ch != PLACEHOLDER {
+        return None;
+    }
+    let low = match cell.attrs.fg {
+        Color::Rgb(rgb) => u32::from(rgb.r) << 16 | u32::from(rgb.g) << 8 | u32::from(rgb.b),
+        Color::Indexed(index) => u32::from(index),
+        Color::Default => return None,
+    };
+    let mut numbers = cell.extra.as_deref().unwrap_or_default().chars().map(diacritic);
+    let row = numbers.next().flatten();
+    let col = numbers.next().flatten();
+    let high = numbers.next().flatten();
+
+    let left = left.filter(|left| {
+        left.image_id & 0xff_ffff == low
+            && row.is_none_or(|row| row == left.row)
+            && col.is_none_or(|col| col == left.col + 1)
+    });
+    Some(PlaceholderCell {
+        image_id: high.or(left.map(|left| left.image_id >> 24)).unwrap_or(0) << 24 | low,
+        row: row.or(left.map(|left| left.row)).unwrap_or(0),
+        col: col.or(left.map(|left| left.col + 1)).unwrap_or(0),
+    })
+}
+
+fn diacritic(ch: char) -> Option<u32> {
+    let code = ch as u32;
+    let mut number = 0;
+    for &(first, last) in DIACRITICS {
+        if (first..=last).contains(&code) {
+            return Some(number + code - first);
+        }
+        number += last - first + 1;
+    }
+    None
+}
+
+#[derive(Clone, Copy)]
+struct Command {
+    /// `a`: transmit, transmit and place, query, place or delete.
+    action: u8,
+    /// `q`: 1 drops OK replies, 2 errors too.
+    quiet: u32,
+    /// `f`: 24 (RGB), 32 (RGBA) or 100 (PNG).
+    format: u32,
+    /// `t`: direct, file, temporary file or shared memory.
+    medium: u8,
+    /// `o=z`: zlib compressed.
+    compressed: bool,
+    /// `s` and `v`: size of raw pixels.
+    width: u32,
+    height: u32,
+    /// `S` and `O`: how much of a file or shared memory object to read.
+    size: u32,
+    offset: u32,
+    /// `i`, `I` and `p`.
+    id: u32,
+    number: u32,
+    placement: u32,
+    /// `m=1`: more chunks follow.
+    more: bool,
+    /// `x`, `y`, `w` and `h`: the part of the image to show. Deletions use
+    /// `x` and `y` for cells.
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+    /// `X` and `Y`: offset inside the first cell.
+    cell_x: u32,
+    cell_y: u32,
+    /// `c` and `r`: cells to fit the image into.
+    cols: u32,
+    rows: u32,
+    z: i32,
+    /// `C=1`: leave the cursor where it is.
+    stay: bool,
+    /// `U=1`: a virtual placement, for Unicode placeholders.
+    unicode: bool,
+    /// `d`: what to delete.
+    delete: u8,
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Self {
+            action: b't',
+            quiet: 0,
+            format: 32,
+            medium: b'd',
+            compressed: false,
+            width: 0,
+            height: 0,
+            size: 0,
+            offset: 0,
+            id: 0,
+            number: 0,
+            placement: 0,
+            more: false,
+            x: 0,
+            y: 0,
+            w: 0,
+            h: 0,
+            cell_x: 0,
+            cell_y: 0,
+            cols: 0,
+            rows: 0,
+            z: 0,
+            stay: false,
+            unicode: false,
+            delete: b'a',
+        }
+    }
+}
+
+impl Command {
+    fn parse(control: &[u8]) -> Self {
+        let mut command = Self::default();
+        for pair in control.split(|&byte| byte == b',') {
+            let Some(equals) = pair.iter().position(|&byte| byte == b'=') else { continue };
+            let (&[key], value) = (&pair[..equals], &pair[equals + 1..]) else { continue };
+            let letter = value.first().copied().unwrap_or(0);
+            match key {
+                b'a' => command.action = letter,
+                b'q' => command.quiet = number(value),
+                b'f' => command.format = number(value),
+                b't' => command.medium = letter,
+                b'o' => command.compressed = letter == b'z',
+                b's' => command.width = number(value),
+                b'v' => command.height = number(value),
+                b'S' => command.size = number(value),
+                b'O' => command.offset = number(value),
+                b'i' => command.id = number(value),
+                b'I' => command.number = number(value),
+                b'p' => command.placement = number(value),
+                b'm' => command.more = number::<u32>(value) == 1,
+                b'x' => command.x = number(value),
+                b'y' => command.y = number(value),
+                b'w' => command.w = number(value),
+                b'h' => command.h = number(value),
+                b'X' => command.cell_x = number(value),
+                b'Y' => command.cell_y = number(value),
+                b'c' => command.cols = number::<u32>(value).min(MAX_CELLS),
+                b'r' => command.rows = number::<u32>(value).min(MAX_CELLS),
+                b'z' => command.z = number(value),
+                b'C' => command.stay = number::<u32>(value) == 1,
+                b'U' => command.unicode = number::<u32>(value) == 1,
+                b'd' => command.delete = letter,
+                _ => {}
+            }
+        }
+        command
+    }
+}
+
+fn number<T: FromStr + Default>(value: &[u8]) -> T {
+    std::str::from_utf8(value).ok().and_then(|value| value.parse().ok()).unwrap_or_default()
+}
+
+/// Where a command runs: the cursor and the grid, in absolute lines.
+pub struct Context {
+    pub line: usize,
+    pub col: usize,
+    /// Absolute line of the grid's top row.
+    pub top: usize,
+    pub rows: usize,
+    pub alternate: bool,
+    pub cell_size: (u32, u32),
+}
+
+/// Client side names for images and the transmission in progress.
+#[derive(Default)]
+pub struct Graphics {
+    /// Client image ids to ids in `Images`.
+    ids: HashMap<u32, u32>,
+    /// Image numbers to the client id of the newest image sent with them.
+    numbers: HashMap<u32, u32>,
+    /// Last id picked for an image sent by number.
+    last_assigned: u32,
+    /// First command and base64 payload so far of a chunked transmission.
+    pending: Option<(Command, Vec<u8>)>,
+}
+
+impl Graphics {
+    /// The id in `Images` of the client's image `id`.
+    pub fn image(&self, id: u32) -> Option<u32> {
+        self.ids.get(&id).copied()
+    }
+
+    /// Runs a command, queueing its reply on `responses`. Returns the rows
+    /// and columns a new placement covers when the cursor should move past
+    /// it.
+    pub fn apply(
+        &mut self,
+        data: &[u8],
+        images: &mut Images,
+        context: &Context,
+        responses: &mut Vec<u8>,
+    ) -> Option<(usize, usize)> {
+        let (control, payload) = match data.iter().position(|&byte| byte == b';') {
+            Some(semicolon) => (&data[..semicolon], &data[semicolon + 1..]),
+            None => (data, &[][..]),
+        };
+        let mut command = Command::parse(control);
+        let payload = match self.pending.take() {
+            // Later chunks only carry `m`; everything else comes from the first.
+            Some((first, mut joined)) => {
+                joined.extend_from_slice(payload);
+                if joined.len() / 4 * 3 > images.limit() {
+                    reply(&first, Err("EFBIG:image is over the memory limit".into()), responses);
+                    return None;
+                }
+                if command.more {
+                    self.pending = Some((first, joined));
+                    return None;
+                }
+                command = first;
+                joined
+            }
+            None if command.more && matches!(command.action, b't' | b'T' | b'q') => {
+                self.pending = Some((command, payload.to_vec()));
+                return None;
+            }
+            None => payload.to_vec(),
+        };
+
+        let result = match command.action {
+            b't' | b'T' | b'q' => self.transmit(&mut command, &payload, images, context),
+            b'p' => match self.lookup(&mut command, images) {
+                Some(id) => place(&command, id, images, context),
+                None => Err("ENOENT:no such image".into()),
+            },
+            b'd' => {
+                self.delete(&mut command, images, context);
+                return None;
+            }
+            // Animation frames and composition are not supported.
+            _ => return None,
+        };
+        match result {
+            Ok(moved) => {
+                reply(&command, Ok(()), responses);
+                moved
+            }
+            Err(error) => {
+                reply(&command, Err(error), responses);
+                None
+            }
+        }
+    }
+
+    fn transmit(
+        &mut self,
+        command: &mut Command,
+        payload: &[u8],
+        images: &mut Images,
+        context: &Context,
+    ) -> Result<Option<(usize, usize)>, String> {
+        let data = load(command, payload, images.limit())?;
+        let mut image = decode(command, data)?;
+        if command.action == b'q' {
+            return Ok(None);
+        }
+        if command.id == 0 && command.number != 0 {
+            command.id = self.assign_id();
+        }
+        // Images nobody can name again go with their placement.
+        image.persistent = command.id != 0;
+        if let Some(old) = self.ids.remove(&command.id) {
+            images.remove(old);
+        }
+        let id = images.insert(image).ok_or("EFBIG:image is over the memory limit")?;
+        if command.id != 0 {
+            self.ids.insert(command.id, id);
+        }
+        if command.number != 0 {
+            self.numbers.insert(command.number, command.id);
+        }
+        if command.action == b'T' {
+            return place(command, id, images, context);
+        }
+        Ok(None)
+    }
+
+    fn assign_id(&mut self) -> u32 {
+        loop {
+            self.last_assigned = self.last_assigned.wrapping_add(1).max(1);
+            if !self.ids.contains_key(&self.last_assigned) {
+                return self.last_assigned;
+            }
+        }
+    }
+
+    /// The image a command names by id or number, filling in the id for
+    /// numbers so the reply carries it.
+    fn lookup(&mut self, command: &mut Command, images: &Images) -> Option<u32> {
+        if command.id == 0 && command.number != 0 {
+            command.id = *self.numbers.get(&command.number)?;
+        }
+        let id = *self.ids.get(&command.id)?;
+        if images.image(id).is_none() {
+            // Evicted to make room for newer images.
+            self.ids.remove(&command.id);
+            return None;
+        }
+        Some(id)
+    }
+
+    /// `a=d`: lowercase targets only delete placements, uppercase ones also
+    /// free the images left without any.
+    fn delete(&mut self, command: &mut Command, images: &mut Images, context: &Context) {
+        let cell = |x: u32, y: u32| (context.top + y.max(1) as usize - 1, x.max(1) as usize - 1);
+        let (x, y) = (command.x, command.y);
+        let mut named = Vec::new();
+        match command.delete.to_ascii_lowercase() {
+            b'i' | b'n' => named.extend(self.lookup(command, images)),
+            b'r' => named.extend(self.ids.iter().filter(|(id, _)| (x..=y).contains(id)).map(|(_, id)| *id)),
+            _ => {}
+        }
+        let placement = command.placement;
+        let z = command.z;
+        let hit = |target: &Placement| match command.delete.to_ascii_lowercase() {
+            b'a' => target.last_line() >= context.top && target.line < context.top + context.rows,
+            b'i' | b'n' => named.contains(&target.image) && (placement == 0 || target.id == placement),
+            b'r' => named.contains(&target.image),
+            b'c' => target.covers(context.line, context.col),
+            b'p' => {
+                let (line, col) = cell(x, y);
+                target.covers(line, col)
+            }
+            b'q' => {
+                let (line, col) = cell(x, y);
+                target.covers(line, col) && target.z == z
+            }
+            b'x' => (target.col..target.col + target.cols.max(1)).contains(&(x.max(1) as usize - 1)),
+            b'y' => (target.line..=target.last_line()).contains(&cell(1, y).0),
+            b'z' => target.z == z,
+            _ => false,
+        };
+
+        let mut affected = named.clone();
+        let alternate = context.alternate;
+        images.retain(|target| {
+            let deleted = (target.alternate == alternate || !named.is_empty()) && hit(target);
+            if deleted {
+                affected.push(target.image);
+            }
+            !deleted
+        });
+        if !named.is_empty() {
+            images.retain_virtual(|target| !named.contains(&target.image) || (placement != 0 && target.id != placement));
+        }
+        if command.delete.is_ascii_uppercase() {
+            for id in affected {
+                if images.image(id).is_some() && !images.is_placed(id) {
+                    images.remove(id);
+                    self.ids.retain(|_, image| *image != id);
+                }
+            }
+            let ids = &self.ids;
+            self.numbers.retain(|_, id| ids.contains_key(id));
+        }
+    }
+}
+
+/// Queues the reply to `command`. Clients only get one when they gave an
+/// id or number to match it up with, and `q` may silence it.
+fn reply(command: &Command, result: Result<(), String>, responses: &mut Vec<u8>) {
+    if command.id == 0 && command.number == 0 {
+        return;
+    }
+    let message = match result {
+        Ok(()) if command.quiet == 0 => "OK".to_string(),
+        Err(error) if command.quiet < 2 => error,
+        _ => return,
+    };
+    let mut keys = format!("i={}", command.id);
+    if command.number != 0 {
+        keys.push_str(&format!(",I={}", command.number));
+    }
+    if command.placement != 0 {
+        keys.push_str(&format!(",p={}", command.placement));
+    }
+    responses.extend_from_slice(format!("\x1b_G{keys};{message}\x1b\\").as_bytes());
+}
+
+/// The image bytes a command carries or points to, decompressed.
+fn load(command: &Command, payload: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    let payload: Vec<u8> = payload.iter().copied().filter(|byte| !byte.is_ascii_whitespace()).collect();
+    let payload = BASE64.decode(payload).map_err(|_| "EINVAL:bad base64 data")?;
+    let data = match command.medium {
+        b'd' => payload,
+        b'f' | b't' => {
+            let path = fs::canonicalize(OsStr::from_bytes(&payload)).map_err(|err| format!("EBADF:{}", err))?;
+            let data = read_file(&path, command, limit);
+            if command.medium == b't' && is_temporary(&path) {
+                let _ = fs::remove_file(&path);
+            }
+            data.map_err(|err| format!("EBADF:{}", err))?
+        }
+        b's' => read_shared_memory(&payload, command, limit).map_err(|err| format!("EBADF:{}", err))?,
+        _ => return Err("EINVAL:unknown transmission medium".into()),
+    };
+    if !command.compressed {
+        return Ok(data);
+    }
+    inflate::zlib(&data, limit).ok_or_else(|| "EINVAL:bad zlib data".into())
+}
+
+/// Reads `S` bytes from offset `O` of a regular file, given its canonical
+/// path; files under `/proc`, `/sys` and `/dev` are refused.
+fn read_file(resolved: &Path, command: &Command, limit: usize) -> io::Result<Vec<u8>> {
+    if ["/proc", "/sys", "/dev"].iter().any(|dir| resolved.starts_with(dir)) {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "not an image file"));
+    }
+    let mut file = File::open(resolved)?;
+    if !file.metadata()?.is_file() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a regular file"));
+    }
+    file.seek(SeekFrom::Start(u64::from(command.offset)))?;
+    let len = if command.size == 0 { limit } else { (command.size as usize).min(limit) };
+    let mut data = Vec::new();
+    file.take(len as u64).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Whether the terminal may delete a file sent with `t=t`, given its
+/// canonical path: it has to sit directly in a temporary directory and its
+/// name has to say it is meant for this protocol.
+fn is_temporary(resolved: &Path) -> bool {
+    let named = resolved.file_name().is_some_and(|name| name.to_string_lossy().contains("tty-graphics-protocol"));
+    let Some(parent) = resolved.parent() else { return false };
+    let dirs = [env::temp_dir(), "/tmp".into(), "/dev/shm".into()];
+    named && dirs.iter().filter_map(|dir| fs::canonicalize(dir).ok()).any(|dir| dir == parent)
+}
+
+/// Reads a POSIX shared memory object and unlinks it, as the protocol asks
+/// of the terminal.
+fn read_shared_memory(name: &[u8], command: &Command, limit: usize) -> io::Result<Vec<u8>> {
+    let name = CString::new(name).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "bad name"))?;
+    let fd = unsafe { libc::shm_open(name.as_ptr(), libc::O_RDONLY, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let file = unsafe { File::from_raw_fd(fd) };
+    let data = map_shared_memory(&file, command, limit);
+    unsafe { libc::shm_unlink(name.as_ptr()) };
+    data
+}
+
+fn map_shared_memory(file: &File, command: &Command, limit: usize) -> io::Result<Vec<u8>> {
+    let total = file.metadata()?.len() as usize;
+    let offset = command.offset as usize;
+    let len = if command.size == 0 { total.saturating_sub(offset) } else { command.size as usize };
+    if offset + len > total {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "object is smaller than the image"));
+    }
+    if len > limit {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "image is over the memory limit"));
+    }
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    let mapping = unsafe {
+        libc::mmap(std::ptr::null_mut(), offset + len, libc::PROT_READ, libc::MAP_SHARED, file.as_raw_fd(), 0)
+    };
+    if mapping == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+    let data = unsafe { std::slice::from_raw_parts(mapping.cast::<u8>().add(offset), len) }.to_vec();
+    unsafe { libc::munmap(mapping, offset + len) };
+    Ok(data)
+}
+
+fn decode(command: &Command, mut data: Vec<u8>) -> Result<Image, String> {
+    match command.format {
+        24 | 32 => {
+            let (width, height) = (command.width, command.height);
+            if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+                return Err("EINVAL:bad image size".into());
+            }
+            let channels = command.format as usize / 8;
+            let len = width as usize * height as usize * channels;
+            if data.len() < len {
+                return Err("ENODATA:insufficient image data".into());
+            }
+            data.truncate(len);
+            let pixels = if channels == 4 {
+                data
+            } else {
+                data.chunks_exact(3).flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 0xff]).collect()
+            };
+            Ok(Image::rgba(width, height, pixels))
+        }
+        100 => Image::encoded(data).ok_or_else(|| "EINVAL:not a PNG image".into()),
+        _ => Err("EINVAL:unknown image format".into()),
+    }
+}
+
+/// Places image `id` at the cursor as `command` says. The image is drawn at
+/// its own size unless columns or rows are given, keeping its aspect ratio
+/// if only one is.
+fn place(
+    command: &Command,
+    id: u32,
+    images: &mut Images,
+    context: &Context,
+) -> Result<Option<(usize, usize)>, String> {
+    let image = images.image(id).ok_or("ENOENT:no such image")?;
+    let x = command.x.min(image.width);
+    let y = command.y.min(image.height);
+    let source_width = if command.w == 0 { image.width - x } else { command.w.min(image.width - x) };
+    let source_height = if command.h == 0 { image.height - y } else { command.h.min(image.height - y) };
+    if source_width == 0 || source_height == 0 {
+        return Err("EINVAL:empty source rectangle".into());
+    }
+    let (cell_width, cell_height) = context.cell_size;
+
+    if command.unicode {
+        let cols = if command.cols == 0 { source_width.div_ceil(cell_width) } else { command.cols };
+        let rows = if command.rows == 0 { source_height.div_ceil(cell_height) } else { command.rows };
+        images.place_virtual(VirtualPlacement {
+            image: id,
+            id: command.placement,
+            cols: cols.min(MAX_CELLS) as usize,
+            rows: rows.min(MAX_CELLS) as usize,
+        });
+        return Ok(None);
+    }
+
+    let offset = (command.cell_x.min(cell_width - 1), command.cell_y.min(cell_height - 1));
+    let fill_width = || (command.cols * cell_width).saturating_sub(offset.0).max(1);
+    let fill_height = || (command.rows * cell_height).saturating_sub(offset.1).max(1);
+    let scale = |length: u32, to: u32, from: u32| {
+        (u64::from(length) * u64::from(to) / u64::from(from)).clamp(1, u64::from(u32::MAX)) as u32
+    };
+    let (width, height) = match (command.cols, command.rows) {
+        (0, 0) => (source_width, source_height),
+        (_, 0) => (fill_width(), scale(source_height, fill_width(), source_width)),
+        (0, _) => (scale(source_width, fill_height(), source_height), fill_height()),
+        _ => (fill_width(), fill_height()),
+    };
+    let cols = (offset.0 + width).div_ceil(cell_width).min(MAX_CELLS) as usize;
+    let rows = (offset.1 + height).div_ceil(cell_height).min(MAX_CELLS) as usize;
+    images.place(Placement {
+        image: id,
+        id: command.placement,
+        line: context.line,
+        col: context.col,
+        offset,
+        source: (x, y, source_width, source_height),
+        width,
+        height,
+        rows,
+        cols,
+        z: command.z,
+        alternate: context.alternate,
+    });
+    Ok((!command.stay).then_some((rows, cols)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::Terminal;
+    use crate::terminal::cell::Attrs;
+    use crate::terminal::color::Rgb;
+    use crate::terminal::graphics::ImageData;
+    use std::path::PathBuf;
+
+    const CONTEXT: Context = Context { line: 3, col: 4, top: 0, rows: 24, alternate: false, cell_size: (10, 20) };
+
+    #[derive(Default)]
+    struct Client {
+        graphics: Graphics,
+        images: Images,
+    }
+
+    impl Client {
+        /// Runs a command, returning its reply and where the cursor moves.
+        fn run(&mut self, command: &str) -> (String, Option<(usize, usize)>) {
+            let mut responses = Vec::new();
+            let moved = self.graphics.apply(command.as_bytes(), &mut self.images, &CONTEXT, &mut responses);
+            (String::from_utf8(responses).unwrap(), moved)
+        }
+
+        fn reply(&mut self, command: &str) -> String {
+            self.run(command).0
+        }
+
+        /// Sends a black RGB image of `width` × `height` as client image `id`.
+        fn transmit(&mut self, action: char, id: u32, width: u32, height: u32, extra: &str) -> String {
+            let payload = BASE64.encode(vec![0; (width * height * 3) as usize]);
+            self.reply(&format!("a={action},f=24,s={width},v={height},i={id}{extra};{payload}"))
+        }
+
+        fn image(&self, id: u32) -> Option<&Image> {
+            self.images.image(self.graphics.image(id)?)
+        }
+
+        fn placement_lines(&self) -> Vec<usize> {
+            self.images.placements().iter().map(|placement| placement.line).collect()
+        }
+    }
+
+    fn ok(keys: &str) -> String {
+        format!("\x1b_G{keys};OK\x1b\\")
+    }
+
+    fn placeholder_cell(fg: Color, diacritics: &[char]) -> Cell {
+        let mut cell = Cell::new(PLACEHOLDER, Attrs { fg, ..Attrs::default() });
+        for &ch in diacritics {
+            cell.push_extra(ch);
+        }
+        cell
+    }
+
+    #[test]
+    fn diacritics_number_placeholder_rows_and_columns() {
+        assert_eq!(diacritic('\u{0305}'), Some(0));
+        assert_eq!(diacritic('\u{030D}'), Some(1));
+        assert_eq!(diacritic('\u{0310}'), Some(3));
+        assert_eq!(diacritic('\u{1D244}'), Some(296));
+        assert_eq!(diacritic('a'), None);
+        assert_eq!(diacritic('\u{0306}'), None);
+    }
+
+    #[test]
+    fn reads_placeholder_cells() {
+        let cell = placeholder_cell(Color::Indexed(5), &['\u{030D}', '\u{0310}']);
+        assert_eq!(placeholder(&cell, None), Some(PlaceholderCell { image_id: 5, row: 1, col: 3 }));
+
+        // The third diacritic is the high byte of the id; 24-bit colors give
+        // the low bytes.
+        let cell = placeholder_cell(Color::Rgb(Rgb::new(0x12, 0x34, 0x56)), &['\u{0305}', '\u{0305}', '\u{030E}']);
+        assert_eq!(placeholder(&cell, None), Some(PlaceholderCell { image_id: 0x0212_3456, row: 0, col: 0 }));
+
+        assert_eq!(placeholder(&placeholder_cell(Color::Default, &[]), None), None);
+        assert_eq!(placeholder(&Cell::new('x', Attrs { fg: Color::Indexed(5), ..Attrs::default() }), None), None);
+    }
+
+    #[test]
+    fn placeholder_cells_continue_from_the_left() {
+        let left = PlaceholderCell { image_id: 0x0100_0005, row: 2, col: 6 };
+        let next = Some(PlaceholderCell { col: 7, ..left });
+        assert_eq!(placeholder(&placeholder_cell(Color::Indexed(5), &[]), Some(&left)), next);
+        assert_eq!(placeholder(&placeholder_cell(Color::Indexed(5), &['\u{030E}']), Some(&left)), next);
+
+        // A different image or row starts over.
+        let other = placeholder_cell(Color::Indexed(6), &[]);
+        assert_eq!(placeholder(&other, Some(&left)), Some(PlaceholderCell { image_id: 6, row: 0, col: 0 }));
+        let next_row = placeholder_cell(Color::Indexed(5), &['\u{0310}']);
+        assert_eq!(placeholder(&next_row, Some(&left)), Some(PlaceholderCell { image_id: 5, row: 3, col: 0 }));
+    }
+
+    #[test]
+    fn parses_control_data() {
+        let command = Command::parse(b"a=T,f=100,t=f,o=z,i=7,p=2,c=5000,z=-3,C=1,U=1,q=2,bogus,ab=1,m=x");
+        assert_eq!(command.action, b'T');
+        assert_eq!(command.format, 100);
+        assert_eq!(command.medium, b'f');
+        assert!(command.compressed);
+        assert_eq!((command.id, command.placement), (7, 2));
+        assert_eq!(command.cols, MAX_CELLS);
+        assert_eq!(command.z, -3);
+        assert!(command.stay && command.unicode && !command.more);
+        assert_eq!(command.quiet, 2);
+    }
+
+    /// A directory of its own under the temporary directory.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("kitty-test-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        fs::canonicalize(dir).unwrap()
+    }
+
+    fn send_file(client: &mut Client, path: &Path) -> String {
+        let payload = BASE64.encode(path.as_os_str().as_bytes());
+        client.reply(&format!("a=t,t=t,f=24,s=1,v=1,i=1;{payload}"))
+    }
+
+    #[test]
+    fn temporary_files_are_deleted_after_reading() {
+        let dir = scratch_dir("temporary");
+        let temp = fs::canonicalize(env::temp_dir()).unwrap();
+        let path = temp.join(format!("{}-tty-graphics-protocol", dir.file_name().unwrap().to_string_lossy()));
+        fs::write(&path, [255, 0, 0]).unwrap();
+        let mut client = Client::default();
+        assert_eq!(send_file(&mut client, &path), ok("i=1"));
+        assert!(matches!(&client.image(1).unwrap().data, ImageData::Rgba(pixels) if pixels == &[255, 0, 0, 255]));
+        assert!(!path.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn only_files_directly_in_a_temporary_directory_are_deleted() {
+        let dir = scratch_dir("nested");
+        let nested = dir.join("x-tty-graphics-protocol");
+        fs::write(&nested, [255, 0, 0]).unwrap();
+        let unnamed = dir.with_extension("image");
+        fs::write(&unnamed, [255, 0, 0]).unwrap();
+        let mut client = Client::default();
+
+        assert_eq!(send_file(&mut client, &nested), ok("i=1"));
+        assert!(nested.exists());
+        assert_eq!(send_file(&mut client, &unnamed), ok("i=1"));
+        assert!(unnamed.exists());
+
+        // `..` and symbolic links are resolved before the path is checked.
+        let temp = fs::canonicalize(env::temp_dir()).unwrap();
+        let dotted = temp.join("..").join(nested.strip_prefix("/").unwrap());
+        assert_eq!(send_file(&mut client, &dotted), ok("i=1"));
+        assert!(nested.exists());
+        let link = temp.join(format!("{}-link-tty-graphics-protocol", dir.file_name().unwrap().to_string_lossy()));
+        std::os::unix::fs::symlink(&nested, &link).unwrap();
+        assert_eq!(send_file(&mut client, &link), ok("i=1"));
+        assert!(nested.exists());
+
+        fs::remove_file(link).unwrap();
+        fs::remove_file(unnamed).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn transmits_raw_pixels() {
+        let mut client = Client::default();
+        assert_eq!(client.reply("a=t,f=24,s=1,v=1,i=1;/wAA"), ok("i=1"));
+        let image = client.image(1).unwrap();
+        assert!(image.persistent);
+        assert!(matches!(&image.data, ImageData::Rgba(pixels) if pixels == &[255, 0, 0, 255]));
+
+        assert_eq!(client.reply("a=t,f=32,s=1,v=1,i=2;AQIDBA=="), ok("i=2"));
+        assert!(matches!(&client.image(2).unwrap().data, ImageData::Rgba(pixels) if pixels == &[1, 2, 3, 4]));
+
+        // Sending an id again replaces the image.
+        let old = client.graphics.image(1);
+        client.transmit('t', 1, 2, 2, "");
+        assert_ne!(client.graphics.image(1), old);
+        assert!(client.images.image(old.unwrap()).is_none());
+    }
+
+    #[test]
+    fn transmits_compressed_and_chunked_data() {
+        let mut client = Client::default();
+        assert_eq!(client.reply("a=t,f=24,s=2,v=2,o=z,i=1;eJxj+M/AAEMAGfID/Q=="), ok("i=1"));
+        assert!(matches!(&client.image(1).unwrap().data, ImageData::Rgba(pixels) if pixels[..4] == [0, 255, 0, 255]));
+
+        assert_eq!(client.reply("a=t,f=32,s=1,v=1,i=2,m=1;AQID"), "");
+        assert_eq!(client.reply("m=0;BA"), ok("i=2"));
+        assert!(matches!(&client.image(2).unwrap().data, ImageData::Rgba(pixels) if pixels == &[1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn replies_with_errors() {
+        let mut client = Client::default();
+        assert_eq!(client.reply("a=t,f=24,s=2,v=1,i=1;/wAA"), "\x1b_Gi=1;ENODATA:insufficient image data\x1b\\");
+        assert_eq!(client.reply("a=t,f=24,i=1;/wAA"), "\x1b_Gi=1;EINVAL:bad image size\x1b\\");
+        assert_eq!(client.reply("a=t,f=24,s=1,v=1,i=1;!!!"), "\x1b_Gi=1;EINVAL:bad base64 data\x1b\\");
+        assert_eq!(client.reply("a=t,f=24,s=1,v=1,o=z,i=1;/wAA"), "\x1b_Gi=1;EINVAL:bad zlib data\x1b\\");
+        assert_eq!(client.reply("a=p,i=9"), "\x1b_Gi=9;ENOENT:no such image\x1b\\");
+        // Quiet levels, and commands without an id, get no reply.
+        assert_eq!(client.reply("a=p,i=9,q=1"), "\x1b_Gi=9;ENOENT:no such image\x1b\\");
+        assert_eq!(client.reply("a=p,i=9,q=2"), "");
+        assert_eq!(client.reply("a=t,f=24,s=1,v=1,i=1,q=1;/wAA"), "");
+        assert_eq!(client.reply("a=p"), "");
+    }
+
+    #[test]
+    fn queries_do_not_store_anything() {
+        let mut client = Client::default();
+        assert_eq!(client.reply("a=q,f=24,s=1,v=1,i=31;/wAA"), ok("i=31"));
+        assert!(client.image(31).is_none());
+    }
+
+    #[test]
+    fn numbers_get_an_assigned_id() {
+        let mut client = Client::default();
+        assert_eq!(client.reply("a=t,f=24,s=1,v=1,I=7;/wAA"), ok("i=1,I=7"));
+        assert_eq!(client.reply("a=p,I=7,p=3"), ok("i=1,I=7,p=3"));
+        assert_eq!(client.placement_lines(), [3]);
+    }
+
+    #[test]
+    fn places_at_the_cursor() {
+        let mut client = Client::default();
+        client.transmit('t', 1, 20, 40, "");
+        let (reply, moved) = client.run("a=p,i=1");
+        assert_eq!((reply.as_str(), moved), (ok("i=1").as_str(), Some((2, 2))));
+        let placement = client.images.placements()[0];
+        assert_eq!((placement.line, placement.col, placement.width, placement.height), (3, 4, 20, 40));
+
+        // Columns alone keep the aspect ratio; C=1 leaves the cursor alone.
+        let (_, moved) = client.run("a=p,i=1,p=2,c=4,C=1");
+        assert_eq!(moved, None);
+        let placement = client.images.placements()[1];
+        assert_eq!((placement.width, placement.height, placement.rows, placement.cols), (40, 80, 4, 4));
+
+        // A source rectangle and an offset inside the first cell.
+        client.run("a=p,i=1,p=3,x=5,y=10,w=10,X=3,Y=30");
+        let placement = client.images.placements()[2];
+        assert_eq!(placement.source, (5, 10, 10, 30));
+        assert_eq!(placement.offset, (3, 19));
+        assert_eq!((placement.rows, placement.cols), (3, 2));
+
+        // Placing with the same placement id moves it.
+        client.run("a=p,i=1,p=3,z=-1");
+        assert_eq!(client.images.placements().len(), 3);
+        assert_eq!(client.images.placements()[2].z, -1);
+    }
+
+    #[test]
+    fn transmit_and_place() {
+        let mut client = Client::default();
+        let (reply, moved) = client.run(&format!("a=T,f=24,s=1,v=1,i=1;{}", BASE64.encode([0; 3])));
+        assert_eq!((reply, moved), (ok("i=1"), Some((1, 1))));
+        assert_eq!(client.placement_lines(), [3]);
+    }
+
+    #[test]
+    fn virtual_placements() {
+        let mut client = Client::default();
+        client.transmit('t', 1, 25, 45, "");
+        assert_eq!(client.run("a=p,U=1,i=1,p=2"), (ok("i=1,p=2"), None));
+        let image = client.graphics.image(1).unwrap();
+        assert_eq!(client.images.virtual_placements(), [VirtualPlacement { image, id: 2, cols: 3, rows: 3 }]);
+        assert!(client.images.placements().is_empty());
+        client.run("a=p,U=1,i=1,p=2,c=8,r=2");
+        assert_eq!((client.images.virtual_placements()[0].cols, client.images.virtual_placements()[0].rows), (8, 2));
+    }
+
+    #[test]
+    fn deletes_by_id_keeping_or_freeing_the_image() {
+        let mut client = Client::default();
+        client.transmit('T', 1, 10, 20, "");
+        client.transmit('T', 2, 10, 20, "");
+        client.reply("a=d,d=i,i=1");
+        assert_eq!(client.images.placements().len(), 1);
+        assert!(client.image(1).is_some());
+        client.reply("a=d,d=I,i=2");
+        assert!(client.images.placements().is_empty());
+        assert!(client.image(2).is_none());
+        assert!(client.image(1).is_some());
+    }
+
+    #[test]
+    fn deletes_by_placement_id_and_range() {
+        let mut client = Client::default();
+        client.transmit('t', 1, 10, 20, "");
+        client.transmit('t', 5, 10, 20, "");
+        client.transmit('t', 9, 10, 20, "");
+        for (id, placement) in [(1, 1), (1, 2), (5, 0), (9, 0)] {
+            client.reply(&format!("a=p,i={id},p={placement}"));
+        }
+        client.reply("a=d,d=i,i=1,p=2");
+        assert_eq!(client.images.placements().len(), 3);
+        client.reply("a=d,d=R,x=2,y=9");
+        assert_eq!(client.images.placements().len(), 1);
+        assert!(client.image(5).is_none() && client.image(9).is_none());
+        assert!(client.image(1).is_some());
+    }
+
+    #[test]
+    fn deletes_by_position_and_z_index() {
+        let mut client = Client::default();
+        client.transmit('t', 1, 10, 20, "");
+        client.reply("a=p,i=1,p=1");
+        client.reply("a=p,i=1,p=2,z=-1");
+        client.reply("a=p,i=1,p=3,x=0,c=3");
+
+        // The cursor's cell, 1-based cells, columns, rows and z-indexes.
+        client.reply("a=d,d=z,z=-1");
+        assert_eq!(client.images.placements().len(), 2);
+        client.reply("a=d,d=p,x=7,y=4");
+        assert_eq!(client.images.placements().len(), 1);
+        client.reply("a=p,i=1,p=4");
+        client.reply("a=d,d=y,y=5");
+        assert_eq!(client.images.placements().len(), 2);
+        client.reply("a=d,d=x,x=6");
+        assert_eq!(client.images.placements().len(), 2);
+        client.reply("a=d,d=x,x=5");
+        assert!(client.images.placements().is_empty());
+        client.reply("a=p,i=1");
+        client.reply("a=d,d=c");
+        assert!(client.images.placements().is_empty());
+        // Lowercase targets keep the image.
+        assert!(client.image(1).is_some());
+
+        client.reply("a=p,i=1");
+        client.reply("a=d,d=A");
+        assert!(client.images.placements().is_empty());
+        assert!(client.image(1).is_none());
+    }
+
+    #[test]
+    fn placements_move_the_terminal_cursor() {
+        let mut terminal = Terminal::new(20, 5);
+        terminal.screen_mut().set_cell_pixel_size(10, 20);
+        let payload = BASE64.encode(vec![0; 30 * 50 * 3]);
+        terminal.feed(format!("ab\x1b_Ga=T,f=24,s=30,v=50,i=1;{payload}\x1b\\").as_bytes());
+        let screen = terminal.screen_mut();
+        assert_eq!(screen.take_responses(), ok("i=1").into_bytes());
+        assert_eq!((screen.cursor().row, screen.cursor().col), (2, 5));
+        let placement = screen.images().placements()[0];
+        assert_eq!((placement.line, placement.col, placement.rows, placement.cols), (0, 2, 3, 3));
+    }
+}
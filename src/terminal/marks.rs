@@ -0,0 +1,135 @@
+//! Shell integration marks (OSC 133).
+//!
+//! A shell that emits FinalTerm's semantic prompt sequences sends `A` as it
+//! starts drawing the prompt, `B` where the typed command begins, `C` when
+//! the command starts running and `D;status` once it has finished. Each
+//! prompt starts a `CommandMark`; its lines are absolute, like selections.
+
+use std::ops::RangeInclusive;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CommandMark {
+    /// Line the prompt starts on.
+    pub prompt: usize,
+    /// First line of output, once the command is running.
+    pub output: Option<usize>,
+    /// Last line of output, once the command has finished; before `output`
+    /// if it printed nothing.
+    pub output_end: Option<usize>,
+    pub exit_status: Option<i32>,
+}
+
+impl CommandMark {
+    pub fn new(prompt: usize) -> Self {
+        Self { prompt, output: None, output_end: None, exit_status: None }
+    }
+
+    /// The lines the command printed. Output of a command still running
+    /// extends to `last_line`.
+    pub fn output_lines(&self, last_line: usize) -> Option<RangeInclusive<usize>> {
+        let start = self.output?;
+        let end = self.output_end.unwrap_or(last_line);
+        (start <= end).then_some(start..=end)
+    }
+
+    /// The last line belonging to the command: its output, or the prompt.
+    pub fn last_line(&self, last_line: usize) -> usize {
+        self.output_lines(last_line).map_or(self.prompt, |lines| *lines.end()).max(self.prompt)
+    }
+
+    pub fn failed(&self) -> bool {
+        self.exit_status.is_some_and(|status| status != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::Terminal;
+
+    const PROMPT: &str = "\x1b]133;A\x07$ \x1b]133;B\x07";
+
+    #[test]
+    fn output_lines() {
+        let mut command = CommandMark::new(3);
+        assert_eq!(command.output_lines(9), None);
+        assert_eq!(command.last_line(9), 3);
+        command.output = Some(4);
+        assert_eq!(command.output_lines(9), Some(4..=9));
+        command.output_end = Some(6);
+        assert_eq!(command.output_lines(9), Some(4..=6));
+        assert_eq!(command.last_line(9), 6);
+        // A command that printed nothing.
+        command.output_end = Some(3);
+        assert_eq!(command.output_lines(9), None);
+        assert_eq!(command.last_line(9), 3);
+    }
+
+    #[test]
+    fn failed() {
+        let mut command = CommandMark::new(0);
+        assert!(!command.failed());
+        command.exit_status = Some(0);
+        assert!(!command.failed());
+        command.exit_status = Some(127);
+        assert!(command.failed());
+    }
+
+    #[test]
+    fn marks_commands_from_osc_133() {
+        let bytes = format!(
+            "{PROMPT}ls\r\n\x1b]133;C\x07a\r\nb\r\n\x1b]133;D;2\x07\
+             {PROMPT}true\r\n\x1b]133;C\x07\x1b]133;D;0\x07{PROMPT}"
+        );
+        let terminal = Terminal::with_output(20, 10, &bytes);
+        let commands = terminal.screen().commands();
+        assert_eq!(commands.len(), 3);
+        assert_eq!(commands[0], CommandMark { prompt: 0, output: Some(1), output_end: Some(2), exit_status: Some(2) });
+        assert!(commands[0].failed());
+        assert_eq!(commands[1], CommandMark { prompt: 3, output: Some(4), output_end: Some(3), exit_status: Some(0) });
+        assert_eq!(commands[1].output_lines(9), None);
+        assert_eq!(commands[2], CommandMark::new(4));
+    }
+
+    #[test]
+    fn output_without_a_final_newline_ends_on_the_cursor_line() {
+        let bytes = format!("{PROMPT}printf x\r\n\x1b]133;C\x07x\x1b]133;D;0\x07");
+        let terminal = Terminal::with_output(20, 5, &bytes);
+        assert_eq!(terminal.screen().commands()[0].output_lines(4), Some(1..=1));
+    }
+
+    #[test]
+    fn a_redrawn_prompt_replaces_the_mark() {
+        let terminal = Terminal::with_output(20, 5, &format!("{PROMPT}\r{PROMPT}"));
+        assert_eq!(terminal.screen().commands(), [CommandMark::new(0)]);
+    }
+
+    #[test]
+    fn marks_leave_with_their_history() {
+        let mut terminal = Terminal::with_output(20, 2, PROMPT);
+        terminal.screen_mut().set_scrollback_limit(Some(1));
+        terminal.feed(b"\r\n\r\n");
+        assert_eq!(terminal.screen().commands().len(), 1);
+        terminal.feed(b"\r\n");
+        assert_eq!(terminal.screen().commands(), []);
+    }
+
+    #[test]
+    fn no_marks_on_the_alternate_screen() {
+        let mut terminal = Terminal::with_output(20, 5, PROMPT);
+        terminal.feed(format!("\x1b[?1049h{PROMPT}").as_bytes());
+        assert_eq!(terminal.screen().commands(), []);
+        terminal.feed(b"\x1b[?1049l");
+        assert_eq!(terminal.screen().commands(), [CommandMark::new(0)]);
+    }
+
+    #[test]
+    fn marks_follow_reflow() {
+        let bytes = format!("{PROMPT}echo abcdefgh\r\n\x1b]133;C\x07abcdefgh\r\n\x1b]133;D;0\x07{PROMPT}");
+        let mut terminal = Terminal::with_output(20, 10, &bytes);
+        terminal.screen_mut().resize(5, 10);
+        let commands = terminal.screen().commands();
+        assert_eq!(commands[0], CommandMark { prompt: 0, output: Some(3), output_end: Some(4), exit_status: Some(0) });
+        assert_eq!(commands[1].prompt, 5);
+    }
+}
@@ -0,0 +1,64 @@
+//! Headless terminal emulation.
+//!
+//! The parser turns the child's byte stream into control functions and the
+//! screen model applies them to a grid of cells. Nothing in here touches GTK,
+//! so the emulator can be driven and inspected without a display.
+
+mod cwd;
+mod handler;
+mod inflate;
+mod iterm;
+mod sixel;
+mod terminfo;
+
+pub mod cell;
+pub mod clipboard;
+pub mod color;
+pub mod graphics;
+pub mod hyperlink;
+pub mod kitty;
+pub mod marks;
+pub mod mouse;
+pub mod parser;
+pub mod screen;
+pub mod scrollback;
+pub mod search;
+pub mod selection;
+
+pub use parser::Parser;
+pub use screen::Screen;
+
+pub struct Terminal {
+    parser: Parser,
+    screen: Screen,
+}
+
+impl Terminal {
+    pub fn new(cols: usize, rows: usize) -> Self {
+        Self {
+            parser: Parser::new(),
+            screen: Screen::new(cols, rows),
+        }
+    }
+
+    /// Runs output from the child through the parser into the screen.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.parser.advance(&mut self.screen, bytes);
+    }
+
+    /// A terminal of the given size that has been fed `output`.
+    #[cfg(test)]
+    pub fn with_output(cols: usize, rows: usize, output: &str) -> Self {
+        let mut terminal = Self::new(cols, rows);
+        terminal.feed(output.as_bytes());
+        terminal
+    }
+
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    pub fn screen_mut(&mut self) -> &mut Screen {
+        &mut self.screen
+    }
+}
@@ -0,0 +1,224 @@
+//! Mouse reporting: which events the application asked for and how they are
+//! encoded on the wire.
+
+/// Tracking mode selected with DECSET 9/1000/1002/1003.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MouseMode {
+    #[default]
+    Off,
+    /// Mode 9: button presses only, without modifiers.
+    X10,
+    /// Mode 1000: presses and releases.
+    Normal,
+    /// Mode 1002: as `Normal`, plus motion while a button is held.
+    ButtonEvent,
+    /// Mode 1003: as `Normal`, plus all motion.
+    AnyEvent,
+}
+
+/// Coordinate encoding selected with DECSET 1005/1006/1015.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MouseEncoding {
+    /// `ESC [ M` followed by three bytes; positions past 223 cannot be sent.
+    #[default]
+    Default,
+    /// Mode 1005: as `Default`, with values written as UTF-8 characters.
+    Utf8,
+    /// Mode 1006: `ESC [ < b ; x ; y M` for presses and `m` for releases.
+    Sgr,
+    /// Mode 1015: `ESC [ b ; x ; y M` with decimal values.
+    Urxvt,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    WheelUp,
+    WheelDown,
+    WheelLeft,
+    WheelRight,
+    /// Motion with no button held.
+    None,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseAction {
+    Press,
+    Release,
+    Motion,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MouseEvent {
+    pub action: MouseAction,
+    pub button: MouseButton,
+    /// 0-based cell position.
+    pub col: usize,
+    pub row: usize,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+impl MouseMode {
+    /// Whether the application asked to hear about `event`.
+    pub fn reports(self, event: &MouseEvent) -> bool {
+        let wheel = matches!(
+            event.button,
+            MouseButton::WheelUp | MouseButton::WheelDown | MouseButton::WheelLeft | MouseButton::WheelRight
+        );
+        match (self, event.action) {
+            (MouseMode::Off, _) => false,
+            (MouseMode::X10, MouseAction::Press) => !wheel,
+            (MouseMode::X10, _) => false,
+            (_, MouseAction::Press) => true,
+            // Wheel "buttons" are never released.
+            (_, MouseAction::Release) => !wheel,
+            (MouseMode::ButtonEvent, MouseAction::Motion) => event.button != MouseButton::None,
+            (MouseMode::AnyEvent, MouseAction::Motion) => true,
+            (MouseMode::Normal, MouseAction::Motion) => false,
+        }
+    }
+}
+
+/// Encodes `event` the way the application asked for, or `None` if it cannot
+/// be represented in the selected encoding.
+pub fn encode(event: &MouseEvent, mode: MouseMode, encoding: MouseEncoding) -> Option<Vec<u8>> {
+    let mut code: u32 = match event.button {
+        MouseButton::Left => 0,
+        MouseButton::Middle => 1,
+        MouseButton::Right => 2,
+        MouseButton::None => 3,
+        MouseButton::WheelUp => 64,
+        MouseButton::WheelDown => 65,
+        MouseButton::WheelLeft => 66,
+        MouseButton::WheelRight => 67,
+    };
+    // Only SGR says which button was released.
+    if event.action == MouseAction::Release && encoding != MouseEncoding::Sgr {
+        code = 3;
+    }
+    if event.action == MouseAction::Motion {
+        code += 32;
+    }
+    // Shift (4) is never sent: Shift-clicks are kept for local selection.
+    if mode != MouseMode::X10 {
+        code += u32::from(event.alt) * 8 + u32::from(event.ctrl) * 16;
+    }
+
+    let x = u32::try_from(event.col).ok()?.checked_add(1)?;
+    let y = u32::try_from(event.row).ok()?.checked_add(1)?;
+    match encoding {
+        MouseEncoding::Sgr => {
+            let end = if event.action == MouseAction::Release { 'm' } else { 'M' };
+            Some(format!("\x1b[<{};{};{}{}", code, x, y, end).into_bytes())
+        }
+        MouseEncoding::Urxvt => Some(format!("\x1b[{};{};{}M", code + 32, x, y).into_bytes()),
+        MouseEncoding::Utf8 => {
+            let mut bytes = b"\x1b[M".to_vec();
+            for value in [code, x, y] {
+                let ch = char::from_u32(value + 32).filter(|ch| u32::from(*ch) < 2048)?;
+                bytes.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes());
+            }
+            Some(bytes)
+        }
+        MouseEncoding::Default => {
+            let byte = |value: u32| u8::try_from(value + 32).ok();
+            Some(vec![0x1b, b'[', b'M', byte(code)?, byte(x)?, byte(y)?])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(action: MouseAction, button: MouseButton, col: usize, row: usize) -> MouseEvent {
+        MouseEvent { action, button, col, row, alt: false, ctrl: false }
+    }
+
+    fn press(button: MouseButton, col: usize, row: usize) -> MouseEvent {
+        event(MouseAction::Press, button, col, row)
+    }
+
+    #[test]
+    fn modes_filter_events() {
+        let click = press(MouseButton::Left, 0, 0);
+        let release = event(MouseAction::Release, MouseButton::Left, 0, 0);
+        let wheel = press(MouseButton::WheelUp, 0, 0);
+        let drag = event(MouseAction::Motion, MouseButton::Left, 0, 0);
+        let hover = event(MouseAction::Motion, MouseButton::None, 0, 0);
+
+        assert!(!MouseMode::Off.reports(&click));
+        assert!(MouseMode::X10.reports(&click));
+        assert!(!MouseMode::X10.reports(&release));
+        assert!(!MouseMode::X10.reports(&wheel));
+        assert!(MouseMode::Normal.reports(&release));
+        assert!(MouseMode::Normal.reports(&wheel));
+        assert!(!MouseMode::Normal.reports(&drag));
+        assert!(MouseMode::ButtonEvent.reports(&drag));
+        assert!(!MouseMode::ButtonEvent.reports(&hover));
+        assert!(MouseMode::AnyEvent.reports(&hover));
+        assert!(!MouseMode::AnyEvent.reports(&event(MouseAction::Release, MouseButton::WheelDown, 0, 0)));
+    }
+
+    #[test]
+    fn encodes_default() {
+        let bytes = encode(&press(MouseButton::Left, 0, 0), MouseMode::Normal, MouseEncoding::Default);
+        assert_eq!(bytes.as_deref(), Some(&b"\x1b[M\x20\x21\x21"[..]));
+
+        // Releases do not say which button.
+        let release = event(MouseAction::Release, MouseButton::Right, 9, 4);
+        let bytes = encode(&release, MouseMode::Normal, MouseEncoding::Default);
+        assert_eq!(bytes.as_deref(), Some(&b"\x1b[M\x23\x2a\x25"[..]));
+
+        // Column 223 is the last one a byte can hold.
+        assert!(encode(&press(MouseButton::Left, 222, 0), MouseMode::Normal, MouseEncoding::Default).is_some());
+        assert_eq!(encode(&press(MouseButton::Left, 223, 0), MouseMode::Normal, MouseEncoding::Default), None);
+    }
+
+    #[test]
+    fn encodes_modifiers_except_in_x10() {
+        let mut click = press(MouseButton::Middle, 0, 0);
+        click.alt = true;
+        click.ctrl = true;
+        let bytes = encode(&click, MouseMode::Normal, MouseEncoding::Sgr);
+        assert_eq!(bytes.as_deref(), Some(&b"\x1b[<25;1;1M"[..]));
+        let bytes = encode(&click, MouseMode::X10, MouseEncoding::Sgr);
+        assert_eq!(bytes.as_deref(), Some(&b"\x1b[<1;1;1M"[..]));
+    }
+
+    #[test]
+    fn encodes_sgr() {
+        let bytes = encode(&press(MouseButton::WheelDown, 299, 99), MouseMode::Normal, MouseEncoding::Sgr);
+        assert_eq!(bytes.as_deref(), Some(&b"\x1b[<65;300;100M"[..]));
+
+        let release = event(MouseAction::Release, MouseButton::Right, 0, 0);
+        let bytes = encode(&release, MouseMode::Normal, MouseEncoding::Sgr);
+        assert_eq!(bytes.as_deref(), Some(&b"\x1b[<2;1;1m"[..]));
+
+        let drag = event(MouseAction::Motion, MouseButton::Left, 4, 2);
+        let bytes = encode(&drag, MouseMode::ButtonEvent, MouseEncoding::Sgr);
+        assert_eq!(bytes.as_deref(), Some(&b"\x1b[<32;5;3M"[..]));
+    }
+
+    #[test]
+    fn encodes_urxvt() {
+        let bytes = encode(&press(MouseButton::Left, 299, 0), MouseMode::Normal, MouseEncoding::Urxvt);
+        assert_eq!(bytes.as_deref(), Some(&b"\x1b[32;300;1M"[..]));
+    }
+
+    #[test]
+    fn encodes_utf8() {
+        let bytes = encode(&press(MouseButton::Left, 0, 0), MouseMode::Normal, MouseEncoding::Utf8);
+        assert_eq!(bytes.as_deref(), Some(&b"\x1b[M\x20\x21\x21"[..]));
+
+        // Column 300 is 332 after the offset, two bytes of UTF-8.
+        let bytes = encode(&press(MouseButton::Left, 299, 0), MouseMode::Normal, MouseEncoding::Utf8);
+        assert_eq!(bytes.as_deref(), Some(&b"\x1b[M\x20\xc5\x8c\x21"[..]));
+
+        assert!(encode(&press(MouseButton::Left, 2014, 0), MouseMode::Normal, MouseEncoding::Utf8).is_some());
+        assert_eq!(encode(&press(MouseButton::Left, 2015, 0), MouseMode::Normal, MouseEncoding::Utf8), None);
+    }
+}
@@ -0,0 +1,615 @@
+//! DEC/xterm escape sequence parser.
+//!
+//! A byte-at-a-time state machine modelled on Paul Williams' DEC ANSI parser
+//! (https://vt100.net/emu/dec_ansi_parser). Ground-state text is decoded as
+//! UTF-8; C1 controls are recognised as decoded code points U+0080..U+009F.
+
+const MAX_INTERMEDIATES: usize = 2;
+const MAX_PARAMS: usize = 32;
+/// Upper bound for an OSC payload; longer strings are truncated. Inline
+/// images (OSC 1337) come whole in one string.
+const MAX_OSC_LEN: usize = 64 * 1024 * 1024;
+/// Upper bound for an APC payload; longer strings are dropped.
+const MAX_APC_LEN: usize = 8 * 1024 * 1024;
+
+/// Receives the actions produced by the parser.
+///
+/// Every method has an empty default so handlers only implement what they use.
+pub trait Perform {
+    /// A printable character in the ground state.
+    fn print(&mut self, _ch: char) {}
+
+    /// A C0 or C1 control function.
+    fn execute(&mut self, _byte: u8) {}
+
+    /// A complete control sequence (`CSI ... action`).
+    fn csi_dispatch(&mut self, _params: &Params, _intermediates: &[u8], _ignore: bool, _action: char) {}
+
+    /// A complete escape sequence (`ESC intermediates byte`).
+    fn esc_dispatch(&mut self, _intermediates: &[u8], _ignore: bool, _byte: u8) {}
+
+    /// An operating system command, split on `;`.
+    fn osc_dispatch(&mut self, _params: &[&[u8]], _bell_terminated: bool) {}
+
+    /// Start of a device control string; followed by `put` and `unhook`.
+    fn hook(&mut self, _params: &Params, _intermediates: &[u8], _ignore: bool, _action: char) {}
+
+    /// A data byte of the current device control string.
+    fn put(&mut self, _byte: u8) {}
+
+    /// End of the current device control string.
+    fn unhook(&mut self) {}
+
+    /// An application program command (`APC data ST`).
+    fn apc_dispatch(&mut self, _data: &[u8]) {}
+}
+
+/// Numeric parameters of a control sequence.
+///
+/// Parameters are separated by `;`; a parameter may carry `:`-separated
+/// subparameters (as in `38:2::255:0:0`), which are kept in the same group.
+#[derive(Debug, Default)]
+pub struct Params {
+    values: Vec<u16>,
+    /// Index into `values` where each parameter group starts.
+    groups: Vec<usize>,
+}
+
+impl Params {
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Returns the first value of parameter `index`, or `default` when it is
+    /// missing or zero.
+    pub fn get(&self, index: usize, default: u16) -> u16 {
+        match self.group(index).and_then(|group| group.first()) {
+            Some(&value) if value != 0 => value,
+            _ => default,
+        }
+    }
+
+    /// Returns parameter `index` together with its subparameters.
+    pub fn group(&self, index: usize) -> Option<&[u16]> {
+        let start = *self.groups.get(index)?;
+        let end = self.groups.get(index + 1).copied().unwrap_or(self.values.len());
+        Some(&self.values[start..end])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &[u16]> {
+        (0..self.groups.len()).filter_map(move |index| self.group(index))
+    }
+
+    fn clear(&mut self) {
+        self.values.clear();
+        self.groups.clear();
+    }
+
+    fn push(&mut self, value: u16, subparam: bool) {
+        if self.values.len() >= MAX_PARAMS * 4 || (!subparam && self.groups.len() >= MAX_PARAMS) {
+            return;
+        }
+        if !subparam || self.groups.is_empty() {
+            self.groups.push(self.values.len());
+        }
+        self.values.push(value);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    CsiEntry,
+    CsiParam,
+    CsiIntermediate,
+    CsiIgnore,
+    DcsEntry,
+    DcsParam,
+    DcsIntermediate,
+    DcsPassthrough,
+    DcsIgnore,
+    OscString,
+    SosPmString,
+    ApcString,
+}
+
+pub struct Parser {
+    state: State,
+    params: Params,
+    param: u32,
+    param_started: bool,
+    subparam: bool,
+    intermediates: [u8; MAX_INTERMEDIATES],
+    intermediate_len: usize,
+    ignoring: bool,
+    osc: Vec<u8>,
+    apc: Vec<u8>,
+    /// Set once the APC string outgrew `MAX_APC_LEN`.
+    apc_overflow: bool,
+    utf8: [u8; 4],
+    utf8_len: usize,
+    utf8_needed: usize,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self {
+            state: State::Ground,
+            params: Params::default(),
+            param: 0,
+            param_started: false,
+            subparam: false,
+            intermediates: [0; MAX_INTERMEDIATES],
+            intermediate_len: 0,
+            ignoring: false,
+            osc: Vec::new(),
+            apc: Vec::new(),
+            apc_overflow: false,
+            utf8: [0; 4],
+            utf8_len: 0,
+            utf8_needed: 0,
+        }
+    }
+
+    /// Feeds a chunk of bytes through the state machine.
+    pub fn advance<P: Perform>(&mut self, performer: &mut P, bytes: &[u8]) {
+        for &byte in bytes {
+            self.advance_byte(performer, byte);
+        }
+    }
+
+    fn advance_byte<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        if self.state == State::Ground {
+            self.ground(performer, byte);
+            return;
+        }
+
+        // Transitions that apply from every state except ground.
+        match byte {
+            0x18 | 0x1a => {
+                if self.state == State::DcsPassthrough {
+                    performer.unhook();
+                }
+                performer.execute(byte);
+                self.state = State::Ground;
+                return;
+            }
+            0x1b => {
+                match self.state {
+                    State::OscString => self.osc_end(performer, false),
+                    State::ApcString => self.apc_end(performer),
+                    State::DcsPassthrough => performer.unhook(),
+                    _ => {}
+                }
+                self.enter_escape();
+                return;
+            }
+            _ => {}
+        }
+
+        match self.state {
+            State::Ground => unreachable!(),
+            State::Escape => self.escape(performer, byte),
+            State::EscapeIntermediate => match byte {
+                0x00..=0x17 | 0x19 | 0x1c..=0x1f => performer.execute(byte),
+                0x20..=0x2f => self.collect(byte),
+                0x30..=0x7e => {
+                    performer.esc_dispatch(self.intermediates(), self.ignoring, byte);
+                    self.state = State::Ground;
+                }
+                _ => {}
+            },
+            State::CsiEntry => match byte {
+                0x00..=0x17 | 0x19 | 0x1c..=0x1f => performer.execute(byte),
+                0x20..=0x2f => {
+                    self.collect(byte);
+                    self.state = State::CsiIntermediate;
+                }
+                b'0'..=b'9' | b':' | b';' => {
+                    self.param_byte(byte);
+                    self.state = State::CsiParam;
+                }
+                0x3c..=0x3f => {
+                    self.collect(byte);
+                    self.state = State::CsiParam;
+                }
+                0x40..=0x7e => self.csi_end(performer, byte),
+                _ => {}
+            },
+            State::CsiParam => match byte {
+                0x00..=0x17 | 0x19 | 0x1c..=0x1f => performer.execute(byte),
+                b'0'..=b'9' | b':' | b';' => self.param_byte(byte),
+                0x3c..=0x3f => self.state = State::CsiIgnore,
+                0x20..=0x2f => {
+                    self.collect(byte);
+                    self.state = State::CsiIntermediate;
+                }
+                0x40..=0x7e => self.csi_end(performer, byte),
+                _ => {}
+            },
+            State::CsiIntermediate => match byte {
+                0x00..=0x17 | 0x19 | 0x1c..=0x1f => performer.execute(byte),
+                0x20..=0x2f => self.collect(byte),
+                0x30..=0x3f => self.state = State::CsiIgnore,
+                0x40..=0x7e => self.csi_end(performer, byte),
+                _ => {}
+            },
+            State::CsiIgnore => match byte {
+                0x00..=0x17 | 0x19 | 0x1c..=0x1f => performer.execute(byte),
+                0x40..=0x7e => self.state = State::Ground,
+                _ => {}
+            },
+            State::DcsEntry => match byte {
+                0x20..=0x2f => {
+                    self.collect(byte);
+                    self.state = State::DcsIntermediate;
+                }
+                b'0'..=b'9' | b':' | b';' => {
+                    self.param_byte(byte);
+                    self.state = State::DcsParam;
+                }
+                0x3c..=0x3f => {
+                    self.collect(byte);
+                    self.state = State::DcsParam;
+                }
+                0x40..=0x7e => self.dcs_hook(performer, byte),
+                _ => {}
+            },
+            State::DcsParam => match byte {
+                b'0'..=b'9' | b':' | b';' => self.param_byte(byte),
+                0x3c..=0x3f => self.state = State::DcsIgnore,
+                0x20..=0x2f => {
+                    self.collect(byte);
+                    self.state = State::DcsIntermediate;
+                }
+                0x40..=0x7e => self.dcs_hook(performer, byte),
+                _ => {}
+            },
+            State::DcsIntermediate => match byte {
+                0x20..=0x2f => self.collect(byte),
+                0x30..=0x3f => self.state = State::DcsIgnore,
+                0x40..=0x7e => self.dcs_hook(performer, byte),
+                _ => {}
+            },
+            State::DcsPassthrough => match byte {
+                0x7f => {}
+                _ => performer.put(byte),
+            },
+            State::DcsIgnore => {}
+            State::OscString => match byte {
+                0x07 => self.osc_end(performer, true),
+                0x00..=0x1f => {}
+                _ => {
+                    if self.osc.len() < MAX_OSC_LEN {
+                        self.osc.push(byte);
+                    }
+                }
+            },
+            State::SosPmString => {}
+            State::ApcString => match byte {
+                0x00..=0x1f => {}
+                _ if self.apc.len() < MAX_APC_LEN => self.apc.push(byte),
+                _ => self.apc_overflow = true,
+            },
+        }
+    }
+
+    fn ground<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        if self.utf8_needed > 0 {
+            if byte & 0xc0 == 0x80 {
+                self.utf8[self.utf8_len] = byte;
+                self.utf8_len += 1;
+                if self.utf8_len == self.utf8_needed {
+                    let ch = std::str::from_utf8(&self.utf8[..self.utf8_len])
+                        .ok()
+                        .and_then(|text| text.chars().next())
+                        .unwrap_or(char::REPLACEMENT_CHARACTER);
+                    self.utf8_needed = 0;
+                    self.utf8_len = 0;
+                    self.decoded(performer, ch);
+                }
+                return;
+            }
+            // Truncated sequence: report it and reprocess this byte.
+            self.utf8_needed = 0;
+            self.utf8_len = 0;
+            performer.print(char::REPLACEMENT_CHARACTER);
+        }
+
+        match byte {
+            0x1b => self.enter_escape(),
+            0x00..=0x1f => performer.execute(byte),
+            0x20..=0x7e => performer.print(byte as char),
+            0x7f => {}
+            _ => {
+                let needed = match byte {
+                    0xc2..=0xdf => 2,
+                    0xe0..=0xef => 3,
+                    0xf0..=0xf4 => 4,
+                    _ => {
+                        performer.print(char::REPLACEMENT_CHARACTER);
+                        return;
+                    }
+                };
+                self.utf8[0] = byte;
+                self.utf8_len = 1;
+                self.utf8_needed = needed;
+            }
+        }
+    }
+
+    /// Handles a fully decoded non-ASCII character, including C1 controls.
+    fn decoded<P: Perform>(&mut self, performer: &mut P, ch: char) {
+        match ch as u32 {
+            0x90 => self.enter_dcs(),
+            0x9b => self.enter_csi(),
+            0x9d => self.enter_osc(),
+            0x98 | 0x9e => self.state = State::SosPmString,
+            0x9f => self.enter_apc(),
+            0x9c => {}
+            code @ 0x80..=0x9f => performer.execute(code as u8),
+            _ => performer.print(ch),
+        }
+    }
+
+    fn escape<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        match byte {
+            0x00..=0x17 | 0x19 | 0x1c..=0x1f => performer.execute(byte),
+            0x20..=0x2f => {
+                self.collect(byte);
+                self.state = State::EscapeIntermediate;
+            }
+            b'P' => self.enter_dcs(),
+            b'[' => self.enter_csi(),
+            b']' => self.enter_osc(),
+            b'X' | b'^' => self.state = State::SosPmString,
+            b'_' => self.enter_apc(),
+            0x30..=0x7e => {
+                performer.esc_dispatch(&[], false, byte);
+                self.state = State::Ground;
+            }
+            _ => {}
+        }
+    }
+
+    fn enter_escape(&mut self) {
+        self.clear();
+        self.state = State::Escape;
+    }
+
+    fn enter_csi(&mut self) {
+        self.clear();
+        self.state = State::CsiEntry;
+    }
+
+    fn enter_dcs(&mut self) {
+        self.clear();
+        self.state = State::DcsEntry;
+    }
+
+    fn enter_osc(&mut self) {
+        self.osc.clear();
+        self.state = State::OscString;
+    }
+
+    fn enter_apc(&mut self) {
+        self.apc.clear();
+        self.apc_overflow = false;
+        self.state = State::ApcString;
+    }
+
+    fn clear(&mut self) {
+        self.params.clear();
+        self.param = 0;
+        self.param_started = false;
+        self.subparam = false;
+        self.intermediate_len = 0;
+        self.ignoring = false;
+    }
+
+    fn collect(&mut self, byte: u8) {
+        if self.intermediate_len == MAX_INTERMEDIATES {
+            self.ignoring = true;
+        } else {
+            self.intermediates[self.intermediate_len] = byte;
+            self.intermediate_len += 1;
+        }
+    }
+
+    fn intermediates(&self) -> &[u8] {
+        &self.intermediates[..self.intermediate_len]
+    }
+
+    fn param_byte(&mut self, byte: u8) {
+        match byte {
+            b';' | b':' => {
+                self.params.push(self.param as u16, self.subparam);
+                self.param = 0;
+                self.subparam = byte == b':';
+            }
+            digit => {
+                self.param = (self.param * 10 + u32::from(digit - b'0')).min(u32::from(u16::MAX));
+            }
+        }
+        self.param_started = true;
+    }
+
+    fn finish_params(&mut self) {
+        if self.param_started {
+            self.params.push(self.param as u16, self.subparam);
+        }
+    }
+
+    fn csi_end<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        self.finish_params();
+        performer.csi_dispatch(&self.params, self.intermediates(), self.ignoring, byte as char);
+        self.state = State::Ground;
+    }
+
+    fn dcs_hook<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        self.finish_params();
+        performer.hook(&self.params, self.intermediates(), self.ignoring, byte as char);
+        self.state = State::DcsPassthrough;
+    }
+
+    fn osc_end<P: Perform>(&mut self, performer: &mut P, bell_terminated: bool) {
+        let params: Vec<&[u8]> = self.osc.split(|&byte| byte == b';').collect();
+        performer.osc_dispatch(&params, bell_terminated);
+        self.osc.clear();
+        self.state = State::Ground;
+    }
+
+    fn apc_end<P: Perform>(&mut self, performer: &mut P) {
+        if !self.apc_overflow {
+            performer.apc_dispatch(&self.apc);
+        }
+        self.apc.clear();
+        self.state = State::Ground;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records every action as a line of text.
+    #[derive(Default)]
+    struct Log(Vec<String>);
+
+    impl Perform for Log {
+        fn print(&mut self, ch: char) {
+            self.0.push(format!("print {}", ch));
+        }
+
+        fn execute(&mut self, byte: u8) {
+            self.0.push(format!("execute {:02x}", byte));
+        }
+
+        fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+            let params: Vec<String> =
+                params.iter().map(|group| group.iter().map(u16::to_string).collect::<Vec<_>>().join(":")).collect();
+            self.0.push(format!(
+                "csi {} [{}] {}{}",
+                params.join(";"),
+                String::from_utf8_lossy(intermediates),
+                action,
+                if ignore { " ignored" } else { "" }
+            ));
+        }
+
+        fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+            self.0.push(format!("esc {}{}", String::from_utf8_lossy(intermediates), byte as char));
+        }
+
+        fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool) {
+            let params: Vec<String> = params.iter().map(|param| String::from_utf8_lossy(param).into_owned()).collect();
+            self.0.push(format!("osc {} {}", params.join("|"), if bell_terminated { "bel" } else { "st" }));
+        }
+
+        fn hook(&mut self, params: &Params, intermediates: &[u8], _ignore: bool, action: char) {
+            self.0.push(format!("hook {} {}{}", params.get(0, 0), String::from_utf8_lossy(intermediates), action));
+        }
+
+        fn put(&mut self, byte: u8) {
+            self.0.push(format!("put {}", byte as char));
+        }
+
+        fn unhook(&mut self) {
+            self.0.push("unhook".to_string());
+        }
+
+        fn apc_dispatch(&mut self, data: &[u8]) {
+            self.0.push(format!("apc {}", String::from_utf8_lossy(data)));
+        }
+    }
+
+    fn parse(chunks: &[&[u8]]) -> Vec<String> {
+        let mut parser = Parser::new();
+        let mut log = Log::default();
+        for chunk in chunks {
+            parser.advance(&mut log, chunk);
+        }
+        log.0
+    }
+
+    #[test]
+    fn prints_text_and_executes_c0() {
+        assert_eq!(parse(&[b"a\r\n\x07"]), ["print a", "execute 0d", "execute 0a", "execute 07"]);
+    }
+
+    #[test]
+    fn recognises_c1_controls_as_code_points() {
+        // U+0084 (IND) is executed, U+009B starts a CSI.
+        assert_eq!(parse(&["\u{84}\u{9b}2J".as_bytes()]), ["execute 84", "csi 2 [] J"]);
+    }
+
+    #[test]
+    fn dispatches_escape_sequences() {
+        assert_eq!(parse(&[b"\x1b7\x1b(0\x1b#8"]), ["esc 7", "esc (0", "esc #8"]);
+    }
+
+    #[test]
+    fn collects_csi_params_subparams_and_intermediates() {
+        assert_eq!(
+            parse(&[b"\x1b[1;2H\x1b[38:2::255:0:0m\x1b[?1049h\x1b[ q\x1b[m"]),
+            ["csi 1;2 [] H", "csi 38:2:0:255:0:0 [] m", "csi 1049 [?] h", "csi  [ ] q", "csi  [] m"]
+        );
+    }
+
+    #[test]
+    fn executes_controls_inside_csi() {
+        assert_eq!(parse(&[b"\x1b[1\n;2H"]), ["execute 0a", "csi 1;2 [] H"]);
+    }
+
+    #[test]
+    fn ignores_malformed_csi() {
+        // A private marker after a parameter makes the sequence invalid.
+        assert_eq!(parse(&[b"\x1b[1?2hx"]), ["print x"]);
+        // Too many intermediates are flagged.
+        assert_eq!(parse(&[b"\x1b[1 !$p"]), ["csi 1 [ !] p ignored"]);
+    }
+
+    #[test]
+    fn cancel_aborts_a_sequence() {
+        assert_eq!(parse(&[b"\x1b[1\x18x"]), ["execute 18", "print x"]);
+    }
+
+    #[test]
+    fn dispatches_osc_terminated_by_bel_and_st() {
+        assert_eq!(parse(&[b"\x1b]0;a;b\x07"]), ["osc 0|a|b bel"]);
+        assert_eq!(parse(&[b"\x1b]2;title\x1b\\"]), ["osc 2|title st", "esc \\"]);
+    }
+
+    #[test]
+    fn passes_dcs_data_through() {
+        assert_eq!(
+            parse(&[b"\x1bP1$qab\x1b\\"]),
+            ["hook 1 $q", "put a", "put b", "unhook", "esc \\"]
+        );
+    }
+
+    #[test]
+    fn dispatches_apc_and_skips_sos_pm() {
+        assert_eq!(parse(&[b"\x1b_Gi=1\x1b\\\x1b^ignored\x1b\\x"]), ["apc Gi=1", "esc \\", "esc \\", "print x"]);
+    }
+
+    #[test]
+    fn decodes_utf8_split_across_calls() {
+        let bytes = "é中🎉".as_bytes();
+        let chunks: Vec<&[u8]> = bytes.chunks(1).collect();
+        assert_eq!(parse(&chunks), ["print é", "print 中", "print 🎉"]);
+    }
+
+    #[test]
+    fn replaces_invalid_utf8() {
+        assert_eq!(parse(&[b"\xe4\xb8a\xff"]), ["print \u{fffd}", "print a", "print \u{fffd}"]);
+    }
+}
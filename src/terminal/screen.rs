@@ -0,0 +1,1861 @@
+//! The cell grid and the cursor/mode state that escape sequences act on.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use unicode_width::UnicodeWidthChar;
+
+use super::cell::{Attrs, Cell, Flags, Row};
+use super::clipboard::ClipboardRequest;
+use super::color::{ColorSlot, Palette, Rgb};
+use super::graphics::{Image, Images, Placement, VirtualPlacement};
+use super::hyperlink::Hyperlinks;
+use super::iterm;
+use super::kitty;
+use super::marks::CommandMark;
+use super::mouse::{MouseEncoding, MouseMode};
+use super::scrollback::Scrollback;
+use super::sixel;
+use super::terminfo;
+
+/// History size used until the configuration says otherwise.
+pub const DEFAULT_SCROLLBACK_LINES: usize = 10_000;
+
+/// Depth of the kitty keyboard flag stack; pushing more drops the oldest entry.
+const KEYBOARD_FLAGS_STACK_LIMIT: usize = 16;
+
+/// Depth of the XTWINOPS title stack; pushing more drops the oldest entry.
+const TITLE_STACK_LIMIT: usize = 16;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CursorShape {
+    #[default]
+    Block,
+    Underline,
+    Bar,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cursor {
+    pub row: usize,
+    pub col: usize,
+    /// The pen used for newly printed characters.
+    pub attrs: Attrs,
+    /// Set after printing in the last column; the next character wraps first.
+    pub pending_wrap: bool,
+}
+
+/// Character sets selectable with `ESC ( X` / `ESC ) X`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Charset {
+    #[default]
+    Ascii,
+    DecSpecialGraphics,
+}
+
+impl Charset {
+    fn map(self, ch: char) -> char {
+        if self == Charset::Ascii {
+            return ch;
+        }
+        match ch {
+            '_' => ' ',
+            '`' => '◆',
+            'a' => '▒',
+            'b' => '␉',
+            'c' => '␌',
+            'd' => '␍',
+            'e' => '␊',
+            'f' => '°',
+            'g' => '±',
+            'h' => '␤',
+            'i' => '␋',
+            'j' => '┘',
+            'k' => '┐',
+            'l' => '┌',
+            'm' => '└',
+            'n' => '┼',
+            'o' => '⎺',
+            'p' => '⎻',
+            'q' => '─',
+            'r' => '⎼',
+            's' => '⎽',
+            't' => '├',
+            'u' => '┤',
+            'v' => '┴',
+            'w' => '┬',
+            'x' => '│',
+            'y' => '≤',
+            'z' => '≥',
+            '{' => 'π',
+            '|' => '≠',
+            '}' => '£',
+            '~' => '·',
+            ch => ch,
+        }
+    }
+}
+
+/// State saved by DECSC and restored by DECRC.
+#[derive(Clone, Copy, Debug)]
+struct SavedCursor {
+    cursor: Cursor,
+    origin: bool,
+    charsets: [Charset; 2],
+    active_charset: usize,
+}
+
+/// Terminal modes toggled by SM/RM and DECSET/DECRST.
+#[derive(Clone, Copy, Debug)]
+pub struct Modes {
+    /// DECCKM: cursor keys send application sequences.
+    pub application_cursor: bool,
+    /// DECKPAM/DECKPNM: keypad sends application sequences.
+    pub application_keypad: bool,
+    /// DECOM: cursor addressing is relative to the scroll region.
+    pub origin: bool,
+    /// DECAWM: printing past the last column wraps to the next line.
+    pub autowrap: bool,
+    /// DECTCEM: the cursor is drawn.
+    pub cursor_visible: bool,
+    /// IRM: printed characters shift the rest of the line right.
+    pub insert: bool,
+    /// LNM: line feed also performs a carriage return.
+    pub linefeed_newline: bool,
+    /// Mode 2004: pastes are wrapped in `ESC [200~` / `ESC [201~`.
+    pub bracketed_paste: bool,
+    /// Mode 2026: the application is in the middle of a synchronized update,
+    /// so the screen should not be drawn until it ends.
+    pub synchronized_output: bool,
+    /// Which mouse events are reported to the application.
+    pub mouse: MouseMode,
+    pub mouse_encoding: MouseEncoding,
+    /// XTMODKEYS `modifyOtherKeys` level (0, 1 or 2).
+    pub modify_other_keys: u8,
+}
+
+impl Default for Modes {
+    fn default() -> Self {
+        Self {
+            application_cursor: false,
+            application_keypad: false,
+            origin: false,
+            autowrap: true,
+            cursor_visible: true,
+            insert: false,
+            linefeed_newline: false,
+            bracketed_paste: false,
+            synchronized_output: false,
+            mouse: MouseMode::Off,
+            mouse_encoding: MouseEncoding::Default,
+            modify_other_keys: 0,
+        }
+    }
+}
+
+/// Handlers for the device control strings the screen understands.
+enum Dcs {
+    Sixel(Box<sixel::Decoder>),
+    /// XTGETTCAP: the hex capability names asked for.
+    RequestCapabilities(Vec<u8>),
+}
+
+pub struct Screen {
+    cols: usize,
+    rows: usize,
+    grid: Vec<Row>,
+    /// The grid that is not shown: the primary screen while the alternate
+    /// screen is active and vice versa.
+    inactive_grid: Vec<Row>,
+    alternate_active: bool,
+    scrollback: Scrollback,
+    /// Lines ever scrolled off the top of the primary grid, evicted or not.
+    /// Grid row `r` is absolute line `lines_scrolled + r`, which stays put as
+    /// output scrolls.
+    lines_scrolled: usize,
+    /// Number of history lines the viewport is scrolled back by.
+    display_offset: usize,
+    cursor: Cursor,
+    /// DECSC slot of the active grid; each grid keeps its own.
+    saved_cursor: Option<SavedCursor>,
+    inactive_saved_cursor: Option<SavedCursor>,
+    /// Inclusive scroll region set by DECSTBM.
+    scroll_top: usize,
+    scroll_bottom: usize,
+    tab_stops: Vec<bool>,
+    charsets: [Charset; 2],
+    active_charset: usize,
+    last_printed: Option<char>,
+    /// Rows changed since the renderer last called `take_damage`.
+    damaged: Vec<bool>,
+    /// Kitty keyboard protocol flags; the main and alternate screens keep
+    /// separate stacks.
+    keyboard_flags: Vec<u8>,
+    inactive_keyboard_flags: Vec<u8>,
+    /// Replies to queries, waiting to be written back to the child.
+    responses: Vec<u8>,
+    /// OSC 52 requests, waiting for the frontend to apply its policy.
+    clipboard_requests: Vec<ClipboardRequest>,
+    hyperlinks: Hyperlinks,
+    /// OSC 133 marks of the primary screen, oldest first.
+    commands: Vec<CommandMark>,
+    /// Directory the shell last reported through OSC 7.
+    working_directory: Option<PathBuf>,
+    title: String,
+    icon_name: String,
+    /// Titles and icon names saved by XTWINOPS 22; `None` for the parts a
+    /// push left out.
+    title_stack: Vec<(Option<String>, Option<String>)>,
+    images: Images,
+    /// Kitty graphics ids and chunked transmissions.
+    kitty: kitty::Graphics,
+    /// Arguments and base64 so far of an iTerm2 file sent in parts.
+    multipart_file: Option<(Vec<u8>, Vec<u8>)>,
+    /// Size of a cell in pixels, for fitting images to the grid.
+    cell_pixel_size: (u32, u32),
+    /// Device control string being received.
+    dcs: Option<Dcs>,
+    pub modes: Modes,
+    pub palette: Palette,
+    /// The configured palette, which OSC 104 and 110–112 go back to.
+    default_palette: Palette,
+    pub cursor_shape: CursorShape,
+    pub cursor_blinking: bool,
+}
+
+impl Screen {
+    pub fn new(cols: usize, rows: usize) -> Self {
+        let cols = cols.max(1);
+        let rows = rows.max(1);
+        Self {
+            cols,
+            rows,
+            grid: (0..rows).map(|_| Row::new(cols, Cell::default())).collect(),
+            inactive_grid: (0..rows).map(|_| Row::new(cols, Cell::default())).collect(),
+            alternate_active: false,
+            scrollback: Scrollback::new(Some(DEFAULT_SCROLLBACK_LINES)),
+            lines_scrolled: 0,
+            display_offset: 0,
+            cursor: Cursor::default(),
+            saved_cursor: None,
+            inactive_saved_cursor: None,
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+            tab_stops: default_tab_stops(cols),
+            charsets: [Charset::Ascii; 2],
+            active_charset: 0,
+            last_printed: None,
+            damaged: vec![true; rows],
+            keyboard_flags: Vec::new(),
+            inactive_keyboard_flags: Vec::new(),
+            responses: Vec::new(),
+            clipboard_requests: Vec::new(),
+            hyperlinks: Hyperlinks::default(),
+            commands: Vec::new(),
+            working_directory: None,
+            title: String::new(),
+            icon_name: String::new(),
+            title_stack: Vec::new(),
+            images: Images::default(),
+            kitty: kitty::Graphics::default(),
+            multipart_file: None,
+            cell_pixel_size: (8, 16),
+            dcs: None,
+            modes: Modes::default(),
+            palette: Palette::default(),
+            default_palette: Palette::default(),
+            cursor_shape: CursorShape::default(),
+            cursor_blinking: false,
+        }
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cursor(&self) -> &Cursor {
+        &self.cursor
+    }
+
+    pub fn row(&self, index: usize) -> &Row {
+        &self.grid[index]
+    }
+
+    /// Lines of history reachable from the viewport; none on the alternate screen.
+    pub fn history_len(&self) -> usize {
+        if self.alternate_active { 0 } else { self.scrollback.len() }
+    }
+
+    pub fn set_scrollback_limit(&mut self, limit: Option<usize>) {
+        self.scrollback.set_limit(limit);
+        self.display_offset = self.display_offset.min(self.scrollback.len());
+        self.damage(0..self.rows);
+    }
+
+    pub fn display_offset(&self) -> usize {
+        self.display_offset
+    }
+
+    /// Scrolls the viewport; positive deltas move back into history.
+    pub fn scroll_display(&mut self, delta: isize) {
+        let offset = self.display_offset.saturating_add_signed(delta);
+        self.set_display_offset(offset);
+    }
+
+    pub fn set_display_offset(&mut self, offset: usize) {
+        let offset = offset.min(self.history_len());
+        if offset != self.display_offset {
+            self.display_offset = offset;
+            self.damaged.fill(true);
+        }
+    }
+
+    /// Returns row `index` of the viewport, which may come from history.
+    pub fn visible_row(&self, index: usize) -> Cow<'_, Row> {
+        if index >= self.display_offset {
+            return Cow::Borrowed(&self.grid[index - self.display_offset]);
+        }
+        match self.scrollback.get_from_bottom(self.display_offset - 1 - index) {
+            Some(line) => Cow::Owned(line.to_row(self.cols)),
+            None => Cow::Owned(Row::new(self.cols, Cell::default())),
+        }
+    }
+
+    /// Absolute line number of viewport row `index`.
+    pub fn viewport_line(&self, index: usize) -> usize {
+        self.lines_scrolled + index - self.display_offset
+    }
+
+    /// Returns a row by absolute line number, or `None` once it has left the
+    /// history.
+    pub fn absolute_row(&self, line: usize) -> Option<Cow<'_, Row>> {
+        if line >= self.lines_scrolled {
+            return self.grid.get(line - self.lines_scrolled).map(Cow::Borrowed);
+        }
+        if self.alternate_active {
+            return None;
+        }
+        let line = self.scrollback.get_from_bottom(self.lines_scrolled - 1 - line)?;
+        Some(Cow::Owned(line.to_row(self.cols)))
+    }
+
+    /// The oldest absolute line still reachable.
+    pub fn first_line(&self) -> usize {
+        self.lines_scrolled - self.history_len()
+    }
+
+    /// Absolute line number of the bottom row of the screen.
+    pub fn last_line(&self) -> usize {
+        self.lines_scrolled + self.rows - 1
+    }
+
+    /// Scrolls the viewport so absolute `line` is the top row, or as close
+    /// as the history allows.
+    pub fn scroll_line_to_top(&mut self, line: usize) {
+        self.set_display_offset(self.lines_scrolled.saturating_sub(line));
+    }
+
+    /// Scrolls the viewport the least distance that brings absolute `line`
+    /// into view.
+    pub fn scroll_to_line(&mut self, line: usize) {
+        let top = self.viewport_line(0);
+        let offset = if line < top {
+            self.lines_scrolled.saturating_sub(line)
+        } else if line >= top + self.rows {
+            self.last_line().saturating_sub(line)
+        } else {
+            return;
+        };
+        self.set_display_offset(offset);
+    }
+
+    pub fn pen_mut(&mut self) -> &mut Attrs {
+        &mut self.cursor.attrs
+    }
+
+    /// RIS: returns every piece of state to its power-on value, keeping the
+    /// scrollback. Colors changed through OSC go back to the configured
+    /// palette.
+    pub fn reset(&mut self) {
+        let palette = std::mem::take(&mut self.default_palette);
+        let scrollback = std::mem::replace(&mut self.scrollback, Scrollback::new(None));
+        let responses = std::mem::take(&mut self.responses);
+        let hyperlinks = std::mem::take(&mut self.hyperlinks);
+        let commands = std::mem::take(&mut self.commands);
+        let working_directory = self.working_directory.take();
+        let title = std::mem::take(&mut self.title);
+        let icon_name = std::mem::take(&mut self.icon_name);
+        // Image ids keep counting up, so renderers never mistake a new image
+        // for one they cached.
+        let mut images = std::mem::take(&mut self.images);
+        images.remove_all();
+        let cell_pixel_size = self.cell_pixel_size;
+        let lines_scrolled = self.lines_scrolled;
+        *self = Screen::new(self.cols, self.rows);
+        self.palette = palette.clone();
+        self.default_palette = palette;
+        self.scrollback = scrollback;
+        self.lines_scrolled = lines_scrolled;
+        self.responses = responses;
+        self.hyperlinks = hyperlinks;
+        self.commands = commands;
+        self.working_directory = working_directory;
+        self.title = title;
+        self.icon_name = icon_name;
+        self.images = images;
+        self.cell_pixel_size = cell_pixel_size;
+    }
+
+    /// Resizes both grids. The primary screen is reflowed together with the
+    /// scrollback so soft-wrapped lines rewrap at the new width; the
+    /// alternate screen is only cropped or padded, since full-screen programs
+    /// redraw after SIGWINCH anyway.
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        let cols = cols.max(1);
+        let rows = rows.max(1);
+        if cols == self.cols && rows == self.rows {
+            return;
+        }
+
+        let history = self.scrollback.len();
+        let first_line = self.lines_scrolled - history;
+        let row_map = if self.alternate_active {
+            // The primary cursor only survives in the DECSC slot; without one,
+            // anchor on the last line with content.
+            let anchor = match self.inactive_saved_cursor {
+                Some(saved) => (saved.cursor.row, saved.cursor.col),
+                None => (self.inactive_grid.iter().rposition(|row| !is_blank(row)).unwrap_or(0), 0),
+            };
+            let (grid, (row, col), row_map) =
+                reflow(&mut self.scrollback, &self.inactive_grid, self.cols, cols, rows, anchor);
+            self.inactive_grid = grid;
+            if let Some(saved) = &mut self.inactive_saved_cursor {
+                saved.cursor.row = row;
+                saved.cursor.col = col;
+            }
+            crop(&mut self.grid, cols, rows);
+            row_map
+        } else {
+            let cursor = (self.cursor.row, self.cursor.col);
+            let (grid, (row, col), row_map) = reflow(&mut self.scrollback, &self.grid, self.cols, cols, rows, cursor);
+            self.grid = grid;
+            self.cursor.row = row;
+            self.cursor.col = col;
+            crop(&mut self.inactive_grid, cols, rows);
+            row_map
+        };
+
+        // Reflow keeps the oldest line's number, so marks move with the rows
+        // they were on; marks on lines pushed out of the history are dropped.
+        let map = |line: usize| {
+            let row = row_map.get(line.checked_sub(first_line)?).copied()?;
+            Some(first_line + row?)
+        };
+        // An output end covers every row its line now wraps onto: up to the
+        // row before the next line starts.
+        let map_end = |line: usize| {
+            let start = map(line)?;
+            Some(map(line + 1).map_or(start, |next| next.saturating_sub(1).max(start)))
+        };
+        self.commands = std::mem::take(&mut self.commands)
+            .into_iter()
+            .filter_map(|command| {
+                Some(CommandMark {
+                    prompt: map(command.prompt)?,
+                    output: command.output.and_then(map),
+                    output_end: command.output_end.and_then(map_end),
+                    exit_status: command.exit_status,
+                })
+            })
+            .collect();
+        self.images.remap(false, map);
+
+        // Reflow moves lines between the grid and the history.
+        self.lines_scrolled = self.lines_scrolled - history + self.scrollback.len();
+        self.cols = cols;
+        self.rows = rows;
+        self.cursor.row = self.cursor.row.min(rows - 1);
+        self.cursor.col = self.cursor.col.min(cols - 1);
+        self.cursor.pending_wrap = false;
+        self.scroll_top = 0;
+        self.scroll_bottom = rows - 1;
+        let old_stops = self.tab_stops.len();
+        self.tab_stops.resize(cols, false);
+        for (col, stop) in self.tab_stops.iter_mut().enumerate().skip(old_stops) {
+            *stop = col % 8 == 0;
+        }
+        self.display_offset = 0;
+        self.damaged = vec![true; rows];
+    }
+
+    /// Takes the replies queued for the child since the last call.
+    pub fn take_responses(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.responses)
+    }
+
+    pub(super) fn respond(&mut self, bytes: &[u8]) {
+        self.responses.extend_from_slice(bytes);
+    }
+
+    /// CPR (`CSI 6 n`) or DECXCPR (`CSI ? 6 n`): reports the 1-based cursor
+    /// position, relative to the scroll region in origin mode.
+    pub(super) fn report_cursor_position(&mut self, private: bool) {
+        let top = if self.modes.origin { self.scroll_top } else { 0 };
+        let row = self.cursor.row.saturating_sub(top) + 1;
+        let reply = format!("\x1b[{}{};{}R", if private { "?" } else { "" }, row, self.cursor.col + 1);
+        self.respond(reply.as_bytes());
+    }
+
+    /// Replaces the palette, along with the one OSC 104 and 110–112 restore.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette.clone();
+        self.default_palette = palette;
+        self.damaged.fill(true);
+    }
+
+    /// OSC 4 and 10–12: changes one color of the palette.
+    pub(super) fn set_color(&mut self, slot: ColorSlot, color: Rgb) {
+        self.palette.set_color(slot, color);
+        self.damaged.fill(true);
+    }
+
+    /// OSC 104 and 110–112: puts a color back to its configured value.
+    pub(super) fn reset_color(&mut self, slot: ColorSlot) {
+        let color = self.default_palette.color(slot);
+        self.set_color(slot, color);
+    }
+
+    /// Takes the OSC 52 requests made since the last call.
+    pub fn take_clipboard_requests(&mut self) -> Vec<ClipboardRequest> {
+        std::mem::take(&mut self.clipboard_requests)
+    }
+
+    pub(super) fn request_clipboard(&mut self, request: ClipboardRequest) {
+        self.clipboard_requests.push(request);
+    }
+
+    /// Shell integration marks still within reach, oldest first; none on the
+    /// alternate screen.
+    pub fn commands(&self) -> &[CommandMark] {
+        if self.alternate_active {
+            return &[];
+        }
+        let first = self.first_line();
+        let start = self.commands.partition_point(|command| command.prompt < first);
+        &self.commands[start..]
+    }
+
+    /// Absolute line of the cursor on the primary screen.
+    fn cursor_line(&self) -> Option<usize> {
+        (!self.alternate_active).then_some(self.lines_scrolled + self.cursor.row)
+    }
+
+    /// OSC 133 A: a prompt starts at the cursor.
+    pub(super) fn mark_prompt(&mut self) {
+        let Some(line) = self.cursor_line() else { return };
+        // Forget commands whose prompt left the history, and any the prompt
+        // is being redrawn over.
+        let first = self.first_line();
+        self.commands.retain(|command| command.prompt >= first && command.prompt < line);
+        self.commands.push(CommandMark::new(line));
+    }
+
+    /// OSC 133 C: the command's output starts at the cursor.
+    pub(super) fn mark_output(&mut self) {
+        let Some(line) = self.cursor_line() else { return };
+        if let Some(command) = self.commands.last_mut() {
+            command.output = Some(line);
+        }
+    }
+
+    /// OSC 133 D: the command finished. Output ends on the line before the
+    /// cursor, or on the cursor's line if it did not end with a newline.
+    pub(super) fn mark_command_end(&mut self, exit_status: Option<i32>) {
+        let Some(line) = self.cursor_line() else { return };
+        let end = if self.cursor.col > 0 { Some(line) } else { line.checked_sub(1) };
+        if let Some(command) = self.commands.last_mut()
+            && command.output.is_some()
+            && command.output_end.is_none()
+        {
+            command.output_end = end;
+            command.exit_status = exit_status;
+        }
+    }
+
+    /// The URI of OSC 8 link `link` (from `Attrs::link`).
+    pub fn hyperlink(&self, link: u32) -> Option<&str> {
+        self.hyperlinks.uri(link)
+    }
+
+    /// OSC 8: starts printing a hyperlink to `uri`, or ends it if `uri` is
+    /// empty. When the link table is full, links no cell uses any more are
+    /// dropped; if that frees nothing the text is printed without a link.
+    pub fn set_hyperlink(&mut self, id: Option<&str>, uri: &str) {
+        if uri.is_empty() {
+            self.cursor.attrs.link = 0;
+            return;
+        }
+        if self.hyperlinks.is_full() {
+            let mut used: HashSet<u32> = self
+                .grid
+                .iter()
+                .chain(&self.inactive_grid)
+                .flat_map(|row| row.cells.iter().map(|cell| cell.attrs.link))
+                .collect();
+            used.extend(self.scrollback.iter().flat_map(|line| line.attrs().map(|attrs| attrs.link)));
+            self.hyperlinks.retain(&used);
+        }
+        self.cursor.attrs.link = self.hyperlinks.insert(id, uri).unwrap_or(0);
+    }
+
+    /// The local directory the shell last reported, if any.
+    pub fn working_directory(&self) -> Option<&Path> {
+        self.working_directory.as_deref()
+    }
+
+    /// OSC 7; `None` when the shell reported a directory on another host.
+    pub(super) fn set_working_directory(&mut self, directory: Option<PathBuf>) {
+        self.working_directory = directory;
+    }
+
+    /// The window title set by OSC 0 or 2; empty until the application sets one.
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// OSC 0 sets both the title and the icon name, OSC 1 only the icon name
+    /// and OSC 2 only the title.
+    pub fn set_title(&mut self, title: Option<&str>, icon_name: Option<&str>) {
+        if let Some(title) = title {
+            self.title = title.to_string();
+        }
+        if let Some(icon_name) = icon_name {
+            self.icon_name = icon_name.to_string();
+        }
+    }
+
+    /// XTWINOPS 22: saves the title (`which` 0 or 2) and the icon name (0 or 1).
+    /// Pushing more than the stack holds drops the oldest entry.
+    pub fn push_title(&mut self, which: u16) {
+        if self.title_stack.len() >= TITLE_STACK_LIMIT {
+            self.title_stack.remove(0);
+        }
+        let title = matches!(which, 0 | 2).then(|| self.title.clone());
+        let icon_name = matches!(which, 0 | 1).then(|| self.icon_name.clone());
+        self.title_stack.push((title, icon_name));
+    }
+
+    /// XTWINOPS 23: restores what the matching push saved, as far as `which`
+    /// asks for it.
+    pub fn pop_title(&mut self, which: u16) {
+        let Some((title, icon_name)) = self.title_stack.pop() else { return };
+        let title = title.filter(|_| matches!(which, 0 | 2));
+        let icon_name = icon_name.filter(|_| matches!(which, 0 | 1));
+        self.set_title(title.as_deref(), icon_name.as_deref());
+    }
+
+    pub fn images(&self) -> &Images {
+        &self.images
+    }
+
+    /// Caps the pixel memory kept for images, dropping the oldest to fit.
+    pub fn set_image_memory_limit(&mut self, limit: usize) {
+        self.images.set_limit(limit);
+    }
+
+    /// Tells the screen how many pixels a cell covers.
+    pub fn set_cell_pixel_size(&mut self, width: u32, height: u32) {
+        self.cell_pixel_size = (width.max(1), height.max(1));
+    }
+
+    /// Image placements on the active screen that reach into the viewport.
+    pub fn visible_placements(&self) -> impl Iterator<Item = &Placement> {
+        let top = self.viewport_line(0);
+        let bottom = top + self.rows - 1;
+        self.images.placements().iter().filter(move |placement| {
+            placement.alternate == self.alternate_active && placement.line <= bottom && placement.last_line() >= top
+        })
+    }
+
+    /// The virtual placement Unicode placeholders for the kitty image
+    /// `image_id` draw. Picking among several takes an underline color,
+    /// which cells do not keep, so this is the image's newest.
+    pub fn virtual_placement(&self, image_id: u32) -> Option<&VirtualPlacement> {
+        let id = self.kitty.image(image_id)?;
+        self.images.virtual_placements().iter().rev().find(|placement| placement.image == id)
+    }
+
+    /// Forgets placements that scrolled out of the history.
+    fn prune_images(&mut self) {
+        let first = self.lines_scrolled - self.scrollback.len();
+        self.images.retain(|placement| placement.alternate || placement.last_line() >= first);
+    }
+
+    /// Stores `image` and places it at the cursor, drawn `width` × `height`
+    /// pixels. Returns the rows and columns it covers, or `None` if it is
+    /// over the memory limit.
+    fn place_image(&mut self, image: Image, width: u32, height: u32) -> Option<(usize, usize)> {
+        let (cell_width, cell_height) = self.cell_pixel_size;
+        let source = (0, 0, image.width, image.height);
+        let rows = height.div_ceil(cell_height) as usize;
+        let cols = width.div_ceil(cell_width) as usize;
+        // Before adding more, so the new image is not taken for unplaced.
+        self.prune_images();
+        let id = self.images.insert(image)?;
+        self.images.place(Placement {
+            image: id,
+            id: 0,
+            line: self.lines_scrolled + self.cursor.row,
+            col: self.cursor.col,
+            offset: (0, 0),
+            source,
+            width,
+            height,
+            rows,
+            cols,
+            z: 0,
+            alternate: self.alternate_active,
+        });
+        Some((rows, cols))
+    }
+
+    /// Moves the cursor from the top-left cell of an image to the cell after
+    /// its bottom-right corner, scrolling as text would.
+    fn move_past_image(&mut self, rows: usize, cols: usize) {
+        let col = self.cursor.col;
+        for _ in 1..rows {
+            self.index();
+        }
+        self.cursor.col = (col + cols).min(self.cols - 1);
+        self.cursor.pending_wrap = false;
+    }
+
+    /// APC G: a kitty graphics command. A placement moves the cursor to the
+    /// cell after its bottom-right corner, scrolling as text would.
+    pub(super) fn kitty_graphics(&mut self, data: &[u8]) {
+        self.prune_images();
+        let context = kitty::Context {
+            line: self.lines_scrolled + self.cursor.row,
+            col: self.cursor.col,
+            top: self.lines_scrolled,
+            rows: self.rows,
+            alternate: self.alternate_active,
+            cell_size: self.cell_pixel_size,
+        };
+        if let Some((rows, cols)) = self.kitty.apply(data, &mut self.images, &context, &mut self.responses) {
+            self.move_past_image(rows, cols);
+        }
+    }
+
+    /// OSC 1337 File=: an iTerm2 inline image, shown at the cursor. The
+    /// cursor ends up after its bottom-right corner.
+    pub(super) fn iterm_file(&mut self, args: &[u8], payload: &[u8]) {
+        let args = iterm::Args::parse(args);
+        let Some(image) = args.image(payload) else { return };
+        let (cell_width, cell_height) = self.cell_pixel_size;
+        let grid = (cell_width * self.cols as u32, cell_height * self.rows as u32);
+        let (width, height) = args.size(image.width, image.height, self.cell_pixel_size, grid);
+        if let Some((rows, cols)) = self.place_image(image, width, height) {
+            self.move_past_image(rows, cols);
+        }
+    }
+
+    /// OSC 1337 MultipartFile=: starts a file sent in `FilePart=` chunks.
+    pub(super) fn begin_multipart_file(&mut self, args: &[u8]) {
+        self.multipart_file = Some((args.to_vec(), Vec::new()));
+    }
+
+    pub(super) fn multipart_file_part(&mut self, part: &[u8]) {
+        let limit = self.images.limit() / 3 * 4;
+        if let Some((_, payload)) = &mut self.multipart_file {
+            if payload.len() + part.len() > limit {
+                self.multipart_file = None;
+            } else {
+                payload.extend_from_slice(part);
+            }
+        }
+    }
+
+    /// OSC 1337 FileEnd: shows the file sent in parts.
+    pub(super) fn end_multipart_file(&mut self) {
+        if let Some((args, payload)) = self.multipart_file.take() {
+            self.iterm_file(&args, &payload);
+        }
+    }
+
+    /// DCS with final byte `action`: starts collecting the string.
+    pub(super) fn hook_dcs(&mut self, intermediates: &[u8], action: char) {
+        self.dcs = match (intermediates, action) {
+            ([], 'q') => Some(Dcs::Sixel(Box::new(sixel::Decoder::new(self.images.limit())))),
+            ([b'+'], 'q') => Some(Dcs::RequestCapabilities(Vec::new())),
+            _ => None,
+        };
+    }
+
+    pub(super) fn put_dcs(&mut self, byte: u8) {
+        match &mut self.dcs {
+            Some(Dcs::Sixel(decoder)) => decoder.put(byte),
+            Some(Dcs::RequestCapabilities(names)) if names.len() < terminfo::MAX_QUERY_LEN => names.push(byte),
+            _ => {}
+        }
+    }
+
+    /// End of the device control string: acts on it.
+    pub(super) fn unhook_dcs(&mut self) {
+        match self.dcs.take() {
+            Some(Dcs::Sixel(decoder)) => {
+                let Some(image) = decoder.finish() else { return };
+                // Sixel leaves the cursor below the image, in the column it
+                // started in.
+                let col = self.cursor.col;
+                let (width, height) = (image.width, image.height);
+                if let Some((rows, _)) = self.place_image(image, width, height) {
+                    for _ in 0..rows {
+                        self.index();
+                    }
+                    self.cursor.col = col;
+                }
+            }
+            Some(Dcs::RequestCapabilities(names)) => self.respond(&terminfo::reply(&names)),
+            None => {}
+        }
+    }
+
+    /// Removes images starting on grid rows `rows` of the active screen.
+    fn clear_images(&mut self, rows: Range<usize>) {
+        if !rows.is_empty() {
+            let lines = self.lines_scrolled + rows.start..=self.lines_scrolled + rows.end - 1;
+            self.images.clear(self.alternate_active, lines);
+        }
+    }
+
+    /// The active kitty keyboard protocol flags.
+    pub fn keyboard_flags(&self) -> u8 {
+        self.keyboard_flags.last().copied().unwrap_or(0)
+    }
+
+    pub fn push_keyboard_flags(&mut self, flags: u8) {
+        if self.keyboard_flags.len() >= KEYBOARD_FLAGS_STACK_LIMIT {
+            self.keyboard_flags.remove(0);
+        }
+        self.keyboard_flags.push(flags);
+    }
+
+    /// Pops `count` entries; popping everything turns the protocol off.
+    pub fn pop_keyboard_flags(&mut self, count: usize) {
+        let len = self.keyboard_flags.len();
+        self.keyboard_flags.truncate(len.saturating_sub(count));
+    }
+
+    /// `CSI = flags ; mode u`: 1 replaces the flags, 2 adds to them and 3
+    /// removes them.
+    pub fn set_keyboard_flags(&mut self, flags: u8, mode: u16) {
+        let current = self.keyboard_flags();
+        let flags = match mode {
+            1 => flags,
+            2 => current | flags,
+            3 => current & !flags,
+            _ => return,
+        };
+        match self.keyboard_flags.last_mut() {
+            Some(top) => *top = flags,
+            None => self.keyboard_flags.push(flags),
+        }
+    }
+
+    /// Returns which rows changed since the last call and clears the record.
+    pub fn take_damage(&mut self) -> Vec<bool> {
+        std::mem::replace(&mut self.damaged, vec![false; self.rows])
+    }
+
+    fn damage(&mut self, rows: Range<usize>) {
+        // While scrolled back every grid row sits at a shifted position, and
+        // this is rare enough that redrawing everything is simpler.
+        let rows = if self.display_offset > 0 { 0..self.rows } else { rows };
+        for damaged in &mut self.damaged[rows] {
+            *damaged = true;
+        }
+    }
+
+    fn blank(&self) -> Cell {
+        Cell::blank(self.cursor.attrs)
+    }
+
+    pub fn print(&mut self, ch: char) {
+        let ch = self.charsets[self.active_charset].map(ch);
+        let width = ch.width().unwrap_or(1).min(self.cols);
+
+        if let Some((row, col)) = self.previous_cluster()
+            && joins_cluster(&self.grid[row].cells[col], ch, width)
+        {
+            self.grid[row].cells[col].push_extra(ch);
+            self.damage(row..row + 1);
+            return;
+        }
+        if width == 0 {
+            // A combining mark with nothing before it to combine with.
+            return;
+        }
+
+        if self.cursor.pending_wrap {
+            self.wrap_line();
+        }
+        if width == 2 && self.cursor.col + 1 >= self.cols {
+            if self.modes.autowrap {
+                // Wide characters never straddle two lines; pad the last column.
+                let mut leader = self.blank();
+                leader.attrs.flags.insert(Flags::LEADING_SPACER);
+                let (row, col) = (self.cursor.row, self.cursor.col);
+                self.clear_wide_char(row, col);
+                self.grid[row].cells[col] = leader;
+                self.wrap_line();
+            } else {
+                self.cursor.col = self.cols - 2;
+            }
+        }
+
+        let row = self.cursor.row;
+        let col = self.cursor.col;
+        if self.modes.insert {
+            self.split_wide_char(row, col);
+            self.split_wide_char(row, self.cols - width);
+            let blank = self.blank();
+            let cells = &mut self.grid[row].cells;
+            for _ in 0..width {
+                cells.insert(col, blank.clone());
+            }
+            cells.truncate(self.cols);
+        }
+        for offset in 0..width {
+            self.clear_wide_char(row, col + offset);
+        }
+
+        let mut attrs = self.cursor.attrs;
+        if width == 2 {
+            let mut spacer = attrs;
+            spacer.flags.insert(Flags::WIDE_SPACER);
+            self.grid[row].cells[col + 1] = Cell::new(' ', spacer);
+            attrs.flags.insert(Flags::WIDE);
+        }
+        self.grid[row].cells[col] = Cell::new(ch, attrs);
+        self.last_printed = Some(ch);
+        self.damage(row..row + 1);
+
+        if col + width >= self.cols {
+            self.cursor.col = self.cols - 1;
+            self.cursor.pending_wrap = self.modes.autowrap;
+        } else {
+            self.cursor.col += width;
+        }
+    }
+
+    /// Moves to the start of the next line, marking the current one as
+    /// continuing there.
+    fn wrap_line(&mut self) {
+        self.grid[self.cursor.row].wrapped = true;
+        self.cursor.col = 0;
+        self.index();
+    }
+
+    /// The cell holding the grapheme cluster that was printed last, which a
+    /// following zero-width character extends.
+    fn previous_cluster(&self) -> Option<(usize, usize)> {
+        let row = self.cursor.row;
+        let mut col = if self.cursor.pending_wrap {
+            self.cursor.col
+        } else {
+            self.cursor.col.checked_sub(1)?
+        };
+        if self.grid[row].cells[col].attrs.flags.contains(Flags::WIDE_SPACER) {
+            col = col.checked_sub(1)?;
+        }
+        Some((row, col))
+    }
+
+    /// Blanks both halves of a wide character about to be partly overwritten.
+    fn clear_wide_char(&mut self, row: usize, col: usize) {
+        let cells = &mut self.grid[row].cells;
+        let flags = cells[col].attrs.flags;
+        let start = if flags.contains(Flags::WIDE_SPACER) && col > 0 {
+            col - 1
+        } else if flags.contains(Flags::WIDE) {
+            col
+        } else {
+            return;
+        };
+        for cell in cells.iter_mut().skip(start).take(2) {
+            cell.attrs.flags.remove(Flags::WIDE);
+            cell.attrs.flags.remove(Flags::WIDE_SPACER);
+            cell.ch = ' ';
+            cell.extra = None;
+        }
+    }
+
+    /// Blanks a wide character straddling the boundary just before `col`,
+    /// which an edit is about to pull apart.
+    fn split_wide_char(&mut self, row: usize, col: usize) {
+        if col < self.cols && self.grid[row].cells[col].attrs.flags.contains(Flags::WIDE_SPACER) {
+            self.clear_wide_char(row, col);
+        }
+    }
+
+    /// REP: repeats the last printed character.
+    pub fn repeat_last(&mut self, count: usize) {
+        if let Some(ch) = self.last_printed {
+            for _ in 0..count.min(self.cols * self.rows) {
+                self.print(ch);
+            }
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        self.cursor.pending_wrap = false;
+        self.cursor.col = self.cursor.col.saturating_sub(1);
+    }
+
+    pub fn carriage_return(&mut self) {
+        self.cursor.pending_wrap = false;
+        self.cursor.col = 0;
+    }
+
+    /// LF, VT and FF; honours LNM.
+    pub fn linefeed(&mut self) {
+        if self.modes.linefeed_newline {
+            self.cursor.col = 0;
+        }
+        self.index();
+    }
+
+    /// IND: moves down one line, scrolling at the bottom margin.
+    pub fn index(&mut self) {
+        self.cursor.pending_wrap = false;
+        if self.cursor.row == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.cursor.row + 1 < self.rows {
+            self.cursor.row += 1;
+        }
+    }
+
+    /// RI: moves up one line, scrolling at the top margin.
+    pub fn reverse_index(&mut self) {
+        self.cursor.pending_wrap = false;
+        if self.cursor.row == self.scroll_top {
+            self.scroll_down(1);
+        } else if self.cursor.row > 0 {
+            self.cursor.row -= 1;
+        }
+    }
+
+    /// NEL: carriage return plus index.
+    pub fn next_line(&mut self) {
+        self.cursor.col = 0;
+        self.index();
+    }
+
+    /// Scrolls the scroll region up by `count` lines.
+    pub fn scroll_up(&mut self, count: usize) {
+        let count = count.min(self.scroll_bottom - self.scroll_top + 1);
+        let blank = self.blank();
+        // Only lines leaving the top of the primary screen become history;
+        // images move with them through their line numbers. Elsewhere they
+        // are moved along with the region.
+        let history = self.scroll_top == 0 && !self.alternate_active;
+        if !history {
+            self.scroll_images(-(count as isize));
+        }
+        for _ in 0..count {
+            let row = self.grid.remove(self.scroll_top);
+            if history {
+                self.lines_scrolled += 1;
+                let evicted = self.scrollback.push(&row);
+                // Keep a scrolled-back viewport looking at the same lines.
+                if self.display_offset > 0 && !evicted {
+                    self.display_offset += 1;
+                }
+            }
+            self.grid.insert(self.scroll_bottom, Row::new(self.cols, blank.clone()));
+        }
+        self.damage(self.scroll_top..self.scroll_bottom + 1);
+    }
+
+    /// Scrolls the scroll region down by `count` lines.
+    pub fn scroll_down(&mut self, count: usize) {
+        let count = count.min(self.scroll_bottom - self.scroll_top + 1);
+        let blank = self.blank();
+        self.scroll_images(count as isize);
+        for _ in 0..count {
+            self.grid.remove(self.scroll_bottom);
+            self.grid.insert(self.scroll_top, Row::new(self.cols, blank.clone()));
+        }
+        self.damage(self.scroll_top..self.scroll_bottom + 1);
+    }
+
+    fn scroll_images(&mut self, delta: isize) {
+        if !self.images.placements().is_empty() {
+            let region = self.lines_scrolled + self.scroll_top..=self.lines_scrolled + self.scroll_bottom;
+            self.images.scroll(self.alternate_active, region, delta);
+        }
+    }
+
+    pub fn tab(&mut self, count: usize) {
+        for _ in 0..count {
+            let next = (self.cursor.col + 1..self.cols).find(|&col| self.tab_stops[col]);
+            self.cursor.col = next.unwrap_or(self.cols - 1);
+        }
+    }
+
+    pub fn back_tab(&mut self, count: usize) {
+        for _ in 0..count {
+            let previous = (0..self.cursor.col).rev().find(|&col| self.tab_stops[col]);
+            self.cursor.col = previous.unwrap_or(0);
+        }
+        self.cursor.pending_wrap = false;
+    }
+
+    /// HTS: sets a tab stop at the cursor column.
+    pub fn set_tab_stop(&mut self) {
+        self.tab_stops[self.cursor.col] = true;
+    }
+
+    /// TBC: 0 clears the stop at the cursor, 3 clears all stops.
+    pub fn clear_tab_stops(&mut self, mode: u16) {
+        match mode {
+            0 => self.tab_stops[self.cursor.col] = false,
+            3 => self.tab_stops.iter_mut().for_each(|stop| *stop = false),
+            _ => {}
+        }
+    }
+
+    /// CUP: moves to a 0-based position, relative to the margins in origin mode.
+    pub fn goto(&mut self, row: usize, col: usize) {
+        let (top, bottom) = if self.modes.origin {
+            (self.scroll_top, self.scroll_bottom)
+        } else {
+            (0, self.rows - 1)
+        };
+        self.cursor.row = (top + row).min(bottom);
+        self.cursor.col = col.min(self.cols - 1);
+        self.cursor.pending_wrap = false;
+    }
+
+    pub fn goto_row(&mut self, row: usize) {
+        let col = self.cursor.col;
+        self.goto(row, col);
+    }
+
+    pub fn goto_col(&mut self, col: usize) {
+        self.cursor.col = col.min(self.cols - 1);
+        self.cursor.pending_wrap = false;
+    }
+
+    /// CUU: stops at the top margin when starting inside the scroll region.
+    pub fn move_up(&mut self, count: usize) {
+        let limit = if self.cursor.row >= self.scroll_top { self.scroll_top } else { 0 };
+        self.cursor.row = self.cursor.row.saturating_sub(count).max(limit);
+        self.cursor.pending_wrap = false;
+    }
+
+    /// CUD: stops at the bottom margin when starting inside the scroll region.
+    pub fn move_down(&mut self, count: usize) {
+        let limit = if self.cursor.row <= self.scroll_bottom { self.scroll_bottom } else { self.rows - 1 };
+        self.cursor.row = (self.cursor.row + count).min(limit);
+        self.cursor.pending_wrap = false;
+    }
+
+    pub fn move_forward(&mut self, count: usize) {
+        self.cursor.col = (self.cursor.col + count).min(self.cols - 1);
+        self.cursor.pending_wrap = false;
+    }
+
+    pub fn move_backward(&mut self, count: usize) {
+        self.cursor.col = self.cursor.col.saturating_sub(count);
+        self.cursor.pending_wrap = false;
+    }
+
+    /// DECSTBM: sets the inclusive, 0-based scroll region and homes the cursor.
+    pub fn set_scroll_region(&mut self, top: usize, bottom: usize) {
+        let bottom = bottom.min(self.rows - 1);
+        if top >= bottom {
+            return;
+        }
+        self.scroll_top = top;
+        self.scroll_bottom = bottom;
+        self.goto(0, 0);
+    }
+
+    /// ED: 0 erases below, 1 above, 2 the whole screen and 3 the scrollback.
+    pub fn erase_in_display(&mut self, mode: u16) {
+        let blank = self.blank();
+        let row = self.cursor.row;
+        match mode {
+            0 => {
+                self.erase_in_line(0);
+                for line in &mut self.grid[row + 1..] {
+                    *line = Row::new(self.cols, blank.clone());
+                }
+                self.clear_images(row + 1..self.rows);
+                self.damage(row..self.rows);
+            }
+            1 => {
+                self.erase_in_line(1);
+                for line in &mut self.grid[..row] {
+                    *line = Row::new(self.cols, blank.clone());
+                }
+                self.clear_images(0..row);
+                self.damage(0..row + 1);
+            }
+            2 => {
+                for line in &mut self.grid {
+                    *line = Row::new(self.cols, blank.clone());
+                }
+                self.clear_images(0..self.rows);
+                self.damage(0..self.rows);
+            }
+            3 => {
+                let history = self.lines_scrolled;
+                self.images.retain(|placement| placement.alternate || placement.line >= history);
+                self.scrollback.clear();
+                self.display_offset = 0;
+                self.damage(0..self.rows);
+            }
+            _ => {}
+        }
+        self.cursor.pending_wrap = false;
+    }
+
+    /// EL: 0 erases to the right of the cursor, 1 to the left, 2 the whole line.
+    pub fn erase_in_line(&mut self, mode: u16) {
+        let blank = self.blank();
+        let (row, col) = (self.cursor.row, self.cursor.col);
+        let range = match mode {
+            0 => col..self.cols,
+            1 => 0..col + 1,
+            2 => 0..self.cols,
+            _ => return,
+        };
+        self.split_wide_char(row, range.start);
+        self.split_wide_char(row, range.end);
+        let line = &mut self.grid[row];
+        line.cells[range].fill(blank);
+        if mode != 1 {
+            line.wrapped = false;
+        }
+        self.cursor.pending_wrap = false;
+        self.damage(self.cursor.row..self.cursor.row + 1);
+    }
+
+    /// ECH: blanks `count` cells starting at the cursor.
+    pub fn erase_chars(&mut self, count: usize) {
+        let blank = self.blank();
+        let col = self.cursor.col;
+        let end = (col + count).min(self.cols);
+        self.split_wide_char(self.cursor.row, col);
+        self.split_wide_char(self.cursor.row, end);
+        self.grid[self.cursor.row].cells[col..end].fill(blank);
+        self.cursor.pending_wrap = false;
+        self.damage(self.cursor.row..self.cursor.row + 1);
+    }
+
+    /// ICH: inserts blank cells at the cursor, shifting the rest right.
+    pub fn insert_chars(&mut self, count: usize) {
+        let blank = self.blank();
+        let col = self.cursor.col;
+        let count = count.min(self.cols - col);
+        self.split_wide_char(self.cursor.row, col);
+        self.split_wide_char(self.cursor.row, self.cols - count);
+        let cells = &mut self.grid[self.cursor.row].cells;
+        cells[col..].rotate_right(count);
+        cells[col..col + count].fill(blank);
+        self.cursor.pending_wrap = false;
+        self.damage(self.cursor.row..self.cursor.row + 1);
+    }
+
+    /// DCH: deletes cells at the cursor, shifting the rest left.
+    pub fn delete_chars(&mut self, count: usize) {
+        let blank = self.blank();
+        let col = self.cursor.col;
+        let count = count.min(self.cols - col);
+        self.split_wide_char(self.cursor.row, col);
+        self.split_wide_char(self.cursor.row, col + count);
+        let cells = &mut self.grid[self.cursor.row].cells;
+        cells[col..].rotate_left(count);
+        let len = cells.len();
+        cells[len - count..].fill(blank);
+        self.cursor.pending_wrap = false;
+        self.damage(self.cursor.row..self.cursor.row + 1);
+    }
+
+    /// IL: inserts blank lines at the cursor inside the scroll region.
+    pub fn insert_lines(&mut self, count: usize) {
+        let row = self.cursor.row;
+        if row < self.scroll_top || row > self.scroll_bottom {
+            return;
+        }
+        let count = count.min(self.scroll_bottom - row + 1);
+        let blank = self.blank();
+        for _ in 0..count {
+            self.grid.remove(self.scroll_bottom);
+            self.grid.insert(row, Row::new(self.cols, blank.clone()));
+        }
+        self.damage(row..self.scroll_bottom + 1);
+        self.cursor.col = 0;
+        self.cursor.pending_wrap = false;
+    }
+
+    /// DL: deletes lines at the cursor inside the scroll region.
+    pub fn delete_lines(&mut self, count: usize) {
+        let row = self.cursor.row;
+        if row < self.scroll_top || row > self.scroll_bottom {
+            return;
+        }
+        let count = count.min(self.scroll_bottom - row + 1);
+        let blank = self.blank();
+        for _ in 0..count {
+            self.grid.remove(row);
+            self.grid.insert(self.scroll_bottom, Row::new(self.cols, blank.clone()));
+        }
+        self.damage(row..self.scroll_bottom + 1);
+        self.cursor.col = 0;
+        self.cursor.pending_wrap = false;
+    }
+
+    /// DECALN: fills the screen with `E` for alignment tests.
+    pub fn alignment_test(&mut self) {
+        for line in &mut self.grid {
+            *line = Row::new(self.cols, Cell::new('E', Attrs::default()));
+        }
+        self.damage(0..self.rows);
+        self.scroll_top = 0;
+        self.scroll_bottom = self.rows - 1;
+        self.goto(0, 0);
+    }
+
+    /// DECSC: saves the cursor, pen, origin mode and character sets.
+    pub fn save_cursor(&mut self) {
+        self.saved_cursor = Some(SavedCursor {
+            cursor: self.cursor,
+            origin: self.modes.origin,
+            charsets: self.charsets,
+            active_charset: self.active_charset,
+        });
+    }
+
+    /// DECRC: restores what DECSC saved, or homes the cursor if nothing was saved.
+    pub fn restore_cursor(&mut self) {
+        match self.saved_cursor {
+            Some(saved) => {
+                self.cursor = saved.cursor;
+                self.cursor.row = self.cursor.row.min(self.rows - 1);
+                self.cursor.col = self.cursor.col.min(self.cols - 1);
+                self.modes.origin = saved.origin;
+                self.charsets = saved.charsets;
+                self.active_charset = saved.active_charset;
+            }
+            None => {
+                self.modes.origin = false;
+                self.cursor.attrs = Attrs::default();
+                self.goto(0, 0);
+            }
+        }
+    }
+
+    pub fn is_alternate_screen(&self) -> bool {
+        self.alternate_active
+    }
+
+    /// Switches to the alternate screen (DECSET 47/1047/1049), optionally
+    /// saving the primary cursor first and clearing the alternate grid.
+    pub fn enter_alternate_screen(&mut self, save_cursor: bool, clear: bool) {
+        if self.alternate_active {
+            return;
+        }
+        if save_cursor {
+            self.save_cursor();
+        }
+        self.swap_grids();
+        if clear {
+            for line in &mut self.grid {
+                *line = Row::new(self.cols, Cell::default());
+            }
+            self.images.retain(|placement| !placement.alternate);
+        }
+    }
+
+    /// Returns to the primary screen, whose contents and scrollback were left
+    /// untouched, optionally clearing the alternate grid and restoring the
+    /// cursor saved on entry.
+    pub fn leave_alternate_screen(&mut self, restore_cursor: bool, clear: bool) {
+        if !self.alternate_active {
+            return;
+        }
+        if clear {
+            for line in &mut self.grid {
+                *line = Row::new(self.cols, Cell::default());
+            }
+            self.images.retain(|placement| !placement.alternate);
+        }
+        self.swap_grids();
+        if restore_cursor {
+            self.restore_cursor();
+        }
+    }
+
+    fn swap_grids(&mut self) {
+        std::mem::swap(&mut self.grid, &mut self.inactive_grid);
+        std::mem::swap(&mut self.saved_cursor, &mut self.inactive_saved_cursor);
+        std::mem::swap(&mut self.keyboard_flags, &mut self.inactive_keyboard_flags);
+        self.alternate_active = !self.alternate_active;
+        self.display_offset = 0;
+        self.cursor.pending_wrap = false;
+        self.damaged.fill(true);
+    }
+
+    pub fn designate_charset(&mut self, slot: usize, charset: Charset) {
+        self.charsets[slot] = charset;
+    }
+
+    /// SO/SI: invokes G1 or G0 into GL.
+    pub fn shift_charset(&mut self, slot: usize) {
+        self.active_charset = slot;
+    }
+}
+
+/// Whether `ch` continues the grapheme cluster in `cell` rather than starting
+/// a new one: zero-width characters (combining marks, variation selectors,
+/// ZWJ), anything after a ZWJ, emoji skin tone modifiers and the second half of
+/// a regional indicator flag pair. The cell keeps the width of its base
+/// character, which is what programs measuring with wcwidth expect.
+fn joins_cluster(cell: &Cell, ch: char, width: usize) -> bool {
+    const ZWJ: char = '\u{200d}';
+    let regional_indicator = |ch: char| ('\u{1f1e6}'..='\u{1f1ff}').contains(&ch);
+
+    if cell.is_spacer() {
+        return false;
+    }
+    width == 0
+        || cell.last_char() == ZWJ
+        || ('\u{1f3fb}'..='\u{1f3ff}').contains(&ch)
+        || (regional_indicator(ch) && regional_indicator(cell.ch) && cell.extra.is_none())
+}
+
+fn default_tab_stops(cols: usize) -> Vec<bool> {
+    (0..cols).map(|col| col % 8 == 0 && col != 0).collect()
+}
+
+fn is_blank(row: &Row) -> bool {
+    !row.wrapped && row.cells.iter().all(|cell| *cell == Cell::default())
+}
+
+/// Cuts or pads every row to `cols` cells and the grid to `rows` rows.
+fn crop(grid: &mut Vec<Row>, cols: usize, rows: usize) {
+    for row in grid.iter_mut() {
+        row.cells.resize(cols, Cell::default());
+    }
+    grid.resize_with(rows, || Row::new(cols, Cell::default()));
+}
+
+/// Rewraps the history plus `grid` (`old_cols` wide) at `cols` columns and
+/// returns the new `rows`-high grid, where `cursor` ended up and, for every
+/// old row (history first), where its first cell ended up counted from the
+/// oldest line kept; `None` if that line was pushed out of the history.
+/// Lines that no longer fit on screen go back into `scrollback`.
+fn reflow(
+    scrollback: &mut Scrollback,
+    grid: &[Row],
+    old_cols: usize,
+    cols: usize,
+    rows: usize,
+    cursor: (usize, usize),
+) -> (Vec<Row>, (usize, usize), Vec<Option<usize>>) {
+    let history = scrollback.take_all();
+    let history_rows = history.len();
+    let old_rows = history.iter().map(|line| line.to_row(old_cols)).chain(grid.iter().cloned());
+
+    // Join soft-wrapped rows back into logical lines, remembering which line
+    // holds the cursor and how far into it the cursor sits.
+    let mut lines: Vec<Vec<Cell>> = Vec::new();
+    let mut current: Vec<Cell> = Vec::new();
+    let mut cursor_at = (0, 0);
+    // `(logical line, offset)` of the first cell of every old row.
+    let mut row_origins: Vec<(usize, usize)> = Vec::new();
+    for (index, row) in old_rows.enumerate() {
+        if index == history_rows + cursor.0 {
+            cursor_at = (lines.len(), current.len() + cursor.1);
+        }
+        row_origins.push((lines.len(), current.len()));
+        current.extend_from_slice(&row.cells);
+        if !row.wrapped {
+            let len = current.iter().rposition(|cell| *cell != Cell::default()).map_or(0, |last| last + 1);
+            current.truncate(len);
+            lines.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+
+    let mut rewrapped: Vec<Row> = Vec::new();
+    let mut new_cursor = (0, 0);
+    let mut row_map = vec![0; row_origins.len()];
+    let mut origins = row_origins.iter().enumerate().peekable();
+    for (index, cells) in lines.into_iter().enumerate() {
+        let cursor_offset = (index == cursor_at.0).then_some(cursor_at.1);
+        let len = cells.len();
+        let mut row = Row::new(cols, Cell::default());
+        let mut col = 0;
+        let next_row = |row: &mut Row, rewrapped: &mut Vec<Row>| {
+            row.wrapped = true;
+            rewrapped.push(std::mem::replace(row, Row::new(cols, Cell::default())));
+        };
+        for (offset, cell) in cells.into_iter().enumerate() {
+            if cell.attrs.flags.contains(Flags::LEADING_SPACER) {
+                continue;
+            }
+            let wide = cell.attrs.flags.contains(Flags::WIDE) && cols > 1;
+            if col == cols || (wide && col + 1 == cols) {
+                if col < cols {
+                    row.cells[col].attrs.flags.insert(Flags::LEADING_SPACER);
+                }
+                next_row(&mut row, &mut rewrapped);
+                col = 0;
+            }
+            if cursor_offset == Some(offset) {
+                new_cursor = (rewrapped.len(), col);
+            }
+            while let Some((row, _)) = origins.next_if(|(_, origin)| **origin <= (index, offset)) {
+                row_map[row] = rewrapped.len();
+            }
+            row.cells[col] = cell;
+            col += 1;
+        }
+        // The cursor may sit past the end of the line's content.
+        if let Some(offset) = cursor_offset
+            && offset >= len
+        {
+            let mut target = col + (offset - len);
+            while target >= cols {
+                next_row(&mut row, &mut rewrapped);
+                target -= cols;
+            }
+            new_cursor = (rewrapped.len(), target);
+        }
+        // Rows that only held trailing blanks.
+        while let Some((row, _)) = origins.next_if(|(_, origin)| origin.0 == index) {
+            row_map[row] = rewrapped.len();
+        }
+        rewrapped.push(row);
+    }
+
+    // Blank lines below the cursor would only push content into history.
+    while rewrapped.len() > new_cursor.0 + 1 && rewrapped.last().is_some_and(is_blank) {
+        rewrapped.pop();
+    }
+
+    let top = rewrapped.len().saturating_sub(rows).min(new_cursor.0);
+    let mut new_grid = rewrapped.split_off(top);
+    new_grid.resize_with(rows, || Row::new(cols, Cell::default()));
+    for row in &rewrapped {
+        scrollback.push(row);
+    }
+    let evicted = top - scrollback.len();
+    let row_map = row_map.into_iter().map(|row| row.checked_sub(evicted)).collect();
+    (new_grid, (new_cursor.0 - top, new_cursor.1), row_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::Terminal;
+    use crate::terminal::cell::Color;
+
+    /// The text of grid row `row`, without spacers or trailing blanks.
+    fn text(screen: &Screen, row: usize) -> String {
+        let mut text = String::new();
+        for cell in screen.row(row).cells.iter().filter(|cell| !cell.is_spacer()) {
+            cell.push_to(&mut text);
+        }
+        text.trim_end().to_string()
+    }
+
+    fn lines(screen: &Screen) -> Vec<String> {
+        (0..screen.rows()).map(|row| text(screen, row)).collect()
+    }
+
+    fn cursor(screen: &Screen) -> (usize, usize) {
+        (screen.cursor().row, screen.cursor().col)
+    }
+
+    #[test]
+    fn wraps_at_the_right_margin() {
+        let terminal = Terminal::with_output(5, 3, "abcdefg");
+        let screen = terminal.screen();
+        assert_eq!(lines(screen), ["abcde", "fg", ""]);
+        assert!(screen.row(0).wrapped);
+        assert_eq!(cursor(screen), (1, 2));
+    }
+
+    #[test]
+    fn defers_the_wrap_until_the_next_character() {
+        let terminal = Terminal::with_output(5, 3, "abcde");
+        let screen = terminal.screen();
+        assert_eq!(cursor(screen), (0, 4));
+        assert!(screen.cursor().pending_wrap);
+        // A carriage return cancels the pending wrap.
+        let terminal = Terminal::with_output(5, 3, "abcde\rX");
+        assert_eq!(lines(terminal.screen()), ["Xbcde", "", ""]);
+    }
+
+    #[test]
+    fn overwrites_the_last_column_without_autowrap() {
+        let terminal = Terminal::with_output(5, 2, "\x1b[?7labcdefg");
+        let screen = terminal.screen();
+        assert!(!screen.modes.autowrap);
+        assert_eq!(lines(screen), ["abcdg", ""]);
+    }
+
+    #[test]
+    fn scrolls_into_history_at_the_bottom() {
+        let terminal = Terminal::with_output(5, 3, "1\r\n2\r\n3\r\n4");
+        let screen = terminal.screen();
+        assert_eq!(lines(screen), ["2", "3", "4"]);
+        assert_eq!(screen.history_len(), 1);
+    }
+
+    #[test]
+    fn scrolls_only_inside_the_scroll_region() {
+        let terminal = Terminal::with_output(5, 5, "1\r\n2\r\n3\r\n4\r\n5\x1b[2;4r\x1b[4;1H\nX");
+        let screen = terminal.screen();
+        assert_eq!(lines(screen), ["1", "3", "4", "X", "5"]);
+        assert_eq!(screen.history_len(), 0);
+        assert_eq!(cursor(screen), (3, 1));
+    }
+
+    #[test]
+    fn reverse_index_scrolls_down_at_the_top_margin() {
+        let terminal = Terminal::with_output(5, 4, "1\r\n2\r\n3\r\n4\x1b[2;3r\x1b[2;1H\x1bM");
+        assert_eq!(lines(terminal.screen()), ["1", "", "2", "4"]);
+    }
+
+    #[test]
+    fn origin_mode_addresses_relative_to_the_region() {
+        let terminal = Terminal::with_output(5, 5, "\x1b[2;4r\x1b[?6h\x1b[1;1HX\x1b[9;1HY");
+        let screen = terminal.screen();
+        assert_eq!(lines(screen), ["", "X", "", "Y", ""]);
+    }
+
+    #[test]
+    fn erases_in_display() {
+        let fill = "aaa\r\nbbb\r\nccc\x1b[2;2H";
+        assert_eq!(lines(Terminal::with_output(3, 3, &format!("{}\x1b[J", fill)).screen()), ["aaa", "b", ""]);
+        assert_eq!(lines(Terminal::with_output(3, 3, &format!("{}\x1b[1J", fill)).screen()), ["", "  b", "ccc"]);
+        assert_eq!(lines(Terminal::with_output(3, 3, &format!("{}\x1b[2J", fill)).screen()), ["", "", ""]);
+    }
+
+    #[test]
+    fn erases_in_line() {
+        let fill = "abcde\x1b[1;3H";
+        assert_eq!(text(Terminal::with_output(5, 1, &format!("{}\x1b[K", fill)).screen(), 0), "ab");
+        assert_eq!(text(Terminal::with_output(5, 1, &format!("{}\x1b[1K", fill)).screen(), 0), "   de");
+        assert_eq!(text(Terminal::with_output(5, 1, &format!("{}\x1b[2K", fill)).screen(), 0), "");
+    }
+
+    #[test]
+    fn erase_keeps_the_background_color() {
+        let terminal = Terminal::with_output(3, 1, "\x1b[41m\x1b[2K");
+        let cell = &terminal.screen().row(0).cells[1];
+        assert_eq!(cell.attrs.bg, Color::Indexed(1));
+    }
+
+    #[test]
+    fn inserts_and_deletes_lines_inside_the_region() {
+        let fill = "1\r\n2\r\n3\r\n4\x1b[1;3r\x1b[2;1H";
+        assert_eq!(lines(Terminal::with_output(3, 4, &format!("{}\x1b[L", fill)).screen()), ["1", "", "2", "4"]);
+        assert_eq!(lines(Terminal::with_output(3, 4, &format!("{}\x1b[M", fill)).screen()), ["1", "3", "", "4"]);
+        // Outside the region they do nothing.
+        let inserted = Terminal::with_output(3, 4, &format!("{}\x1b[4;1H\x1b[L", fill));
+        assert_eq!(lines(inserted.screen()), ["1", "2", "3", "4"]);
+    }
+
+    #[test]
+    fn inserts_and_deletes_characters() {
+        assert_eq!(text(Terminal::with_output(5, 1, "abcde\x1b[1;2H\x1b[2@").screen(), 0), "a  bc");
+        assert_eq!(text(Terminal::with_output(5, 1, "abcde\x1b[1;2H\x1b[2P").screen(), 0), "ade");
+        assert_eq!(text(Terminal::with_output(5, 1, "abcde\x1b[1;2H\x1b[2X").screen(), 0), "a  de");
+        // IRM shifts the rest of the line on print.
+        assert_eq!(text(Terminal::with_output(5, 1, "abc\x1b[1;1H\x1b[4hX").screen(), 0), "Xabc");
+    }
+
+    #[test]
+    fn applies_sgr_attributes() {
+        let terminal = Terminal::with_output(8, 1, "\x1b[1;3;4mA\x1b[22mB\x1b[0mC");
+        let cells = &terminal.screen().row(0).cells;
+        let flags = cells[0].attrs.flags;
+        assert!(flags.contains(Flags::BOLD) && flags.contains(Flags::ITALIC) && flags.contains(Flags::UNDERLINE));
+        assert!(!cells[1].attrs.flags.contains(Flags::BOLD));
+        assert!(cells[1].attrs.flags.contains(Flags::ITALIC));
+        assert_eq!(cells[2].attrs, Attrs::default());
+    }
+
+    #[test]
+    fn applies_sgr_colors() {
+        let output = "\x1b[31;102mA\x1b[38;5;200mB\x1b[38:2::1:2:3mC\x1b[48;2;4;5;6mD\x1b[39;49mE";
+        let terminal = Terminal::with_output(8, 1, output);
+        let cells = &terminal.screen().row(0).cells;
+        assert_eq!((cells[0].attrs.fg, cells[0].attrs.bg), (Color::Indexed(1), Color::Indexed(10)));
+        assert_eq!(cells[1].attrs.fg, Color::Indexed(200));
+        assert_eq!(cells[2].attrs.fg, Color::Rgb(Rgb::new(1, 2, 3)));
+        assert_eq!(cells[3].attrs.bg, Color::Rgb(Rgb::new(4, 5, 6)));
+        assert_eq!((cells[4].attrs.fg, cells[4].attrs.bg), (Color::Default, Color::Default));
+    }
+
+    #[test]
+    fn tracks_modes() {
+        let terminal = Terminal::with_output(5, 1, "\x1b[?1h\x1b[?25l\x1b[?2004h\x1b=");
+        let modes = &terminal.screen().modes;
+        assert!(modes.application_cursor && !modes.cursor_visible && modes.bracketed_paste && modes.application_keypad);
+        let terminal = Terminal::with_output(5, 1, "\x1b[?1h\x1b[?2004h\x1bc");
+        let modes = &terminal.screen().modes;
+        assert!(!modes.application_cursor && !modes.bracketed_paste && modes.cursor_visible);
+    }
+
+    #[test]
+    fn alternate_screen_leaves_the_primary_grid_and_history_alone() {
+        let mut terminal = Terminal::with_output(5, 2, "1\r\n2\r\n3");
+        terminal.feed(b"\x1b[?1049hvim\r\n~\r\n~");
+        let screen = terminal.screen();
+        assert!(screen.is_alternate_screen());
+        assert_eq!(lines(screen), ["~", "~"]);
+        assert_eq!(screen.history_len(), 0);
+
+        terminal.feed(b"\x1b[?1049l");
+        let screen = terminal.screen();
+        assert!(!screen.is_alternate_screen());
+        assert_eq!(lines(screen), ["2", "3"]);
+        // Scrolling on the alternate screen did not add history.
+        assert_eq!(screen.history_len(), 1);
+        assert_eq!(cursor(screen), (1, 1));
+    }
+
+    #[test]
+    fn mode_1049_clears_the_alternate_screen_on_entry() {
+        let mut terminal = Terminal::with_output(5, 2, "\x1b[?47hold\x1b[?47l");
+        terminal.feed(b"\x1b[?47h");
+        assert_eq!(lines(terminal.screen()), ["old", ""]);
+        terminal.feed(b"\x1b[?47l\x1b[?1049h");
+        assert_eq!(lines(terminal.screen()), ["", ""]);
+    }
+
+    #[test]
+    fn reflow_round_trips_soft_wrapped_lines() {
+        let mut terminal = Terminal::with_output(6, 2, "abcdefgh");
+        terminal.screen_mut().resize(3, 2);
+        let screen = terminal.screen();
+        assert_eq!(lines(screen), ["def", "gh"]);
+        assert_eq!(screen.history_len(), 1);
+        assert_eq!(cursor(screen), (1, 2));
+
+        terminal.screen_mut().resize(6, 2);
+        let screen = terminal.screen();
+        assert_eq!(lines(screen), ["abcdef", "gh"]);
+        assert_eq!(screen.history_len(), 0);
+        assert!(screen.row(0).wrapped);
+        assert_eq!(cursor(screen), (1, 2));
+    }
+
+    #[test]
+    fn reflow_keeps_hard_line_breaks() {
+        let mut terminal = Terminal::with_output(4, 3, "ab\r\ncd");
+        terminal.screen_mut().resize(10, 3);
+        assert_eq!(lines(terminal.screen()), ["ab", "cd", ""]);
+        assert_eq!(cursor(terminal.screen()), (1, 2));
+
+        terminal.screen_mut().resize(1, 3);
+        terminal.screen_mut().resize(4, 3);
+        assert_eq!(lines(terminal.screen()), ["ab", "cd", ""]);
+        assert_eq!(cursor(terminal.screen()), (1, 2));
+    }
+
+    #[test]
+    fn reflow_moves_the_cursor_with_its_text() {
+        let mut terminal = Terminal::with_output(8, 3, "abcdef\x1b[1;3H");
+        terminal.screen_mut().resize(2, 3);
+        let screen = terminal.screen();
+        assert_eq!(lines(screen), ["ab", "cd", "ef"]);
+        assert_eq!(cursor(screen), (1, 0));
+
+        terminal.screen_mut().resize(8, 3);
+        assert_eq!(cursor(terminal.screen()), (0, 2));
+    }
+
+    /// Asserts every wide character in `row` still has its spacer and every
+    /// spacer its wide character.
+    fn assert_wide_pairs(screen: &Screen, row: usize) {
+        let cells = &screen.row(row).cells;
+        for (col, cell) in cells.iter().enumerate() {
+            if cell.attrs.flags.contains(Flags::WIDE) {
+                assert!(cells[col + 1].attrs.flags.contains(Flags::WIDE_SPACER), "orphaned wide cell at {}", col);
+            }
+            if cell.attrs.flags.contains(Flags::WIDE_SPACER) {
+                assert!(cells[col - 1].attrs.flags.contains(Flags::WIDE), "orphaned spacer at {}", col);
+            }
+        }
+    }
+
+    #[test]
+    fn erase_in_line_clears_split_wide_characters() {
+        let to_end = Terminal::with_output(6, 1, "中文\x1b[1;2H\x1b[K");
+        assert_eq!(lines(to_end.screen()), [""]);
+        assert_wide_pairs(to_end.screen(), 0);
+
+        let terminal = Terminal::with_output(6, 1, "中文\x1b[1;2H\x1b[1K");
+        assert_eq!(lines(terminal.screen()), ["  文"]);
+        assert_wide_pairs(terminal.screen(), 0);
+    }
+
+    #[test]
+    fn erase_chars_clears_split_wide_characters() {
+        let terminal = Terminal::with_output(6, 1, "中文字\x1b[1;2H\x1b[2X");
+        assert_eq!(lines(terminal.screen()), ["    字"]);
+        assert_wide_pairs(terminal.screen(), 0);
+    }
+
+    #[test]
+    fn insert_chars_clears_split_wide_characters() {
+        let split = Terminal::with_output(5, 1, "中文\x1b[1;2H\x1b[@");
+        assert_eq!(lines(split.screen()), ["   文"]);
+        assert_wide_pairs(split.screen(), 0);
+
+        // The spacer of the last wide character is pushed off the line.
+        let terminal = Terminal::with_output(3, 1, "a中\x1b[1;1H\x1b[@");
+        assert_eq!(lines(terminal.screen()), [" a"]);
+        assert_wide_pairs(terminal.screen(), 0);
+    }
+
+    #[test]
+    fn delete_chars_clears_split_wide_characters() {
+        let terminal = Terminal::with_output(6, 1, "中文字\x1b[1;2H\x1b[2P");
+        assert_eq!(lines(terminal.screen()), ["  字"]);
+        assert_wide_pairs(terminal.screen(), 0);
+    }
+
+    #[test]
+    fn insert_mode_clears_split_wide_characters() {
+        let terminal = Terminal::with_output(5, 1, "中文\x1b[1;2H\x1b[4hx");
+        assert_eq!(lines(terminal.screen()), [" x 文"]);
+        assert_wide_pairs(terminal.screen(), 0);
+    }
+
+    #[test]
+    fn saves_and_restores_the_cursor() {
+        let terminal = Terminal::with_output(5, 3, "\x1b[2;3H\x1b[1m\x1b7\x1b[H\x1b[0m\x1b8X");
+        let screen = terminal.screen();
+        assert_eq!(cursor(screen), (1, 3));
+        assert!(screen.row(1).cells[2].attrs.flags.contains(Flags::BOLD));
+    }
+}
@@ -0,0 +1,216 @@
+//! Lines that scrolled off the top of the screen.
+//!
+//! Stored lines drop trailing blank cells and keep their attributes
+//! run-length encoded, since most output shares one rendition for long
+//! stretches. The buffer is a ring: once the configured limit is reached the
+//! oldest line is discarded for every new one.
+
+use std::collections::VecDeque;
+
+use super::cell::{Attrs, Cell, Row};
+
+/// A compacted line of history.
+#[derive(Clone, Debug)]
+pub struct Line {
+    chars: Box<[char]>,
+    /// Combining characters of the few cells that have any, by column.
+    extras: Box<[(usize, Box<str>)]>,
+    /// `(length, attrs)` runs covering `chars` in order.
+    runs: Box<[(u16, Attrs)]>,
+    pub wrapped: bool,
+}
+
+impl Line {
+    pub fn from_row(row: &Row) -> Self {
+        let len = row
+            .cells
+            .iter()
+            .rposition(|cell| *cell != Cell::default())
+            .map_or(0, |last| last + 1);
+        let cells = &row.cells[..len];
+
+        let mut runs: Vec<(u16, Attrs)> = Vec::new();
+        for cell in cells {
+            match runs.last_mut() {
+                Some((count, attrs)) if *attrs == cell.attrs && *count < u16::MAX => *count += 1,
+                _ => runs.push((1, cell.attrs)),
+            }
+        }
+
+        Self {
+            chars: cells.iter().map(|cell| cell.ch).collect(),
+            extras: cells
+                .iter()
+                .enumerate()
+                .filter_map(|(col, cell)| Some((col, cell.extra.clone()?)))
+                .collect(),
+            runs: runs.into_boxed_slice(),
+            wrapped: row.wrapped,
+        }
+    }
+
+    /// The stored cells, without the trimmed trailing blanks.
+    pub fn cells(&self) -> Vec<Cell> {
+        let attrs = self
+            .runs
+            .iter()
+            .flat_map(|&(count, attrs)| std::iter::repeat_n(attrs, usize::from(count)));
+        let mut cells: Vec<Cell> = self.chars.iter().zip(attrs).map(|(&ch, attrs)| Cell::new(ch, attrs)).collect();
+        for (col, extra) in &self.extras {
+            cells[*col].extra = Some(extra.clone());
+        }
+        cells
+    }
+
+    /// The distinct runs of attributes on the line.
+    pub fn attrs(&self) -> impl Iterator<Item = Attrs> + '_ {
+        self.runs.iter().map(|&(_, attrs)| attrs)
+    }
+
+    /// Expands the line back into a row of `cols` cells.
+    pub fn to_row(&self, cols: usize) -> Row {
+        let mut row = Row::new(cols, Cell::default());
+        for (cell, stored) in row.cells.iter_mut().zip(self.cells()) {
+            *cell = stored;
+        }
+        row.wrapped = self.wrapped;
+        row
+    }
+}
+
+pub struct Scrollback {
+    lines: VecDeque<Line>,
+    /// Maximum number of lines kept; `None` keeps everything.
+    limit: Option<usize>,
+}
+
+impl Scrollback {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self { lines: VecDeque::new(), limit }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Returns the line `index` lines above the bottom of the history
+    /// (0 is the most recent line).
+    pub fn get_from_bottom(&self, index: usize) -> Option<&Line> {
+        let len = self.lines.len();
+        index.checked_add(1).and_then(|offset| len.checked_sub(offset)).and_then(|index| self.lines.get(index))
+    }
+
+    /// Appends a line, returning `true` if the oldest line was evicted to make room.
+    pub fn push(&mut self, row: &Row) -> bool {
+        if self.limit == Some(0) {
+            return true;
+        }
+        self.lines.push_back(Line::from_row(row));
+        match self.limit {
+            Some(limit) if self.lines.len() > limit => {
+                self.lines.pop_front();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    /// Every stored line, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Line> {
+        self.lines.iter()
+    }
+
+    /// Removes and returns every line, oldest first.
+    pub fn take_all(&mut self) -> Vec<Line> {
+        self.lines.drain(..).collect()
+    }
+
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+        if let Some(limit) = limit {
+            let excess = self.lines.len().saturating_sub(limit);
+            self.lines.drain(..excess);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::cell::{Color, Flags};
+
+    fn row(text: &str, cols: usize) -> Row {
+        let mut row = Row::new(cols, Cell::default());
+        for (cell, ch) in row.cells.iter_mut().zip(text.chars()) {
+            cell.ch = ch;
+        }
+        row
+    }
+
+    fn text(line: &Line) -> String {
+        line.cells().iter().map(|cell| cell.ch).collect()
+    }
+
+    #[test]
+    fn evicts_the_oldest_line_past_the_limit() {
+        let mut scrollback = Scrollback::new(Some(2));
+        assert!(!scrollback.push(&row("a", 4)));
+        assert!(!scrollback.push(&row("b", 4)));
+        assert!(scrollback.push(&row("c", 4)));
+        assert_eq!(scrollback.len(), 2);
+        assert_eq!(text(scrollback.get_from_bottom(0).unwrap()), "c");
+        assert_eq!(text(scrollback.get_from_bottom(1).unwrap()), "b");
+        assert!(scrollback.get_from_bottom(2).is_none());
+    }
+
+    #[test]
+    fn keeps_nothing_with_a_zero_limit() {
+        let mut scrollback = Scrollback::new(Some(0));
+        assert!(scrollback.push(&row("a", 4)));
+        assert_eq!(scrollback.len(), 0);
+    }
+
+    #[test]
+    fn keeps_everything_without_a_limit() {
+        let mut scrollback = Scrollback::new(None);
+        for _ in 0..1000 {
+            scrollback.push(&row("x", 4));
+        }
+        assert_eq!(scrollback.len(), 1000);
+    }
+
+    #[test]
+    fn lowering_the_limit_drops_the_oldest_lines() {
+        let mut scrollback = Scrollback::new(None);
+        for text in ["a", "b", "c"] {
+            scrollback.push(&row(text, 4));
+        }
+        scrollback.set_limit(Some(1));
+        let kept: Vec<String> = scrollback.iter().map(text).collect();
+        assert_eq!(kept, ["c"]);
+    }
+
+    #[test]
+    fn round_trips_cells_attributes_and_clusters() {
+        let mut original = row("ab c", 8);
+        original.cells[1].attrs.flags.insert(Flags::BOLD);
+        original.cells[3].attrs.fg = Color::Indexed(2);
+        original.cells[3].push_extra('\u{301}');
+        original.wrapped = true;
+
+        let line = Line::from_row(&original);
+        // Trailing blanks are trimmed and attributes stored as runs.
+        assert_eq!(line.cells().len(), 4);
+        assert_eq!(line.attrs().count(), 4);
+
+        let restored = line.to_row(8);
+        assert!(restored.wrapped);
+        assert_eq!(restored.cells, original.cells);
+        // Restoring into a narrower row truncates.
+        assert_eq!(line.to_row(2).cells.len(), 2);
+    }
+}
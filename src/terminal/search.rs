@@ -0,0 +1,176 @@
+//! Searching the scrollback and the screen.
+//!
+//! Soft-wrapped rows are joined back into the lines the program printed
+//! before matching, so a match can span a wrap. Matches are absolute points,
+//! like selections, and stay on their text while output scrolls.
+
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+use super::cell::Flags;
+use super::screen::Screen;
+use super::selection::Point;
+
+/// A match; `end` is the last cell it covers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchMatch {
+    pub start: Point,
+    pub end: Point,
+}
+
+/// One grapheme cluster of a `LogicalLine`.
+struct Cluster {
+    /// Byte offset of the cluster in the line's text.
+    offset: usize,
+    point: Point,
+    width: usize,
+    link: u32,
+}
+
+impl Cluster {
+    /// The right-most cell the cluster covers.
+    fn last_cell(&self) -> Point {
+        Point { col: self.point.col + self.width - 1, ..self.point }
+    }
+}
+
+/// Rows joined across soft wraps, with the cells each character came from.
+pub(super) struct LogicalLine {
+    pub text: String,
+    clusters: Vec<Cluster>,
+}
+
+impl LogicalLine {
+    /// Reads the logical line that starts at absolute line `line`, returning
+    /// it together with the line after it. Trailing blanks are dropped.
+    pub fn read(screen: &Screen, mut line: usize) -> (Self, usize) {
+        let last = screen.last_line();
+        let mut text = String::new();
+        let mut clusters = Vec::new();
+        while let Some(row) = screen.absolute_row(line) {
+            for (col, cell) in row.cells.iter().enumerate() {
+                if cell.is_spacer() {
+                    continue;
+                }
+                clusters.push(Cluster {
+                    offset: text.len(),
+                    point: Point { line, col },
+                    width: if cell.attrs.flags.contains(Flags::WIDE) { 2 } else { 1 },
+                    link: cell.attrs.link,
+                });
+                cell.push_to(&mut text);
+            }
+            line += 1;
+            if !row.wrapped || line > last {
+                break;
+            }
+        }
+        text.truncate(text.trim_end_matches(' ').len());
+        (Self { text, clusters }, line)
+    }
+
+    /// The first and last cell of a non-empty byte range of `text`.
+    pub fn cells(&self, range: Range<usize>) -> (Point, Point) {
+        let first = self.clusters.partition_point(|cluster| cluster.offset <= range.start) - 1;
+        let last = self.clusters.partition_point(|cluster| cluster.offset < range.end) - 1;
+        (self.clusters[first].point, self.clusters[last].last_cell())
+    }
+
+    /// The cells of the run of hyperlink `link` that covers `point`.
+    pub fn link_cells(&self, point: Point, link: u32) -> Option<(Point, Point)> {
+        let index = self
+            .clusters
+            .iter()
+            .position(|cluster| cluster.point <= point && point <= cluster.last_cell())?;
+        let first = self.clusters[..index].iter().rposition(|cluster| cluster.link != link).map_or(0, |before| before + 1);
+        let last = self.clusters[index..]
+            .iter()
+            .position(|cluster| cluster.link != link)
+            .map_or(self.clusters.len(), |after| index + after)
+            - 1;
+        Some((self.clusters[first].point, self.clusters[last].last_cell()))
+    }
+}
+
+/// Compiles a search bar pattern. Plain patterns match literally.
+pub fn compile(pattern: &str, regex: bool, match_case: bool) -> Result<Regex, regex::Error> {
+    let pattern = if regex { pattern.to_string() } else { regex::escape(pattern) };
+    RegexBuilder::new(&pattern).case_insensitive(!match_case).build()
+}
+
+/// Every non-empty match of `regex` from the oldest history line to the
+/// bottom of the screen, in order.
+pub fn find_all(screen: &Screen, regex: &Regex) -> Vec<SearchMatch> {
+    let mut matches = Vec::new();
+    let mut line = screen.first_line();
+    while line <= screen.last_line() {
+        let (logical, next) = LogicalLine::read(screen, line);
+        line = next.max(line + 1);
+        for found in regex.find_iter(&logical.text) {
+            if !found.is_empty() {
+                let (start, end) = logical.cells(found.range());
+                matches.push(SearchMatch { start, end });
+            }
+        }
+    }
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::Terminal;
+
+    fn found(start: (usize, usize), end: (usize, usize)) -> SearchMatch {
+        SearchMatch { start: Point { line: start.0, col: start.1 }, end: Point { line: end.0, col: end.1 } }
+    }
+
+    #[test]
+    fn plain_patterns_match_literally() {
+        let terminal = Terminal::with_output(20, 2, "a.c abc A.C");
+        let screen = terminal.screen();
+        let regex = compile("a.c", false, false).unwrap();
+        assert_eq!(find_all(screen, &regex), [found((0, 0), (0, 2)), found((0, 8), (0, 10))]);
+        let regex = compile("a.c", false, true).unwrap();
+        assert_eq!(find_all(screen, &regex), [found((0, 0), (0, 2))]);
+    }
+
+    #[test]
+    fn regex_patterns() {
+        let terminal = Terminal::with_output(20, 2, "a.c abc\r\nerror: 42");
+        let regex = compile(r"a.c", true, true).unwrap();
+        assert_eq!(find_all(terminal.screen(), &regex), [found((0, 0), (0, 2)), found((0, 4), (0, 6))]);
+        let regex = compile(r"\d+$", true, true).unwrap();
+        assert_eq!(find_all(terminal.screen(), &regex), [found((1, 7), (1, 8))]);
+        assert!(compile("(", true, true).is_err());
+        // Empty matches are skipped.
+        assert_eq!(find_all(terminal.screen(), &compile("x*", true, true).unwrap()), []);
+    }
+
+    #[test]
+    fn matches_span_soft_wraps() {
+        let wrapped = Terminal::with_output(4, 3, "foobarbaz");
+        let regex = compile("barb", false, true).unwrap();
+        assert_eq!(find_all(wrapped.screen(), &regex), [found((0, 3), (1, 2))]);
+        // A hard line break is not a wrap.
+        let unwrapped = Terminal::with_output(4, 3, "foo\r\nbar");
+        assert_eq!(find_all(unwrapped.screen(), &compile("obar", false, true).unwrap()), []);
+    }
+
+    #[test]
+    fn matches_cover_wide_characters() {
+        let terminal = Terminal::with_output(10, 1, "a中文b");
+        let regex = compile("文b", false, true).unwrap();
+        assert_eq!(find_all(terminal.screen(), &regex), [found((0, 3), (0, 5))]);
+    }
+
+    #[test]
+    fn searches_the_history() {
+        let terminal = Terminal::with_output(10, 2, "needle\r\none\r\ntwo\r\nneedle");
+        let screen = terminal.screen();
+        assert_eq!(screen.first_line(), 0);
+        let regex = compile("needle", false, true).unwrap();
+        assert_eq!(find_all(screen, &regex), [found((0, 0), (0, 5)), found((3, 0), (3, 5))]);
+    }
+}
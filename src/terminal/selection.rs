@@ -0,0 +1,285 @@
+//! Mouse selection over the grid and the scrollback.
+//!
+//! Points are absolute line numbers (see `Screen::absolute_row`), so a
+//! selection keeps covering the same text while output scrolls it upwards.
+
+use std::ops::Range;
+
+use super::cell::{Cell, Flags};
+use super::screen::Screen;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Point {
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelectionKind {
+    /// Character by character, following the text across lines.
+    Simple,
+    /// Whole words, from a double click.
+    Word,
+    /// Whole lines, from a triple click.
+    Line,
+    /// A rectangle of columns, from an Alt-drag.
+    Block,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Selection {
+    pub kind: SelectionKind,
+    anchor: Point,
+    head: Point,
+}
+
+/// A selection with words and lines expanded, ready for drawing and copying.
+#[derive(Clone, Copy, Debug)]
+pub struct SelectedRange {
+    pub start: Point,
+    pub end: Point,
+    block: bool,
+}
+
+impl Selection {
+    pub fn new(kind: SelectionKind, point: Point) -> Self {
+        Self { kind, anchor: point, head: point }
+    }
+
+    /// Moves the end that follows the pointer.
+    pub fn extend(&mut self, point: Point) {
+        self.head = point;
+    }
+
+    /// A plain click selects nothing until the pointer moves to another cell.
+    pub fn is_empty(&self) -> bool {
+        matches!(self.kind, SelectionKind::Simple | SelectionKind::Block) && self.anchor == self.head
+    }
+
+    /// Expands the selection to word or line boundaries, treating characters
+    /// in `word_chars` as part of words in addition to letters and digits.
+    pub fn resolve(&self, screen: &Screen, word_chars: &str) -> SelectedRange {
+        let (start, end) = if self.anchor <= self.head {
+            (self.anchor, self.head)
+        } else {
+            (self.head, self.anchor)
+        };
+        let last_col = screen.cols() - 1;
+        let (start, end) = match self.kind {
+            SelectionKind::Simple => (start, end),
+            SelectionKind::Block => (
+                Point { line: start.line, col: self.anchor.col.min(self.head.col) },
+                Point { line: end.line, col: self.anchor.col.max(self.head.col) },
+            ),
+            SelectionKind::Word => (word_start(screen, start, word_chars), word_end(screen, end, word_chars)),
+            SelectionKind::Line => {
+                let mut first = start.line;
+                while first > screen.first_line() && is_wrapped(screen, first - 1) {
+                    first -= 1;
+                }
+                let mut last = end.line;
+                while is_wrapped(screen, last) {
+                    last += 1;
+                }
+                (Point { line: first, col: 0 }, Point { line: last, col: last_col })
+            }
+        };
+        SelectedRange {
+            start: snap_to_wide_char(screen, start),
+            end: Point { col: end.col.min(last_col), ..end },
+            block: self.kind == SelectionKind::Block,
+        }
+    }
+}
+
+impl SelectedRange {
+    /// The selected columns of absolute line `line`, if any.
+    pub fn columns(&self, line: usize, cols: usize) -> Option<Range<usize>> {
+        if line < self.start.line || line > self.end.line {
+            return None;
+        }
+        if self.block {
+            return Some(self.start.col..self.end.col + 1);
+        }
+        let first = if line == self.start.line { self.start.col } else { 0 };
+        let last = if line == self.end.line { self.end.col + 1 } else { cols };
+        (first < last).then_some(first..last)
+    }
+
+    /// The selected text. Soft-wrapped lines are joined back together;
+    /// other lines end with a newline and lose their trailing blanks.
+    pub fn text(&self, screen: &Screen) -> String {
+        let mut text = String::new();
+        for line in self.start.line..=self.end.line {
+            let Some(row) = screen.absolute_row(line) else {
+                text.push('\n');
+                continue;
+            };
+            let Some(columns) = self.columns(line, screen.cols()) else { continue };
+            let joins_next = row.wrapped && !self.block && line != self.end.line && columns.end == screen.cols();
+
+            let mut segment = String::new();
+            for cell in &row.cells[columns] {
+                if !cell.is_spacer() {
+                    cell.push_to(&mut segment);
+                }
+            }
+            if joins_next {
+                text.push_str(&segment);
+            } else {
+                text.push_str(segment.trim_end());
+                if line != self.end.line {
+                    text.push('\n');
+                }
+            }
+        }
+        text
+    }
+}
+
+fn is_wrapped(screen: &Screen, line: usize) -> bool {
+    screen.absolute_row(line).is_some_and(|row| row.wrapped)
+}
+
+fn cell_at(screen: &Screen, point: Point) -> Option<Cell> {
+    screen.absolute_row(point.line).and_then(|row| row.cells.get(point.col).cloned())
+}
+
+fn is_word_char(cell: &Cell, word_chars: &str) -> bool {
+    cell.is_spacer() || cell.ch.is_alphanumeric() || word_chars.contains(cell.ch)
+}
+
+/// Steps one cell back, continuing on the previous line if it wrapped.
+fn previous(screen: &Screen, point: Point) -> Option<Point> {
+    if point.col > 0 {
+        return Some(Point { col: point.col - 1, ..point });
+    }
+    let line = point.line.checked_sub(1).filter(|&line| line >= screen.first_line())?;
+    is_wrapped(screen, line).then_some(Point { line, col: screen.cols() - 1 })
+}
+
+/// Steps one cell forward, continuing on the next line if this one wrapped.
+fn next(screen: &Screen, point: Point) -> Option<Point> {
+    if point.col + 1 < screen.cols() {
+        return Some(Point { col: point.col + 1, ..point });
+    }
+    is_wrapped(screen, point.line).then_some(Point { line: point.line + 1, col: 0 })
+}
+
+fn word_start(screen: &Screen, mut point: Point, word_chars: &str) -> Point {
+    if !cell_at(screen, point).is_some_and(|cell| is_word_char(&cell, word_chars)) {
+        return point;
+    }
+    while let Some(before) = previous(screen, point)
+        && cell_at(screen, before).is_some_and(|cell| is_word_char(&cell, word_chars))
+    {
+        point = before;
+    }
+    point
+}
+
+fn word_end(screen: &Screen, mut point: Point, word_chars: &str) -> Point {
+    if !cell_at(screen, point).is_some_and(|cell| is_word_char(&cell, word_chars)) {
+        return point;
+    }
+    while let Some(after) = next(screen, point)
+        && cell_at(screen, after).is_some_and(|cell| is_word_char(&cell, word_chars))
+    {
+        point = after;
+    }
+    point
+}
+
+/// Moves a point on the right half of a wide character onto its left half.
+fn snap_to_wide_char(screen: &Screen, point: Point) -> Point {
+    match cell_at(screen, point) {
+        Some(cell) if point.col > 0 && cell.attrs.flags.contains(Flags::WIDE_SPACER) => {
+            Point { col: point.col - 1, ..point }
+        }
+        _ => point,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::Terminal;
+
+    fn point(line: usize, col: usize) -> Point {
+        Point { line, col }
+    }
+
+    fn select(screen: &Screen, kind: SelectionKind, from: Point, to: Point) -> String {
+        let mut selection = Selection::new(kind, from);
+        selection.extend(to);
+        selection.resolve(screen, "-_./").text(screen)
+    }
+
+    #[test]
+    fn a_click_selects_nothing() {
+        let mut selection = Selection::new(SelectionKind::Simple, point(0, 2));
+        assert!(selection.is_empty());
+        selection.extend(point(0, 3));
+        assert!(!selection.is_empty());
+        assert!(!Selection::new(SelectionKind::Word, point(0, 2)).is_empty());
+    }
+
+    #[test]
+    fn simple_selection_follows_the_text() {
+        let terminal = Terminal::with_output(10, 3, "hello   \r\nworld");
+        let screen = terminal.screen();
+        assert_eq!(select(screen, SelectionKind::Simple, point(0, 1), point(0, 3)), "ell");
+        // Either end can be the anchor; trailing blanks are dropped.
+        assert_eq!(select(screen, SelectionKind::Simple, point(1, 2), point(0, 3)), "lo\nwor");
+    }
+
+    #[test]
+    fn soft_wrapped_lines_are_joined() {
+        let terminal = Terminal::with_output(5, 3, "abcdefgh\r\nij");
+        let screen = terminal.screen();
+        assert_eq!(select(screen, SelectionKind::Simple, point(0, 0), point(2, 4)), "abcdefgh\nij");
+        assert_eq!(select(screen, SelectionKind::Line, point(1, 1), point(1, 1)), "abcdefgh");
+    }
+
+    #[test]
+    fn word_selection() {
+        let command = Terminal::with_output(20, 2, "cat ~/my-file.txt |");
+        let screen = command.screen();
+        assert_eq!(select(screen, SelectionKind::Word, point(0, 8), point(0, 8)), "/my-file.txt");
+        assert_eq!(select(screen, SelectionKind::Word, point(0, 1), point(0, 8)), "cat ~/my-file.txt");
+        // A word continues across a soft wrap.
+        let wrapped = Terminal::with_output(4, 2, "abcdef gh");
+        let screen = wrapped.screen();
+        assert_eq!(select(screen, SelectionKind::Word, point(1, 0), point(1, 0)), "abcdef");
+    }
+
+    #[test]
+    fn block_selection_takes_columns() {
+        let terminal = Terminal::with_output(10, 3, "abcdef\r\nghijkl\r\nmnopqr");
+        let screen = terminal.screen();
+        assert_eq!(select(screen, SelectionKind::Block, point(0, 3), point(2, 1)), "bcd\nhij\nnop");
+        let mut selection = Selection::new(SelectionKind::Block, point(0, 3));
+        selection.extend(point(2, 1));
+        let range = selection.resolve(screen, "");
+        assert_eq!(range.columns(1, 10), Some(1..4));
+        assert_eq!(range.columns(3, 10), None);
+    }
+
+    #[test]
+    fn wide_characters_are_selected_whole() {
+        let terminal = Terminal::with_output(10, 1, "a中文b");
+        let screen = terminal.screen();
+        assert_eq!(select(screen, SelectionKind::Simple, point(0, 2), point(0, 3)), "中文");
+    }
+
+    #[test]
+    fn selections_follow_scrolled_text() {
+        let mut terminal = Terminal::with_output(10, 2, "one\r\ntwo");
+        let mut selection = Selection::new(SelectionKind::Line, point(0, 0));
+        selection.extend(point(0, 0));
+        terminal.feed(b"\r\nthree\r\nfour");
+        let screen = terminal.screen();
+        assert_eq!(screen.first_line(), 0);
+        assert_eq!(selection.resolve(screen, "").text(screen), "one");
+    }
+}
@@ -0,0 +1,374 @@
+//! Sixel graphics: `DCS P1 ; P2 ; P3 q data ST`.
+//!
+//! Each data character from `?` to `~` sets a column of six pixels in the
+//! current color; `$` returns to the left edge and `-` moves down to the next
+//! band of six rows. `#` defines and selects color registers, which start out
+//! as the VT340 palette, `!` repeats the next character and `"` gives the
+//! image's size. Pixels are square whatever aspect ratio is asked for, and
+//! pixels no sixel sets stay transparent.
+
+use super::graphics::Image;
+
+/// Largest width or height accepted, in pixels.
+const MAX_DIMENSION: usize = 10_000;
+const REGISTERS: usize = 256;
+
+/// VT340 default colors, in percent.
+const VT340_COLORS: [(u32, u32, u32); 16] = [
+    (0, 0, 0),
+    (20, 20, 80),
+    (80, 13, 13),
+    (20, 80, 20),
+    (80, 20, 80),
+    (20, 80, 80),
+    (80, 80, 20),
+    (53, 53, 53),
+    (26, 26, 26),
+    (33, 33, 60),
+    (60, 26, 26),
+    (33, 60, 33),
+    (60, 33, 60),
+    (33, 60, 60),
+    (60, 60, 33),
+    (80, 80, 80),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Repeat,
+    Color,
+    Raster,
+}
+
+pub struct Decoder {
+    /// Opaque RGBA colors, packed as `u32` in memory order.
+    registers: [u32; REGISTERS],
+    color: u32,
+    /// Command whose numeric parameters are being read.
+    command: Option<Command>,
+    params: Vec<u32>,
+    repeat: usize,
+    x: usize,
+    /// Top row of the current band.
+    y: usize,
+    /// Pixels in rows of `stride`; 0 is transparent.
+    pixels: Vec<u32>,
+    stride: usize,
+    /// Extent of the pixels set so far, or of the raster size if larger.
+    width: usize,
+    height: usize,
+    /// Most bytes of RGBA the image may take; past it drawing stops.
+    max_bytes: usize,
+}
+
+impl Decoder {
+    pub fn new(max_bytes: usize) -> Self {
+        let mut registers = [pack(0, 0, 0); REGISTERS];
+        for (register, &(r, g, b)) in registers.iter_mut().zip(&VT340_COLORS) {
+            *register = pack(percent(r), percent(g), percent(b));
+        }
+        Self {
+            registers,
+            color: registers[0],
+            command: None,
+            params: Vec::new(),
+            repeat: 1,
+            x: 0,
+            y: 0,
+            pixels: Vec::new(),
+            stride: 0,
+            width: 0,
+            height: 0,
+            max_bytes,
+        }
+    }
+
+    pub fn put(&mut self, byte: u8) {
+        if self.command.is_some() {
+            match byte {
+                b'0'..=b'9' => {
+                    let param = self.params.last_mut().expect("a command always has a parameter");
+                    *param = param.saturating_mul(10).saturating_add(u32::from(byte - b'0'));
+                    return;
+                }
+                b';' => {
+                    self.params.push(0);
+                    return;
+                }
+                _ => self.finish_command(),
+            }
+        }
+
+        match byte {
+            b'?'..=b'~' => {
+                let bits = byte - b'?';
+                let count = std::mem::replace(&mut self.repeat, 1);
+                self.draw(bits, count);
+            }
+            b'!' => self.start_command(Command::Repeat),
+            b'#' => self.start_command(Command::Color),
+            b'"' => self.start_command(Command::Raster),
+            b'$' => self.x = 0,
+            b'-' => {
+                self.x = 0;
+                self.y += 6;
+            }
+            _ => {}
+        }
+    }
+
+    /// The decoded image, or `None` if nothing was drawn.
+    pub fn finish(mut self) -> Option<Image> {
+        if self.command.is_some() {
+            self.finish_command();
+        }
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+        let mut pixels = Vec::with_capacity(self.width * self.height * 4);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let pixel = if x < self.stride { self.pixels.get(y * self.stride + x).copied().unwrap_or(0) } else { 0 };
+                pixels.extend_from_slice(&pixel.to_ne_bytes());
+            }
+        }
+        Some(Image::rgba(self.width as u32, self.height as u32, pixels))
+    }
+
+    fn start_command(&mut self, command: Command) {
+        self.command = Some(command);
+        self.params.clear();
+        self.params.push(0);
+    }
+
+    fn finish_command(&mut self) {
+        let params = std::mem::take(&mut self.params);
+        match self.command.take() {
+            Some(Command::Repeat) => self.repeat = (params[0] as usize).max(1),
+            Some(Command::Color) => {
+                let register = params[0] as usize % REGISTERS;
+                if let [_, space, x, y, z] = params[..] {
+                    let color = match space {
+                        1 => hls(x, y, z),
+                        2 => pack(percent(x), percent(y), percent(z)),
+                        _ => self.registers[register],
+                    };
+                    self.registers[register] = color;
+                }
+                self.color = self.registers[register];
+            }
+            Some(Command::Raster) => {
+                if let [_, _, width, height] = params[..]
+                    && self.fits(width as usize, height as usize)
+                {
+                    self.width = self.width.max(width as usize);
+                    self.height = self.height.max(height as usize);
+                }
+            }
+            None => {}
+        }
+        self.params = params;
+    }
+
+    /// Whether an image of this size stays within the limits.
+    fn fits(&self, width: usize, height: usize) -> bool {
+        width <= MAX_DIMENSION && height <= MAX_DIMENSION && width * height * 4 <= self.max_bytes
+    }
+
+    /// Sets the pixels of `bits` in `count` columns from the cursor.
+    fn draw(&mut self, bits: u8, count: usize) {
+        let right = self.x.saturating_add(count);
+        if bits != 0 {
+            // One past the lowest pixel set; bit 0 is the top row.
+            let bottom = self.y + 8 - bits.leading_zeros() as usize;
+            if !self.grow(right, bottom) {
+                self.x = right;
+                return;
+            }
+            for bit in 0..6 {
+                if bits & (1 << bit) != 0 {
+                    let row = (self.y + bit) * self.stride;
+                    self.pixels[row + self.x..row + right].fill(self.color);
+                }
+            }
+            self.width = self.width.max(right);
+            self.height = self.height.max(bottom);
+        }
+        self.x = right;
+    }
+
+    /// Makes room for pixels up to `width` × `height`, unless that breaks
+    /// the limits.
+    fn grow(&mut self, width: usize, height: usize) -> bool {
+        let width = width.max(self.width);
+        let height = height.max(self.height);
+        if !self.fits(width, height) {
+            return false;
+        }
+        if width > self.stride {
+            // Leave room to the right so a band drawn left to right does not
+            // copy the image for every column.
+            let stride = width.max(self.stride * 2).min(MAX_DIMENSION);
+            let rows = self.pixels.len() / self.stride.max(1);
+            let mut pixels = vec![0; stride * rows];
+            for row in 0..rows {
+                pixels[row * stride..row * stride + self.stride]
+                    .copy_from_slice(&self.pixels[row * self.stride..(row + 1) * self.stride]);
+            }
+            self.pixels = pixels;
+            self.stride = stride;
+        }
+        if self.pixels.len() < self.stride * height {
+            self.pixels.resize(self.stride * height, 0);
+        }
+        true
+    }
+}
+
+fn percent(value: u32) -> u8 {
+    (value.min(100) * 255 / 100) as u8
+}
+
+fn pack(r: u8, g: u8, b: u8) -> u32 {
+    u32::from_ne_bytes([r, g, b, 0xff])
+}
+
+/// DEC HLS, where hue 0° is blue, 120° red and 240° green.
+fn hls(hue: u32, lightness: u32, saturation: u32) -> u32 {
+    let hue = ((hue % 360 + 240) % 360) as f32 / 60.0;
+    let lightness = lightness.min(100) as f32 / 100.0;
+    let saturation = saturation.min(100) as f32 / 100.0;
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let second = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, second, 0.0),
+        1 => (second, chroma, 0.0),
+        2 => (0.0, chroma, second),
+        3 => (0.0, second, chroma),
+        4 => (second, 0.0, chroma),
+        _ => (chroma, 0.0, second),
+    };
+    let offset = lightness - chroma / 2.0;
+    let channel = |value: f32| ((value + offset) * 255.0).round().clamp(0.0, 255.0) as u8;
+    pack(channel(r), channel(g), channel(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::Terminal;
+    use crate::terminal::graphics::ImageData;
+
+    const TRANSPARENT: [u8; 4] = [0, 0, 0, 0];
+
+    fn decode_with_limit(data: &str, max_bytes: usize) -> Option<Image> {
+        let mut decoder = Decoder::new(max_bytes);
+        for byte in data.bytes() {
+            decoder.put(byte);
+        }
+        decoder.finish()
+    }
+
+    fn decode(data: &str) -> Image {
+        decode_with_limit(data, 1 << 20).expect("an image")
+    }
+
+    fn pixel(image: &Image, x: u32, y: u32) -> [u8; 4] {
+        let ImageData::Rgba(pixels) = &image.data else { panic!("sixel images are RGBA") };
+        let at = ((y * image.width + x) * 4) as usize;
+        pixels[at..at + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn draws_columns_of_six_pixels() {
+        let image = decode("~");
+        assert_eq!((image.width, image.height), (1, 6));
+        assert!((0..6).all(|y| pixel(&image, 0, y) == [0, 0, 0, 255]));
+
+        // Bit 0 is the top pixel; pixels no sixel sets stay transparent.
+        let image = decode("A");
+        assert_eq!((image.width, image.height), (1, 2));
+        assert_eq!(pixel(&image, 0, 0), TRANSPARENT);
+        assert_eq!(pixel(&image, 0, 1), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn repeats() {
+        assert_eq!(decode("!5~").width, 5);
+        assert_eq!(decode("!0~").width, 1);
+        assert_eq!(decode("!3~~").width, 4);
+        // A repeat of blank sixels still moves along.
+        let image = decode("!4?~");
+        assert_eq!(image.width, 5);
+        assert_eq!(pixel(&image, 0, 0), TRANSPARENT);
+    }
+
+    #[test]
+    fn bands_and_carriage_returns() {
+        let image = decode("#2~-#3~");
+        assert_eq!((image.width, image.height), (1, 12));
+        assert_eq!(pixel(&image, 0, 0), [204, 33, 33, 255]);
+        assert_eq!(pixel(&image, 0, 6), [51, 204, 51, 255]);
+
+        // `$` draws over the same band again.
+        let image = decode("#2!2@$#3?@");
+        assert_eq!(pixel(&image, 0, 0), [204, 33, 33, 255]);
+        assert_eq!(pixel(&image, 1, 0), [51, 204, 51, 255]);
+    }
+
+    #[test]
+    fn color_registers() {
+        // Register 1 starts out as the VT340's blue.
+        assert_eq!(pixel(&decode("#1~"), 0, 0), [51, 51, 204, 255]);
+        assert_eq!(pixel(&decode("#5;2;100;50;0~"), 0, 0), [255, 127, 0, 255]);
+        // Percentages are capped.
+        assert_eq!(pixel(&decode("#5;2;200;0;0~"), 0, 0), [255, 0, 0, 255]);
+        // A register keeps its color when selected again.
+        assert_eq!(pixel(&decode("#5;2;0;0;100#0#5~"), 0, 0), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn hls_colors() {
+        assert_eq!(pixel(&decode("#1;1;0;50;100~"), 0, 0), [0, 0, 255, 255]);
+        assert_eq!(pixel(&decode("#1;1;120;50;100~"), 0, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(&decode("#1;1;240;50;100~"), 0, 0), [0, 255, 0, 255]);
+        assert_eq!(pixel(&decode("#1;1;0;100;0~"), 0, 0), [255, 255, 255, 255]);
+        assert_eq!(pixel(&decode("#1;1;0;50;0~"), 0, 0), [128, 128, 128, 255]);
+    }
+
+    #[test]
+    fn raster_attributes() {
+        let image = decode("\"1;1;10;12");
+        assert_eq!((image.width, image.height), (10, 12));
+        assert_eq!(pixel(&image, 9, 11), TRANSPARENT);
+
+        // Pixels drawn past the raster size still count.
+        let image = decode("\"1;1;2;2!3~");
+        assert_eq!((image.width, image.height), (3, 6));
+
+        // A raster size over the limit is ignored.
+        assert!(decode_with_limit("\"1;1;100;100", 1000).is_none());
+        assert!(decode_with_limit("\"1;1;100000;1", 1 << 30).is_none());
+    }
+
+    #[test]
+    fn stops_drawing_at_the_limit() {
+        assert!(decode_with_limit("!20~", 20 * 6 * 4 - 1).is_none());
+        assert_eq!(decode_with_limit("!5~!20~", 10 * 6 * 4).map(|image| image.width), Some(5));
+        assert!(decode_with_limit("", 1 << 20).is_none());
+    }
+
+    #[test]
+    fn places_the_image_and_moves_below_it() {
+        let mut terminal = Terminal::new(10, 5);
+        terminal.screen_mut().set_cell_pixel_size(8, 16);
+        terminal.feed(b"ab\x1bPq#1;2;0;100;0!16~-!16~\x1b\\x");
+        let screen = terminal.screen();
+        let placements = screen.images().placements();
+        assert_eq!(placements.len(), 1);
+        let placement = placements[0];
+        assert_eq!((placement.line, placement.col, placement.rows, placement.cols), (0, 2, 1, 2));
+        assert_eq!((placement.width, placement.height), (16, 12));
+        assert_eq!((screen.cursor().row, screen.cursor().col), (1, 3));
+    }
+}
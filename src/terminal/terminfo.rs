@@ -0,0 +1,233 @@
+//! XTGETTCAP: `DCS + q name ; name … ST`, with names in hex.
+//!
+//! Answers come from the terminfo entry shipped in `terminfo/`, built into
+//! the binary, so an application asking the terminal sees the same
+//! capabilities it would find in the installed entry. Each name gets its own
+//! reply: `DCS 1 + r name = value ST` with the value in hex (none for
+//! booleans), or `DCS 0 + r name ST` for capabilities the entry lacks. `TN`
+//! is the entry's name and `Co` the number of colors, as in xterm.
+
+const SOURCE: &str = include_str!("../../terminfo/macos-term.terminfo");
+
+/// Longest query accepted, in bytes of hex.
+pub const MAX_QUERY_LEN: usize = 4096;
+
+/// The entry's lines without comments: its names, then its capabilities.
+fn entry() -> (&'static str, impl Iterator<Item = &'static str>) {
+    let mut lines = SOURCE.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#'));
+    let names = lines.next().unwrap_or_default();
+    (names, lines)
+}
+
+/// The entry's primary name.
+fn entry_name() -> &'static str {
+    entry().0.split('|').next().unwrap_or_default()
+}
+
+/// The value of capability `name`: `Some(None)` for a boolean, the number
+/// in decimal or the string with its escapes expanded.
+fn lookup(name: &str) -> Option<Option<Vec<u8>>> {
+    let (_, lines) = entry();
+    for field in lines.flat_map(fields) {
+        if field == name {
+            return Some(None);
+        }
+        if let Some(number) = field.strip_prefix(name).and_then(|rest| rest.strip_prefix('#')) {
+            let number = match number.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            return Some(Some(number.to_string().into_bytes()));
+        }
+        if let Some(value) = field.strip_prefix(name).and_then(|rest| rest.strip_prefix('=')) {
+            return Some(Some(unescape(value)));
+        }
+    }
+    None
+}
+
+/// Splits a line at the commas that end each capability; `\,` is a comma
+/// inside a string.
+fn fields(line: &str) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let (mut start, mut escaped) = (0, false);
+    for (index, ch) in line.char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ',' => {
+                fields.push(line[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    fields.retain(|field| !field.is_empty());
+    fields
+}
+
+/// Expands terminfo string escapes: `\E`, `^X`, `\n`, octal and the like.
+fn unescape(value: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut chars = value.bytes().peekable();
+    while let Some(byte) = chars.next() {
+        match byte {
+            b'\\' => match chars.next() {
+                Some(b'E' | b'e') => bytes.push(0x1b),
+                Some(b'n' | b'l') => bytes.push(b'\n'),
+                Some(b'r') => bytes.push(b'\r'),
+                Some(b't') => bytes.push(b'\t'),
+                Some(b'b') => bytes.push(0x08),
+                Some(b'f') => bytes.push(0x0c),
+                Some(b's') => bytes.push(b' '),
+                Some(digit @ b'0'..=b'7') => {
+                    let mut value = u32::from(digit - b'0');
+                    for _ in 0..2 {
+                        match chars.peek() {
+                            Some(&digit @ b'0'..=b'7') => {
+                                value = value * 8 + u32::from(digit - b'0');
+                                chars.next();
+                            }
+                            _ => break,
+                        }
+                    }
+                    // `\0` stands for a NUL, which terminfo cannot hold.
+                    bytes.push(if value == 0 { 0x80 } else { value as u8 });
+                }
+                Some(other) => bytes.push(other),
+                None => bytes.push(b'\\'),
+            },
+            b'^' => match chars.next() {
+                Some(b'?') => bytes.push(0x7f),
+                Some(other) => bytes.push(other & 0x1f),
+                None => bytes.push(b'^'),
+            },
+            _ => bytes.push(byte),
+        }
+    }
+    bytes
+}
+
+/// Replies to a query for the `;`-separated hex `names`.
+pub fn reply(names: &[u8]) -> Vec<u8> {
+    let mut reply = Vec::new();
+    for hex_name in names.split(|&byte| byte == b';') {
+        let value = decode_hex(hex_name).and_then(|name| match name.as_str() {
+            "TN" => Some(Some(entry_name().as_bytes().to_vec())),
+            "Co" => lookup("colors"),
+            _ => lookup(&name),
+        });
+        let hex_name: String =
+            hex_name.iter().filter(|byte| byte.is_ascii_hexdigit()).map(|byte| byte.to_ascii_uppercase() as char).collect();
+        let line = match value {
+            Some(Some(value)) => format!("\x1bP1+r{}={}\x1b\\", hex_name, encode_hex(&value)),
+            Some(None) => format!("\x1bP1+r{}\x1b\\", hex_name),
+            None => format!("\x1bP0+r{}\x1b\\", hex_name),
+        };
+        reply.extend_from_slice(line.as_bytes());
+    }
+    reply
+}
+
+fn decode_hex(hex: &[u8]) -> Option<String> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = hex
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The query for `names`, hex encoded as applications send them.
+    fn query(names: &[&str]) -> Vec<u8> {
+        names.iter().map(|name| encode_hex(name.as_bytes())).collect::<Vec<_>>().join(";").into_bytes()
+    }
+
+    fn reply_to(names: &[&str]) -> String {
+        String::from_utf8(reply(&query(names))).unwrap()
+    }
+
+    #[test]
+    fn fields_split_at_unescaped_commas() {
+        assert_eq!(fields("am, colors#256, bel=^G,"), ["am", "colors#256", "bel=^G"]);
+        assert_eq!(fields(r"a=\,b, c=\\, d"), [r"a=\,b", r"c=\\"]);
+        assert!(fields("  ,  ,").is_empty());
+    }
+
+    #[test]
+    fn unescape_expands_escapes() {
+        assert_eq!(unescape(r"\E[1m"), b"\x1b[1m");
+        assert_eq!(unescape(r"\e\n\l\r\t\b\f\s"), b"\x1b\n\n\r\t\x08\x0c ");
+        assert_eq!(unescape("^G^[^?"), b"\x07\x1b\x7f");
+        assert_eq!(unescape(r"\007\1x\0"), b"\x07\x01x\x80");
+        assert_eq!(unescape(r"\\\,\^\:"), b"\\,^:");
+        assert_eq!(unescape(r"trailing\"), b"trailing\\");
+        assert_eq!(unescape("trailing^"), b"trailing^");
+    }
+
+    #[test]
+    fn entry_starts_with_its_names() {
+        assert_eq!(entry_name(), "xterm-macos-term");
+        assert!(entry().0.contains("|macos-term|"));
+    }
+
+    #[test]
+    fn lookup_finds_every_kind_of_capability() {
+        assert_eq!(lookup("am"), Some(None));
+        assert_eq!(lookup("colors"), Some(Some(b"256".to_vec())));
+        assert_eq!(lookup("pairs"), Some(Some(b"65536".to_vec())));
+        assert_eq!(lookup("bold"), Some(Some(b"\x1b[1m".to_vec())));
+        assert_eq!(lookup("kbs"), Some(Some(b"\x7f".to_vec())));
+        assert!(lookup("initc").unwrap().unwrap().ends_with(b"X\x1b\\"));
+        assert_eq!(lookup("cols"), Some(Some(b"80".to_vec())));
+        assert_eq!(lookup("col"), None);
+        assert_eq!(lookup("nonexistent"), None);
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        assert_eq!(encode_hex(b"\x1b[1m"), "1B5B316D");
+        assert_eq!(decode_hex(b"1B5b316d").as_deref(), Some("\x1b[1m"));
+        assert_eq!(decode_hex(b""), None);
+        assert_eq!(decode_hex(b"123"), None);
+        assert_eq!(decode_hex(b"zz"), None);
+        assert_eq!(decode_hex(b"ff"), None);
+    }
+
+    #[test]
+    fn replies_carry_values_in_hex() {
+        assert_eq!(reply_to(&["bold"]), "\x1bP1+r626F6C64=1B5B316D\x1b\\");
+        assert_eq!(reply_to(&["am"]), "\x1bP1+r616D\x1b\\");
+        assert_eq!(reply_to(&["colors"]), "\x1bP1+r636F6C6F7273=323536\x1b\\");
+    }
+
+    #[test]
+    fn tn_and_co_are_answered() {
+        assert_eq!(reply_to(&["TN"]), format!("\x1bP1+r544E={}\x1b\\", encode_hex(b"xterm-macos-term")));
+        assert_eq!(reply_to(&["Co"]), "\x1bP1+r436F=323536\x1b\\");
+    }
+
+    #[test]
+    fn unknown_names_are_refused() {
+        assert_eq!(reply_to(&["xyzzy"]), "\x1bP0+r78797A7A79\x1b\\");
+        assert_eq!(String::from_utf8(reply(b"6g")).unwrap(), "\x1bP0+r6\x1b\\");
+        assert_eq!(String::from_utf8(reply(b"")).unwrap(), "\x1bP0+r\x1b\\");
+    }
+
+    #[test]
+    fn each_name_gets_its_own_reply() {
+        let separate = reply_to(&["am"]) + &reply_to(&["xyzzy"]) + &reply_to(&["bold"]);
+        assert_eq!(reply_to(&["am", "xyzzy", "bold"]), separate);
+        assert_eq!(reply(b"616d"), reply(b"616D"));
+    }
+}
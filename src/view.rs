@@ -0,0 +1,1722 @@
+//! Custom-drawn terminal widget.
+//!
+//! `TerminalView` renders the screen model cell by cell with GtkSnapshot.
+//! Glyph layouts are cached per character and style, and every row is recorded
+//! into a render node that is only rebuilt when the screen reports damage.
+//! Cells with the default background are left unpainted so the
+//! `.terminal-background` tint behind the widget shows through.
+//!
+//! The view also owns the vertical `Adjustment` that exposes the scrollback to
+//! a `GtkScrollbar`. The grid follows the widget's allocation: whenever a
+//! different number of cells fits, the screen is reflowed, the PTY is told the
+//! new size and `grid-resized` is emitted.
+//!
+//! Search matches are highlighted over the text the same way as the
+//! selection; the search bar itself lives outside the widget. Links under
+//! the pointer, OSC 8 or recognised in the text, are underlined and open on
+//! Ctrl+click. A narrow gutter left of the grid marks the prompts the shell
+//! reported through OSC 133, in red for commands that failed.
+//!
+//! Images are drawn from straight-alpha textures, so their translucent
+//! pixels blend over the glass tint like text does. Placements with a
+//! negative z-index go under the text, cell backgrounds included; the rest
+//! go over it.
+//!
+//! Output is drawn at most once per frame: `feed` only parses, and a frame
+//! clock tick callback brings the title, scrollbar and drawing up to date.
+//! While an application holds a synchronized update (mode 2026) the last
+//! frame is shown as it was, until the update ends or `SYNC_TIMEOUT` passes.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use gtk4::glib::subclass::Signal;
+use gtk4::prelude::*;
+use gtk4::subclass::prelude::*;
+use gtk4::{gdk, glib, graphene, gsk, pango};
+use regex::Regex;
+
+use crate::config::{ClipboardPolicy, Config};
+use crate::keyboard::{self, KeyEventKind, KeyInput, KeyboardModes};
+use crate::open;
+use crate::paste;
+use crate::pty::PtySession;
+use crate::terminal::{Screen, Terminal};
+use crate::terminal::cell::{Attrs, Cell as TermCell, Flags, Row};
+use crate::terminal::clipboard::{self, ClipboardKind, ClipboardRequest};
+use crate::terminal::color::{Palette, Rgb};
+use crate::terminal::graphics::{ImageData, Placement};
+use crate::terminal::hyperlink::{self, Link, LinkTarget};
+use crate::terminal::kitty;
+use crate::terminal::mouse::{self, MouseAction, MouseButton, MouseEvent, MouseMode};
+use crate::terminal::screen::CursorShape;
+use crate::terminal::search::{self, SearchMatch};
+use crate::terminal::selection::{Point, Selection, SelectionKind};
+
+/// Number of cached glyph layouts before the cache is dropped and rebuilt.
+const GLYPH_CACHE_LIMIT: usize = 4096;
+
+/// Lines scrolled per mouse wheel notch.
+const WHEEL_SCROLL_LINES: f64 = 3.0;
+
+/// Highlight drawn over selected cells.
+const SELECTION_COLOR: gdk::RGBA = gdk::RGBA::new(0.35, 0.55, 1.0, 0.45);
+
+/// Highlight drawn over search matches, and over the current one.
+const SEARCH_MATCH_COLOR: gdk::RGBA = gdk::RGBA::new(1.0, 0.8, 0.2, 0.35);
+const SEARCH_CURRENT_COLOR: gdk::RGBA = gdk::RGBA::new(1.0, 0.5, 0.1, 0.65);
+
+/// Space left of the grid for command marks.
+const GUTTER_WIDTH: f32 = 6.0;
+
+/// Gutter marks for prompts, and for the whole extent of failed commands.
+const PROMPT_MARK_COLOR: gdk::RGBA = gdk::RGBA::new(0.6, 0.6, 0.6, 0.5);
+const COMMAND_FAILED_COLOR: gdk::RGBA = gdk::RGBA::new(0.9, 0.25, 0.25, 0.9);
+
+/// Window title when the template comes out empty.
+const DEFAULT_TITLE: &str = "Terminal";
+
+/// How often the foreground process is looked up for the title.
+const PROCESS_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Lines of a paste shown in the confirmation dialog.
+const PASTE_PREVIEW_LINES: usize = 8;
+
+/// Longest a synchronized update may hold back drawing before it is ended.
+const SYNC_TIMEOUT: Duration = Duration::from_millis(150);
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct GlyphKey {
+    ch: char,
+    extra: Option<Box<str>>,
+    bold: bool,
+    italic: bool,
+}
+
+mod imp {
+    use super::*;
+
+    pub struct TerminalView {
+        pub config: RefCell<Rc<Config>>,
+        pub terminal: RefCell<Terminal>,
+        pub session: RefCell<Option<Rc<PtySession>>>,
+        /// Font the metrics and glyph cache were computed for.
+        pub font: RefCell<Option<pango::FontDescription>>,
+        pub cell_size: Cell<(f32, f32)>,
+        pub glyphs: RefCell<HashMap<GlyphKey, pango::Layout>>,
+        pub row_nodes: RefCell<Vec<Option<gsk::RenderNode>>>,
+        /// Forces every row node to be rebuilt on the next snapshot.
+        pub full_redraw: Cell<bool>,
+        /// Scrollback position: `value` is the first visible history line.
+        pub adjustment: gtk4::Adjustment,
+        /// Set while the view itself updates `adjustment`.
+        pub syncing_adjustment: Cell<bool>,
+        /// Fractional wheel deltas from smooth scrolling devices.
+        pub wheel_remainder: Cell<f64>,
+        /// Whether the first allocation has sized the grid yet.
+        pub allocated: Cell<bool>,
+        /// Last pointer position, for wheel events which carry none.
+        pub pointer: Cell<(f64, f64)>,
+        /// Cell of the last reported mouse event, so motion is only reported
+        /// when the pointer enters a new cell.
+        pub mouse_cell: Cell<Option<(usize, usize)>>,
+        /// Hardware keycodes currently held, to tell key repeat from a press.
+        pub pressed_keys: RefCell<HashSet<u32>>,
+        pub selection: RefCell<Option<Selection>>,
+        /// Set while the primary button drags out a selection.
+        pub selecting: Cell<bool>,
+        /// Which screen the selection was made on; switching drops it.
+        pub alternate_screen: Cell<bool>,
+        /// Matches of the last search, oldest first.
+        pub search_matches: RefCell<Vec<SearchMatch>>,
+        pub search_current: Cell<Option<usize>>,
+        /// Link under the pointer, and the cell it was looked up for.
+        pub hovered_link: RefCell<Option<Link>>,
+        pub hover_point: Cell<Option<Point>>,
+        /// Window title last reported through `title-changed`.
+        pub title: RefCell<String>,
+        /// Name of the foreground process, looked up every
+        /// `PROCESS_POLL_INTERVAL` rather than each time the title is built.
+        pub process_name: RefCell<String>,
+        /// Default background last reported through `background-changed`.
+        pub background: Cell<Rgb>,
+        /// Textures of the images on the screen, by image id; `None` for
+        /// images GTK could not decode.
+        pub textures: RefCell<HashMap<u32, Option<gdk::Texture>>>,
+        /// Last frame drawn, shown again during a synchronized update.
+        pub frame: RefCell<Option<gsk::RenderNode>>,
+        /// Ends a synchronized update the application never finishes.
+        pub sync_timeout: RefCell<Option<glib::SourceId>>,
+        /// Set while a tick callback is waiting to redraw.
+        pub redraw_scheduled: Cell<bool>,
+        /// Set while the clipboard read prompt is open; further OSC 52
+        /// queries are refused until it is answered.
+        pub clipboard_prompt: Cell<bool>,
+    }
+
+    impl Default for TerminalView {
+        fn default() -> Self {
+            Self {
+                config: RefCell::new(Rc::new(Config::default())),
+                terminal: RefCell::new(Terminal::new(80, 24)),
+                session: RefCell::new(None),
+                font: RefCell::new(None),
+                cell_size: Cell::new((8.0, 16.0)),
+                glyphs: RefCell::new(HashMap::new()),
+                row_nodes: RefCell::new(Vec::new()),
+                full_redraw: Cell::new(true),
+                adjustment: gtk4::Adjustment::new(0.0, 0.0, 24.0, 1.0, 24.0, 24.0),
+                syncing_adjustment: Cell::new(false),
+                wheel_remainder: Cell::new(0.0),
+                allocated: Cell::new(false),
+                pointer: Cell::new((0.0, 0.0)),
+                mouse_cell: Cell::new(None),
+                pressed_keys: RefCell::new(HashSet::new()),
+                selection: RefCell::new(None),
+                selecting: Cell::new(false),
+                alternate_screen: Cell::new(false),
+                search_matches: RefCell::new(Vec::new()),
+                search_current: Cell::new(None),
+                hovered_link: RefCell::new(None),
+                hover_point: Cell::new(None),
+                title: RefCell::new(String::new()),
+                process_name: RefCell::new(String::new()),
+                background: Cell::new(Rgb::default()),
+                textures: RefCell::new(HashMap::new()),
+                frame: RefCell::new(None),
+                sync_timeout: RefCell::new(None),
+                redraw_scheduled: Cell::new(false),
+                clipboard_prompt: Cell::new(false),
+            }
+        }
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for TerminalView {
+        const NAME: &'static str = "MacosTermTerminalView";
+        type Type = super::TerminalView;
+        type ParentType = gtk4::Widget;
+    }
+
+    impl ObjectImpl for TerminalView {
+        fn constructed(&self) {
+            self.parent_constructed();
+
+            let obj = self.obj();
+            obj.set_focusable(true);
+            obj.set_focus_on_click(true);
+            obj.add_css_class("terminal-text");
+            obj.setup_input();
+        }
+
+        fn dispose(&self) {
+            // Let go of the shell with the window rather than whenever the
+            // last reference to the view happens to be dropped.
+            self.session.take();
+        }
+
+        fn signals() -> &'static [Signal] {
+            static SIGNALS: OnceLock<Vec<Signal>> = OnceLock::new();
+            SIGNALS.get_or_init(|| {
+                vec![
+                    Signal::builder("grid-resized")
+                        .param_types([u32::static_type(), u32::static_type()])
+                        .build(),
+                    Signal::builder("title-changed").param_types([String::static_type()]).build(),
+                    Signal::builder("background-changed").param_types([gdk::RGBA::static_type()]).build(),
+                ]
+            })
+        }
+    }
+
+    impl WidgetImpl for TerminalView {
+        fn measure(&self, orientation: gtk4::Orientation, _for_size: i32) -> (i32, i32, i32, i32) {
+            self.update_metrics();
+            let (cell_width, cell_height) = self.cell_size.get();
+            let terminal = self.terminal.borrow();
+            let screen = terminal.screen();
+            match orientation {
+                gtk4::Orientation::Horizontal => (
+                    (GUTTER_WIDTH + cell_width).ceil() as i32,
+                    (GUTTER_WIDTH + cell_width * screen.cols() as f32).ceil() as i32,
+                    -1,
+                    -1,
+                ),
+                _ => (
+                    cell_height.ceil() as i32,
+                    (cell_height * screen.rows() as f32).ceil() as i32,
+                    -1,
+                    -1,
+                ),
+            }
+        }
+
+        fn size_allocate(&self, width: i32, height: i32, baseline: i32) {
+            self.parent_size_allocate(width, height, baseline);
+            self.update_metrics();
+            let (cell_width, cell_height) = self.cell_size.get();
+            let cols = ((width as f32 - GUTTER_WIDTH) / cell_width).floor().max(1.0) as usize;
+            let rows = (height as f32 / cell_height).floor().max(1.0) as usize;
+            self.obj().resize_grid(cols, rows, width, height);
+        }
+
+        fn snapshot(&self, snapshot: &gtk4::Snapshot) {
+            if self.terminal.borrow().screen().modes.synchronized_output
+                && let Some(frame) = self.frame.borrow().as_ref()
+            {
+                snapshot.append_node(frame);
+                return;
+            }
+            let frame = gtk4::Snapshot::new();
+            self.render(&frame);
+            let frame = frame.to_node();
+            if let Some(frame) = &frame {
+                snapshot.append_node(frame);
+            }
+            self.frame.replace(frame);
+        }
+    }
+
+    impl TerminalView {
+        /// Draws the current state of the screen.
+        fn render(&self, snapshot: &gtk4::Snapshot) {
+            self.update_metrics();
+            let (_, cell_height) = self.cell_size.get();
+
+            let mut terminal = self.terminal.borrow_mut();
+            let damage = terminal.screen_mut().take_damage();
+            let screen = terminal.screen();
+
+            let selection = self
+                .selection
+                .borrow()
+                .filter(|selection| !selection.is_empty())
+                .map(|selection| selection.resolve(screen, &self.config.borrow().word_chars));
+
+            let search_matches = self.search_matches.borrow();
+            let search_current = self.search_current.get();
+            let hovered_link = self.hovered_link.borrow();
+
+            self.render_gutter(snapshot, screen);
+            snapshot.save();
+            snapshot.translate(&graphene::Point::new(GUTTER_WIDTH, 0.0));
+            self.textures.borrow_mut().retain(|id, _| screen.images().image(*id).is_some());
+            self.render_images(snapshot, screen, true);
+
+            let mut row_nodes = self.row_nodes.borrow_mut();
+            row_nodes.resize(screen.rows(), None);
+            let full_redraw = self.full_redraw.replace(false);
+            for (index, node) in row_nodes.iter_mut().enumerate() {
+                if full_redraw || damage[index] {
+                    let line = screen.viewport_line(index);
+                    let selected = selection.as_ref().and_then(|selection| selection.columns(line, screen.cols()));
+                    let found: Vec<(Range<usize>, bool)> = search_matches
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, found)| found.start.line <= line && line <= found.end.line)
+                        .map(|(number, found)| {
+                            let first = if found.start.line == line { found.start.col } else { 0 };
+                            let last = if found.end.line == line { found.end.col + 1 } else { screen.cols() };
+                            (first..last, search_current == Some(number))
+                        })
+                        .collect();
+                    let link = hovered_link.as_ref().and_then(|link| link.columns(line, screen.cols()));
+                    *node = self.render_row(&screen.visible_row(index), &screen.palette, &found, selected, link);
+                }
+                if let Some(node) = node {
+                    snapshot.save();
+                    snapshot.translate(&graphene::Point::new(0.0, index as f32 * cell_height));
+                    snapshot.append_node(node);
+                    snapshot.restore();
+                }
+            }
+
+            self.render_placeholders(snapshot, screen);
+            self.render_images(snapshot, screen, false);
+            self.render_cursor(snapshot, &terminal);
+            snapshot.restore();
+        }
+
+        /// Recomputes the cell size when the CSS font changes.
+        fn update_metrics(&self) {
+            let context = self.obj().pango_context();
+            let font = context.font_description();
+            if *self.font.borrow() == font {
+                return;
+            }
+
+            let layout = pango::Layout::new(&context);
+            layout.set_text("M");
+            let (_, logical) = layout.extents();
+            let (cell_width, cell_height) =
+                (logical.width() as f32 / pango::SCALE as f32, logical.height() as f32 / pango::SCALE as f32);
+            self.cell_size.set((cell_width, cell_height));
+            // Rounded down, so images never end up covering more rows than
+            // the screen moved the cursor past
+            self.terminal.borrow_mut().screen_mut().set_cell_pixel_size(cell_width as u32, cell_height as u32);
+            self.glyphs.borrow_mut().clear();
+            self.full_redraw.set(true);
+            *self.font.borrow_mut() = font;
+        }
+
+        /// Layout for a cell's grapheme cluster in the cell's font style.
+        fn glyph(&self, cell: &TermCell) -> pango::Layout {
+            let flags = cell.attrs.flags;
+            let key = GlyphKey {
+                ch: cell.ch,
+                extra: cell.extra.clone(),
+                bold: flags.contains(Flags::BOLD),
+                italic: flags.contains(Flags::ITALIC),
+            };
+
+            let mut glyphs = self.glyphs.borrow_mut();
+            if glyphs.len() >= GLYPH_CACHE_LIMIT {
+                glyphs.clear();
+            }
+            glyphs
+                .entry(key)
+                .or_insert_with_key(|key| {
+                    let mut text = String::new();
+                    cell.push_to(&mut text);
+                    let layout = self.obj().create_pango_layout(Some(&text));
+                    if key.bold || key.italic {
+                        let mut font = self.font.borrow().clone().unwrap_or_default();
+                        if key.bold {
+                            font.set_weight(pango::Weight::Bold);
+                        }
+                        if key.italic {
+                            font.set_style(pango::Style::Italic);
+                        }
+                        layout.set_font_description(Some(&font));
+                    }
+                    layout
+                })
+                .clone()
+        }
+
+        fn render_row(
+            &self,
+            row: &Row,
+            palette: &Palette,
+            found: &[(Range<usize>, bool)],
+            selected: Option<Range<usize>>,
+            link: Option<Range<usize>>,
+        ) -> Option<gsk::RenderNode> {
+            let (cell_width, cell_height) = self.cell_size.get();
+            let snapshot = gtk4::Snapshot::new();
+
+            // Backgrounds first, merged into runs so adjacent cells do not seam.
+            let mut col = 0;
+            while col < row.cells.len() {
+                let Some(color) = resolve_colors(row.cells[col].attrs, palette).1 else {
+                    col += 1;
+                    continue;
+                };
+                let start = col;
+                while col < row.cells.len() && resolve_colors(row.cells[col].attrs, palette).1 == Some(color) {
+                    col += 1;
+                }
+                let bounds = graphene::Rect::new(
+                    start as f32 * cell_width,
+                    0.0,
+                    (col - start) as f32 * cell_width,
+                    cell_height,
+                );
+                snapshot.append_color(&color, &bounds);
+            }
+
+            for (columns, current) in found {
+                let bounds = graphene::Rect::new(
+                    columns.start as f32 * cell_width,
+                    0.0,
+                    columns.len() as f32 * cell_width,
+                    cell_height,
+                );
+                let color = if *current { &SEARCH_CURRENT_COLOR } else { &SEARCH_MATCH_COLOR };
+                snapshot.append_color(color, &bounds);
+            }
+
+            if let Some(selected) = selected {
+                let bounds = graphene::Rect::new(
+                    selected.start as f32 * cell_width,
+                    0.0,
+                    selected.len() as f32 * cell_width,
+                    cell_height,
+                );
+                snapshot.append_color(&SELECTION_COLOR, &bounds);
+            }
+
+            for (col, cell) in row.cells.iter().enumerate() {
+                let flags = cell.attrs.flags;
+                if flags.contains(Flags::HIDDEN) {
+                    continue;
+                }
+                let (foreground, _) = resolve_colors(cell.attrs, palette);
+                let x = col as f32 * cell_width;
+
+                // Wide characters draw across their spacer from the left half.
+                // Placeholders are drawn over by their image instead.
+                if (cell.ch != ' ' || cell.extra.is_some()) && !cell.is_spacer() && cell.ch != kitty::PLACEHOLDER {
+                    snapshot.save();
+                    snapshot.translate(&graphene::Point::new(x, 0.0));
+                    snapshot.append_layout(&self.glyph(cell), &foreground);
+                    snapshot.restore();
+                }
+                let hovered = link.as_ref().is_some_and(|link| link.contains(&col));
+                if flags.contains(Flags::UNDERLINE) || hovered {
+                    let bounds = graphene::Rect::new(x, cell_height - 1.0, cell_width, 1.0);
+                    snapshot.append_color(&foreground, &bounds);
+                }
+                if flags.contains(Flags::STRIKETHROUGH) {
+                    let bounds = graphene::Rect::new(x, (cell_height / 2.0).floor(), cell_width, 1.0);
+                    snapshot.append_color(&foreground, &bounds);
+                }
+            }
+
+            snapshot.to_node()
+        }
+
+        /// Marks each visible prompt; failed commands get a bar from their
+        /// prompt to the end of their output.
+        fn render_gutter(&self, snapshot: &gtk4::Snapshot, screen: &Screen) {
+            let (_, cell_height) = self.cell_size.get();
+            let top = screen.viewport_line(0);
+            let bottom = top + screen.rows() - 1;
+            for command in screen.commands() {
+                let (last, color) = if command.failed() {
+                    (command.last_line(screen.last_line()), &COMMAND_FAILED_COLOR)
+                } else {
+                    (command.prompt, &PROMPT_MARK_COLOR)
+                };
+                if last < top || command.prompt > bottom {
+                    continue;
+                }
+                let first_row = command.prompt.max(top) - top;
+                let rows = last.min(bottom) - top + 1 - first_row;
+                let bounds =
+                    graphene::Rect::new(1.0, first_row as f32 * cell_height, 3.0, rows as f32 * cell_height);
+                snapshot.append_color(color, &bounds);
+            }
+        }
+
+        /// Draws the placements reaching into the viewport under the text
+        /// (negative z-index) or over it, lowest z-index first, clipped to
+        /// the grid.
+        fn render_images(&self, snapshot: &gtk4::Snapshot, screen: &Screen, under_text: bool) {
+            let (cell_width, cell_height) = self.cell_size.get();
+            let mut placements: Vec<&Placement> =
+                screen.visible_placements().filter(|placement| (placement.z < 0) == under_text).collect();
+            if placements.is_empty() {
+                return;
+            }
+            placements.sort_by_key(|placement| placement.z);
+
+            let top = screen.viewport_line(0) as f32;
+            let grid = graphene::Rect::new(0.0, 0.0, cell_width * screen.cols() as f32, cell_height * screen.rows() as f32);
+            snapshot.push_clip(&grid);
+            for placement in placements {
+                let Some(texture) = self.texture(screen, placement.image) else { continue };
+                let bounds = graphene::Rect::new(
+                    placement.col as f32 * cell_width + placement.offset.0 as f32,
+                    (placement.line as f32 - top) * cell_height + placement.offset.1 as f32,
+                    placement.width as f32,
+                    placement.height as f32,
+                );
+                // Scale the whole texture so the source rectangle fills the
+                // bounds, and clip away the rest.
+                let (x, y, width, height) = placement.source;
+                let scale_x = bounds.width() / width as f32;
+                let scale_y = bounds.height() / height as f32;
+                let whole = graphene::Rect::new(
+                    bounds.x() - x as f32 * scale_x,
+                    bounds.y() - y as f32 * scale_y,
+                    texture.width() as f32 * scale_x,
+                    texture.height() as f32 * scale_y,
+                );
+                snapshot.push_clip(&bounds);
+                snapshot.append_texture(&texture, &whole);
+                snapshot.pop();
+            }
+            snapshot.pop();
+        }
+
+        /// Draws kitty virtual placements into the Unicode placeholder cells
+        /// that show them. Each image is fitted into its placement's cells,
+        /// keeping its aspect ratio, and every placeholder shows the part
+        /// falling into the cell it names.
+        fn render_placeholders(&self, snapshot: &gtk4::Snapshot, screen: &Screen) {
+            if screen.images().virtual_placements().is_empty() {
+                return;
+            }
+            let (cell_width, cell_height) = self.cell_size.get();
+            for index in 0..screen.rows() {
+                let row = screen.visible_row(index);
+                let mut left = None;
+                for (col, cell) in row.cells.iter().enumerate() {
+                    left = kitty::placeholder(cell, left.as_ref());
+                    let Some(placeholder) = left else { continue };
+                    let Some(placement) = screen.virtual_placement(placeholder.image_id) else { continue };
+                    let Some(texture) = self.texture(screen, placement.image) else { continue };
+
+                    let area_width = placement.cols as f32 * cell_width;
+                    let area_height = placement.rows as f32 * cell_height;
+                    let scale = (area_width / texture.width() as f32).min(area_height / texture.height() as f32);
+                    let (width, height) = (texture.width() as f32 * scale, texture.height() as f32 * scale);
+                    let cell_bounds =
+                        graphene::Rect::new(col as f32 * cell_width, index as f32 * cell_height, cell_width, cell_height);
+                    let image_bounds = graphene::Rect::new(
+                        cell_bounds.x() - placeholder.col as f32 * cell_width + (area_width - width) / 2.0,
+                        cell_bounds.y() - placeholder.row as f32 * cell_height + (area_height - height) / 2.0,
+                        width,
+                        height,
+                    );
+                    snapshot.push_clip(&cell_bounds);
+                    snapshot.append_texture(&texture, &image_bounds);
+                    snapshot.pop();
+                }
+            }
+        }
+
+        /// The texture of image `id`, made on first use.
+        fn texture(&self, screen: &Screen, id: u32) -> Option<gdk::Texture> {
+            let image = screen.images().image(id)?;
+            let mut textures = self.textures.borrow_mut();
+            let texture = textures.entry(id).or_insert_with(|| match &image.data {
+                ImageData::Rgba(pixels) => Some(
+                    gdk::MemoryTexture::new(
+                        image.width as i32,
+                        image.height as i32,
+                        gdk::MemoryFormat::R8g8b8a8,
+                        &glib::Bytes::from(&pixels[..]),
+                        image.width as usize * 4,
+                    )
+                    .upcast(),
+                ),
+                ImageData::Encoded(data) => match gdk::Texture::from_bytes(&glib::Bytes::from(&data[..])) {
+                    Ok(texture) => Some(texture),
+                    Err(err) => {
+                        eprintln!("❌ Failed to decode image: {}", err);
+                        None
+                    }
+                },
+            });
+            texture.clone()
+        }
+
+        fn render_cursor(&self, snapshot: &gtk4::Snapshot, terminal: &Terminal) {
+            let screen = terminal.screen();
+            if !screen.modes.cursor_visible {
+                return;
+            }
+
+            // The cursor scrolls out of view along with the bottom of the screen.
+            let visible_row = screen.cursor().row + screen.display_offset();
+            if visible_row >= screen.rows() {
+                return;
+            }
+
+            let (cell_width, cell_height) = self.cell_size.get();
+            let cursor = screen.cursor();
+            let mut col = cursor.col;
+            let line = screen.row(cursor.row);
+            if col > 0 && line.cells[col].attrs.flags.contains(Flags::WIDE_SPACER) {
+                col -= 1;
+            }
+            let cell = &line.cells[col];
+            let x = col as f32 * cell_width;
+            let width = if cell.attrs.flags.contains(Flags::WIDE) { 2.0 * cell_width } else { cell_width };
+            let y = visible_row as f32 * cell_height;
+            let color = rgba(screen.palette.cursor);
+
+            if !self.obj().has_focus() {
+                // Hollow box while another widget has the keyboard.
+                for bounds in [
+                    graphene::Rect::new(x, y, width, 1.0),
+                    graphene::Rect::new(x, y + cell_height - 1.0, width, 1.0),
+                    graphene::Rect::new(x, y, 1.0, cell_height),
+                    graphene::Rect::new(x + width - 1.0, y, 1.0, cell_height),
+                ] {
+                    snapshot.append_color(&color, &bounds);
+                }
+                return;
+            }
+
+            match screen.cursor_shape {
+                CursorShape::Block => {
+                    snapshot.append_color(&color, &graphene::Rect::new(x, y, width, cell_height));
+                    if cell.ch != ' ' || cell.extra.is_some() {
+                        snapshot.save();
+                        snapshot.translate(&graphene::Point::new(x, y));
+                        snapshot.append_layout(&self.glyph(cell), &rgba(screen.palette.background));
+                        snapshot.restore();
+                    }
+                }
+                CursorShape::Underline => {
+                    snapshot.append_color(&color, &graphene::Rect::new(x, y + cell_height - 2.0, width, 2.0));
+                }
+                CursorShape::Bar => {
+                    snapshot.append_color(&color, &graphene::Rect::new(x, y, 2.0, cell_height));
+                }
+            }
+        }
+    }
+}
+
+glib::wrapper! {
+    pub struct TerminalView(ObjectSubclass<imp::TerminalView>)
+        @extends gtk4::Widget,
+        @implements gtk4::Accessible, gtk4::Buildable, gtk4::ConstraintTarget;
+}
+
+impl TerminalView {
+    pub fn new(config: Rc<Config>) -> Self {
+        let view: Self = glib::Object::new();
+        let imp = view.imp();
+        {
+            let mut terminal = imp.terminal.borrow_mut();
+            let screen = terminal.screen_mut();
+            screen.set_palette(config.palette.clone());
+            screen.set_scrollback_limit(config.scrollback_lines);
+            screen.set_image_memory_limit(config.image_memory_limit);
+        }
+        imp.background.set(config.palette.background);
+        *imp.config.borrow_mut() = config;
+        view.sync_adjustment();
+        view
+    }
+
+    /// The adjustment a scrollbar should use to move through the scrollback.
+    pub fn adjustment(&self) -> gtk4::Adjustment {
+        self.imp().adjustment.clone()
+    }
+
+    /// Connects the view to the shell it displays and sends keystrokes to.
+    pub fn set_session(&self, session: Rc<PtySession>) {
+        *self.imp().session.borrow_mut() = Some(session);
+        self.refresh_process_name();
+        let view = self.downgrade();
+        glib::timeout_add_local(PROCESS_POLL_INTERVAL, move || {
+            let Some(view) = view.upgrade() else { return glib::ControlFlow::Break };
+            view.refresh_process_name();
+            glib::ControlFlow::Continue
+        });
+    }
+
+    /// Looks up the foreground process again and updates the title if it
+    /// changed.
+    fn refresh_process_name(&self) {
+        let imp = self.imp();
+        let process = imp
+            .session
+            .borrow()
+            .as_ref()
+            .and_then(|session| session.foreground_process_name())
+            .unwrap_or_default();
+        if *imp.process_name.borrow() != process {
+            imp.process_name.replace(process);
+            self.update_title();
+        }
+    }
+
+    /// Called with the new column and row count whenever the window is resized
+    /// to fit a different number of cells.
+    pub fn connect_grid_resized<F: Fn(&Self, u32, u32) + 'static>(&self, callback: F) -> glib::SignalHandlerId {
+        self.connect_local("grid-resized", false, move |values| {
+            let view = values[0].get::<Self>().expect("grid-resized: view");
+            let cols = values[1].get::<u32>().expect("grid-resized: cols");
+            let rows = values[2].get::<u32>().expect("grid-resized: rows");
+            callback(&view, cols, rows);
+            None
+        })
+    }
+
+    /// Called with the new window title whenever `title` changes.
+    pub fn connect_title_changed<F: Fn(&Self, &str) + 'static>(&self, callback: F) -> glib::SignalHandlerId {
+        self.connect_local("title-changed", false, move |values| {
+            let view = values[0].get::<Self>().expect("title-changed: view");
+            let title = values[1].get::<String>().expect("title-changed: title");
+            callback(&view, &title);
+            None
+        })
+    }
+
+    /// Called when an application changes the default background (OSC 11),
+    /// or puts it back.
+    pub fn connect_background_changed<F: Fn(&Self, &gdk::RGBA) + 'static>(&self, callback: F) -> glib::SignalHandlerId {
+        self.connect_local("background-changed", false, move |values| {
+            let view = values[0].get::<Self>().expect("background-changed: view");
+            let color = values[1].get::<gdk::RGBA>().expect("background-changed: color");
+            callback(&view, &color);
+            None
+        })
+    }
+
+    /// The window title: the configured template with `{title}` (the title
+    /// the application set, or the foreground process without one),
+    /// `{process}` and `{cwd}` filled in.
+    pub fn title(&self) -> String {
+        let imp = self.imp();
+        let process = imp.process_name.borrow().clone();
+        let title = imp.terminal.borrow().screen().title().to_string();
+        let title = if title.is_empty() { process.clone() } else { title };
+        let cwd = self.working_directory();
+        let cwd = match env::var_os("HOME") {
+            Some(home) if !home.is_empty() && cwd.starts_with(&home) => {
+                PathBuf::from("~").join(cwd.strip_prefix(&home).unwrap_or(&cwd))
+            }
+            _ => cwd,
+        };
+        let cwd = cwd.to_string_lossy();
+
+        let rendered = fill_template(&imp.config.borrow().title, |name| match name {
+            "title" => Some(title.as_str()),
+            "process" => Some(process.as_str()),
+            "cwd" => Some(cwd.as_ref()),
+            _ => None,
+        });
+        let rendered = rendered.trim();
+        if rendered.is_empty() { DEFAULT_TITLE.to_string() } else { rendered.to_string() }
+    }
+
+    /// Emits `title-changed` if the title no longer reads the same.
+    fn update_title(&self) {
+        let title = self.title();
+        if *self.imp().title.borrow() != title {
+            self.imp().title.replace(title.clone());
+            self.emit_by_name::<()>("title-changed", &[&title]);
+        }
+    }
+
+    /// Feeds output from the shell into the emulator and schedules a redraw.
+    pub fn feed(&self, bytes: &[u8]) {
+        let (responses, clipboard_requests, alternate, background, synchronized) = {
+            let mut terminal = self.imp().terminal.borrow_mut();
+            terminal.feed(bytes);
+            let screen = terminal.screen_mut();
+            (
+                screen.take_responses(),
+                screen.take_clipboard_requests(),
+                screen.is_alternate_screen(),
+                screen.palette.background,
+                screen.modes.synchronized_output,
+            )
+        };
+        if !responses.is_empty() {
+            self.write_to_session(&responses);
+        }
+        for request in clipboard_requests {
+            self.handle_clipboard_request(request);
+        }
+        if self.imp().alternate_screen.replace(alternate) != alternate {
+            self.clear_selection();
+        }
+        if self.imp().background.replace(background) != background {
+            self.emit_by_name::<()>("background-changed", &[&rgba(background)]);
+        }
+        self.set_sync_timeout(synchronized);
+        self.schedule_redraw();
+    }
+
+    /// Brings the title, scrollbar and drawing up to date on the next frame
+    /// clock tick, so however many reads arrive in between are drawn once.
+    fn schedule_redraw(&self) {
+        if self.imp().redraw_scheduled.replace(true) {
+            return;
+        }
+        self.add_tick_callback(|view, _| {
+            let imp = view.imp();
+            imp.redraw_scheduled.set(false);
+            // Output is also the sign that a command started or finished
+            view.update_title();
+            view.sync_adjustment();
+            if !imp.terminal.borrow().screen().modes.synchronized_output {
+                view.queue_draw();
+            }
+            glib::ControlFlow::Break
+        });
+    }
+
+    /// Arms the timeout when a synchronized update begins and disarms it
+    /// when the update ends.
+    fn set_sync_timeout(&self, synchronized: bool) {
+        let imp = self.imp();
+        if !synchronized {
+            if let Some(source) = imp.sync_timeout.take() {
+                source.remove();
+            }
+            return;
+        }
+        if imp.sync_timeout.borrow().is_some() {
+            return;
+        }
+        let view = self.downgrade();
+        let source = glib::timeout_add_local_once(SYNC_TIMEOUT, move || {
+            if let Some(view) = view.upgrade() {
+                let imp = view.imp();
+                imp.sync_timeout.take();
+                imp.terminal.borrow_mut().screen_mut().modes.synchronized_output = false;
+                view.schedule_redraw();
+            }
+        });
+        imp.sync_timeout.replace(Some(source));
+    }
+
+    fn resize_grid(&self, cols: usize, rows: usize, width: i32, height: i32) {
+        let imp = self.imp();
+        {
+            let mut terminal = imp.terminal.borrow_mut();
+            let screen = terminal.screen_mut();
+            if screen.cols() == cols && screen.rows() == rows {
+                return;
+            }
+            screen.resize(cols, rows);
+        }
+
+        if let Some(session) = imp.session.borrow().as_ref() {
+            let clamp = |value: usize| value.min(usize::from(u16::MAX)) as u16;
+            let pixels = |value: i32| value.clamp(0, i32::from(u16::MAX)) as u16;
+            if let Err(err) = session.resize(clamp(cols), clamp(rows), pixels(width), pixels(height)) {
+                eprintln!("❌ Failed to resize PTY: {}", err);
+            }
+        }
+
+        // Reflow renumbers the lines a selection, search match or link points at.
+        imp.selection.replace(None);
+        imp.hovered_link.replace(None);
+        imp.hover_point.set(None);
+        imp.search_matches.borrow_mut().clear();
+        imp.search_current.set(None);
+        imp.full_redraw.set(true);
+        self.sync_adjustment();
+        self.queue_draw();
+
+        // The initial allocation is not a resize the user asked for. Emit from
+        // an idle so handlers are free to change widgets outside of layout.
+        if imp.allocated.replace(true) {
+            let view = self.downgrade();
+            glib::idle_add_local_once(move || {
+                if let Some(view) = view.upgrade() {
+                    view.emit_by_name::<()>("grid-resized", &[&(cols as u32), &(rows as u32)]);
+                }
+            });
+        }
+    }
+
+    /// Scrolls the viewport; positive deltas move back into history.
+    fn scroll_display(&self, delta: isize) {
+        self.imp().terminal.borrow_mut().screen_mut().scroll_display(delta);
+        self.sync_adjustment();
+        self.queue_draw();
+    }
+
+    fn scroll_to_bottom(&self) {
+        self.imp().terminal.borrow_mut().screen_mut().set_display_offset(0);
+        self.sync_adjustment();
+        self.queue_draw();
+    }
+
+    /// Mirrors the history size and viewport position into the adjustment.
+    fn sync_adjustment(&self) {
+        let imp = self.imp();
+        let (history, rows, offset) = {
+            let terminal = imp.terminal.borrow();
+            let screen = terminal.screen();
+            (screen.history_len() as f64, screen.rows() as f64, screen.display_offset() as f64)
+        };
+        imp.syncing_adjustment.set(true);
+        imp.adjustment.configure(history - offset, 0.0, history + rows, 1.0, rows - 1.0, rows);
+        imp.syncing_adjustment.set(false);
+    }
+
+    /// Highlights every match of `regex` in the scrollback and on the screen
+    /// and scrolls to the newest one. Returns the current match number and
+    /// the number of matches, or `None` if nothing matched.
+    pub fn search(&self, regex: &Regex) -> Option<(usize, usize)> {
+        let imp = self.imp();
+        let matches = search::find_all(imp.terminal.borrow().screen(), regex);
+        let current = matches.len().checked_sub(1);
+        *imp.search_matches.borrow_mut() = matches;
+        imp.search_current.set(current);
+        self.show_search_match()
+    }
+
+    /// Moves to the next older or newer match, wrapping around at either end.
+    pub fn search_step(&self, older: bool) -> Option<(usize, usize)> {
+        let imp = self.imp();
+        let count = imp.search_matches.borrow().len();
+        let current = imp.search_current.get()?;
+        let next = if older { (current + count - 1) % count } else { (current + 1) % count };
+        imp.search_current.set(Some(next));
+        self.show_search_match()
+    }
+
+    pub fn clear_search(&self) {
+        let imp = self.imp();
+        imp.search_matches.borrow_mut().clear();
+        imp.search_current.set(None);
+        imp.full_redraw.set(true);
+        self.queue_draw();
+    }
+
+    /// Positions of the matches as fractions of the scrollable height, each
+    /// with whether it is the current match, for ticks beside the scrollbar.
+    pub fn search_ticks(&self) -> Vec<(f64, bool)> {
+        let imp = self.imp();
+        let terminal = imp.terminal.borrow();
+        let screen = terminal.screen();
+        let first = screen.first_line();
+        let lines = (screen.last_line() + 1 - first) as f64;
+        let current = imp.search_current.get();
+        imp.search_matches
+            .borrow()
+            .iter()
+            .enumerate()
+            .filter(|(_, found)| found.start.line >= first)
+            .map(|(number, found)| ((found.start.line - first) as f64 / lines, current == Some(number)))
+            .collect()
+    }
+
+    /// Scrolls the current match into view and redraws the highlights.
+    fn show_search_match(&self) -> Option<(usize, usize)> {
+        let imp = self.imp();
+        imp.full_redraw.set(true);
+        self.queue_draw();
+        let current = imp.search_current.get()?;
+        let matches = imp.search_matches.borrow();
+        imp.terminal.borrow_mut().screen_mut().scroll_to_line(matches[current].start.line);
+        self.sync_adjustment();
+        Some((current, matches.len()))
+    }
+
+    /// Scrolls the previous or next prompt the shell marked to the top of the
+    /// viewport. Past the last prompt, returns to the live screen.
+    fn jump_to_prompt(&self, previous: bool) {
+        {
+            let mut terminal = self.imp().terminal.borrow_mut();
+            let screen = terminal.screen_mut();
+            let top = screen.viewport_line(0);
+            let mut prompts = screen.commands().iter().map(|command| command.prompt);
+            let target = if previous {
+                prompts.rfind(|&line| line < top)
+            } else {
+                prompts.find(|&line| line > top)
+            };
+            match target {
+                Some(line) => screen.scroll_line_to_top(line),
+                None if !previous => screen.set_display_offset(0),
+                None => return,
+            }
+        }
+        self.sync_adjustment();
+        self.queue_draw();
+    }
+
+    /// Selects the output of the command under the pointer, or else of the
+    /// last command that printed anything, and puts it in PRIMARY.
+    fn select_command_output(&self) {
+        let imp = self.imp();
+        let (x, y) = imp.pointer.get();
+        let line = self.point_at(x, y).line;
+        let lines = {
+            let terminal = imp.terminal.borrow();
+            let screen = terminal.screen();
+            let last = screen.last_line();
+            let commands = screen.commands();
+            commands
+                .iter()
+                .rfind(|command| command.prompt <= line && line <= command.last_line(last))
+                .and_then(|command| command.output_lines(last))
+                .or_else(|| commands.iter().rev().find_map(|command| command.output_lines(last)))
+        };
+        let Some(lines) = lines else { return };
+
+        let mut selection = Selection::new(SelectionKind::Line, Point { line: *lines.start(), col: 0 });
+        selection.extend(Point { line: *lines.end(), col: 0 });
+        imp.selection.replace(Some(selection));
+        imp.full_redraw.set(true);
+        self.queue_draw();
+        if let Some(text) = self.selection_text() {
+            self.primary_clipboard().set_text(&text);
+        }
+    }
+
+    /// Converts widget coordinates into a `(col, row)` cell clamped to the grid.
+    fn cell_at(&self, x: f64, y: f64) -> (usize, usize) {
+        let (cell_width, cell_height) = self.imp().cell_size.get();
+        let terminal = self.imp().terminal.borrow();
+        let screen = terminal.screen();
+        let col = ((x - f64::from(GUTTER_WIDTH)) / f64::from(cell_width)).max(0.0) as usize;
+        let row = (y / f64::from(cell_height)).max(0.0) as usize;
+        (col.min(screen.cols() - 1), row.min(screen.rows() - 1))
+    }
+
+    /// Whether the application currently takes mouse events. Holding Shift
+    /// keeps them local so text can still be selected.
+    fn mouse_reporting(&self, state: gdk::ModifierType) -> bool {
+        !state.contains(gdk::ModifierType::SHIFT_MASK) && self.imp().terminal.borrow().screen().modes.mouse != MouseMode::Off
+    }
+
+    /// Reports a mouse event to the application if it asked for this kind.
+    fn report_mouse(&self, action: MouseAction, button: MouseButton, x: f64, y: f64, state: gdk::ModifierType) {
+        if !self.mouse_reporting(state) {
+            return;
+        }
+        let (col, row) = self.cell_at(x, y);
+        let imp = self.imp();
+        if action == MouseAction::Motion && imp.mouse_cell.get() == Some((col, row)) {
+            return;
+        }
+
+        let event = MouseEvent {
+            action,
+            button,
+            col,
+            row,
+            alt: state.contains(gdk::ModifierType::ALT_MASK),
+            ctrl: state.contains(gdk::ModifierType::CONTROL_MASK),
+        };
+        let (mode, encoding) = {
+            let terminal = imp.terminal.borrow();
+            let modes = terminal.screen().modes;
+            (modes.mouse, modes.mouse_encoding)
+        };
+        if !mode.reports(&event) {
+            return;
+        }
+        imp.mouse_cell.set(Some((col, row)));
+        if let Some(bytes) = mouse::encode(&event, mode, encoding) {
+            self.write_to_session(&bytes);
+        }
+    }
+
+    fn point_at(&self, x: f64, y: f64) -> Point {
+        let (col, row) = self.cell_at(x, y);
+        let line = self.imp().terminal.borrow().screen().viewport_line(row);
+        Point { line, col }
+    }
+
+    /// The shell's current directory: the one it last reported through
+    /// OSC 7, or else that of the foreground process, or else `$HOME`.
+    /// Relative paths in the output are resolved against it.
+    pub fn working_directory(&self) -> PathBuf {
+        let imp = self.imp();
+        if let Some(dir) = imp.terminal.borrow().screen().working_directory() {
+            return dir.to_path_buf();
+        }
+        imp.session
+            .borrow()
+            .as_ref()
+            .and_then(|session| session.foreground_working_directory())
+            .or_else(|| env::var_os("HOME").map(PathBuf::from))
+            .unwrap_or_default()
+    }
+
+    /// Looks up the link under the pointer and underlines it. File locations
+    /// only count if the file exists.
+    fn update_hovered_link(&self, x: f64, y: f64) {
+        let point = self.point_at(x, y);
+        let imp = self.imp();
+        if imp.hover_point.replace(Some(point)) == Some(point) {
+            return;
+        }
+        let link = {
+            let config = imp.config.borrow();
+            let terminal = imp.terminal.borrow();
+            hyperlink::link_at(terminal.screen(), point, &config.url_regex, &config.file_regex)
+        };
+        let link = link.filter(|link| match &link.target {
+            LinkTarget::Uri(_) => true,
+            LinkTarget::File { path, .. } => open::resolve_path(path, &self.working_directory()).is_some(),
+        });
+        self.set_hovered_link(link);
+    }
+
+    fn set_hovered_link(&self, link: Option<Link>) {
+        let imp = self.imp();
+        if *imp.hovered_link.borrow() == link {
+            return;
+        }
+        let tooltip = link.as_ref().map(|link| {
+            let target = match &link.target {
+                LinkTarget::Uri(uri) => uri.clone(),
+                LinkTarget::File { path, line: Some(line), col: Some(col) } => format!("{}:{}:{}", path, line, col),
+                LinkTarget::File { path, line: Some(line), col: None } => format!("{}:{}", path, line),
+                LinkTarget::File { path, .. } => path.clone(),
+            };
+            format!("{}\nCtrl+click to open", target)
+        });
+        self.set_tooltip_text(tooltip.as_deref());
+        self.set_cursor_from_name(link.as_ref().map(|_| "pointer"));
+        imp.hovered_link.replace(link);
+        imp.full_redraw.set(true);
+        self.queue_draw();
+    }
+
+    /// Opens the link at the pointer, if there is one.
+    fn open_link_at(&self, x: f64, y: f64) -> bool {
+        self.update_hovered_link(x, y);
+        let Some(link) = self.imp().hovered_link.borrow().clone() else {
+            return false;
+        };
+        match link.target {
+            LinkTarget::Uri(uri) => open::open_uri(&uri),
+            LinkTarget::File { path, line, .. } => {
+                let cwd = self.working_directory();
+                if let Some(path) = open::resolve_path(&path, &cwd) {
+                    open::open_file(&path, line, &cwd);
+                }
+            }
+        }
+        true
+    }
+
+    fn start_selection(&self, n_press: i32, x: f64, y: f64, state: gdk::ModifierType) {
+        let kind = match n_press {
+            1 if state.contains(gdk::ModifierType::ALT_MASK) => SelectionKind::Block,
+            1 => SelectionKind::Simple,
+            2 => SelectionKind::Word,
+            _ => SelectionKind::Line,
+        };
+        let imp = self.imp();
+        imp.selection.replace(Some(Selection::new(kind, self.point_at(x, y))));
+        imp.selecting.set(true);
+        imp.full_redraw.set(true);
+        self.queue_draw();
+    }
+
+    fn extend_selection(&self, x: f64, y: f64) {
+        let point = self.point_at(x, y);
+        let imp = self.imp();
+        if let Some(selection) = imp.selection.borrow_mut().as_mut() {
+            selection.extend(point);
+        }
+        imp.full_redraw.set(true);
+        self.queue_draw();
+    }
+
+    fn clear_selection(&self) {
+        let imp = self.imp();
+        if imp.selection.replace(None).is_some() {
+            imp.full_redraw.set(true);
+            self.queue_draw();
+        }
+    }
+
+    /// The selected text, if anything is selected.
+    fn selection_text(&self) -> Option<String> {
+        let imp = self.imp();
+        let selection = (*imp.selection.borrow()).filter(|selection| !selection.is_empty())?;
+        let terminal = imp.terminal.borrow();
+        let screen = terminal.screen();
+        let text = selection.resolve(screen, &imp.config.borrow().word_chars).text(screen);
+        (!text.is_empty()).then_some(text)
+    }
+
+    /// Copies the selection to the clipboard.
+    pub fn copy_clipboard(&self) {
+        if let Some(text) = self.selection_text() {
+            self.clipboard().set_text(&text);
+        }
+    }
+
+    /// Reads the primary selection and pastes it, as a middle click does.
+    fn paste_primary(&self) {
+        let view = self.downgrade();
+        self.primary_clipboard().read_text_async(None::<&gtk4::gio::Cancellable>, move |result| {
+            let Some(view) = view.upgrade() else { return };
+            match result {
+                Ok(Some(text)) => view.paste(&text),
+                Ok(None) => {}
+                Err(err) => eprintln!("❌ Failed to read primary selection: {}", err),
+            }
+        });
+    }
+
+    /// Reads the clipboard and pastes its text into the shell.
+    pub fn paste_clipboard(&self) {
+        let view = self.downgrade();
+        self.clipboard().read_text_async(None::<&gtk4::gio::Cancellable>, move |result| {
+            let Some(view) = view.upgrade() else { return };
+            match result {
+                Ok(Some(text)) => view.paste(&text),
+                Ok(None) => {}
+                Err(err) => eprintln!("❌ Failed to read clipboard: {}", err),
+            }
+        });
+    }
+
+    /// Sends pasted text to the shell, asking first if it would run commands
+    /// in a shell that has not enabled bracketed paste.
+    fn paste(&self, text: &str) {
+        let bracketed = self.imp().terminal.borrow().screen().modes.bracketed_paste;
+        if !paste::needs_confirmation(text, bracketed) {
+            self.send_paste(text, bracketed);
+            return;
+        }
+
+        let lines: Vec<&str> = text.lines().collect();
+        let mut preview = lines.iter().take(PASTE_PREVIEW_LINES).copied().collect::<Vec<_>>().join("\n");
+        if lines.len() > PASTE_PREVIEW_LINES {
+            preview.push_str(&format!("\n… and {} more lines", lines.len() - PASTE_PREVIEW_LINES));
+        }
+        let message = if lines.len() > 1 || text.ends_with(['\n', '\r']) {
+            match lines.len() {
+                0 | 1 => "Paste and run this line in the shell?".to_string(),
+                count => format!("Paste {} lines into the shell?", count),
+            }
+        } else {
+            "Paste a command that uses sudo?".to_string()
+        };
+
+        let dialog = gtk4::MessageDialog::builder()
+            .modal(true)
+            .message_type(gtk4::MessageType::Warning)
+            .text(message)
+            .secondary_text(preview)
+            .build();
+        if let Some(window) = self.root().and_downcast::<gtk4::Window>() {
+            dialog.set_transient_for(Some(&window));
+        }
+        dialog.add_button("Cancel", gtk4::ResponseType::Cancel);
+        dialog.add_button("Paste", gtk4::ResponseType::Accept);
+        dialog.set_default_response(gtk4::ResponseType::Cancel);
+
+        let text = text.to_string();
+        let view = self.downgrade();
+        dialog.connect_response(move |dialog, response| {
+            if response == gtk4::ResponseType::Accept
+                && let Some(view) = view.upgrade()
+            {
+                view.send_paste(&text, bracketed);
+            }
+            dialog.destroy();
+        });
+        dialog.present();
+    }
+
+    fn clipboard_of(&self, kind: ClipboardKind) -> gdk::Clipboard {
+        match kind {
+            ClipboardKind::Clipboard => self.clipboard(),
+            ClipboardKind::Primary => self.primary_clipboard(),
+        }
+    }
+
+    /// Applies the configured clipboard policy to an OSC 52 request. Every
+    /// read attempt is logged, whether or not it is allowed.
+    fn handle_clipboard_request(&self, request: ClipboardRequest) {
+        let policy = self.imp().config.borrow().clipboard;
+        match request {
+            ClipboardRequest::Store { kind, text } => {
+                if policy == ClipboardPolicy::Deny {
+                    eprintln!("❌ Denied clipboard write from application (clipboard = {})", policy.name());
+                    return;
+                }
+                self.clipboard_of(kind).set_text(&text);
+            }
+            ClipboardRequest::Load { kind, bell_terminated } => match policy {
+                ClipboardPolicy::Deny | ClipboardPolicy::AllowWrite => {
+                    eprintln!("❌ Denied clipboard read from application (clipboard = {})", policy.name());
+                }
+                ClipboardPolicy::Ask => self.confirm_clipboard_read(kind, bell_terminated),
+                ClipboardPolicy::AllowAll => self.send_clipboard(kind, bell_terminated),
+            },
+        }
+    }
+
+    fn confirm_clipboard_read(&self, kind: ClipboardKind, bell_terminated: bool) {
+        if self.imp().clipboard_prompt.replace(true) {
+            eprintln!("❌ Denied clipboard read from application (already asking)");
+            return;
+        }
+        let dialog = gtk4::MessageDialog::builder()
+            .modal(true)
+            .message_type(gtk4::MessageType::Question)
+            .text("Let the application read the clipboard?")
+            .secondary_text("The program running in the terminal asked for the clipboard contents (OSC 52).")
+            .build();
+        if let Some(window) = self.root().and_downcast::<gtk4::Window>() {
+            dialog.set_transient_for(Some(&window));
+        }
+        dialog.add_button("Deny", gtk4::ResponseType::Reject);
+        dialog.add_button("Allow", gtk4::ResponseType::Accept);
+        dialog.set_default_response(gtk4::ResponseType::Reject);
+
+        let view = self.downgrade();
+        dialog.connect_response(move |dialog, response| {
+            if let Some(view) = view.upgrade() {
+                view.imp().clipboard_prompt.set(false);
+                if response == gtk4::ResponseType::Accept {
+                    view.send_clipboard(kind, bell_terminated);
+                } else {
+                    eprintln!("❌ Denied clipboard read from application (declined)");
+                }
+            }
+            dialog.destroy();
+        });
+        dialog.present();
+    }
+
+    /// Replies to an OSC 52 query with the clipboard's text.
+    fn send_clipboard(&self, kind: ClipboardKind, bell_terminated: bool) {
+        let view = self.downgrade();
+        self.clipboard_of(kind).read_text_async(None::<&gtk4::gio::Cancellable>, move |result| {
+            let Some(view) = view.upgrade() else { return };
+            let text = match result {
+                Ok(text) => text.map(String::from).unwrap_or_default(),
+                Err(err) => {
+                    eprintln!("❌ Failed to read clipboard: {}", err);
+                    return;
+                }
+            };
+            println!("✅ Application read the clipboard ({} bytes)", text.len());
+            view.write_to_session(&clipboard::encode_reply(kind, &text, bell_terminated));
+        });
+    }
+
+    fn send_paste(&self, text: &str, bracketed: bool) {
+        self.scroll_to_bottom();
+        self.write_to_session(&paste::encode(text, bracketed));
+    }
+
+    /// Encodes a key event according to the keyboard modes the application set.
+    fn encode_key(
+        &self,
+        controller: &gtk4::EventControllerKey,
+        keyval: gdk::Key,
+        keycode: u32,
+        state: gdk::ModifierType,
+        kind: KeyEventKind,
+    ) -> Option<Vec<u8>> {
+        let modes = {
+            let terminal = self.imp().terminal.borrow();
+            let screen = terminal.screen();
+            KeyboardModes {
+                application_cursor: screen.modes.application_cursor,
+                application_keypad: screen.modes.application_keypad,
+                modify_other_keys: screen.modes.modify_other_keys,
+                kitty_flags: screen.keyboard_flags(),
+            }
+        };
+        // The unshifted key on the same physical key, for kitty's key codes.
+        let base = self
+            .display()
+            .translate_key(keycode, gdk::ModifierType::empty(), controller.group() as i32)
+            .map_or(keyval, |(base, ..)| base);
+        keyboard::encode(&KeyInput { keyval, base, state, kind }, &modes)
+    }
+
+    fn write_to_session(&self, bytes: &[u8]) {
+        if let Some(session) = self.imp().session.borrow().as_ref()
+            && let Err(err) = session.write(bytes)
+        {
+            eprintln!("❌ Failed to write to shell: {}", err);
+        }
+    }
+
+    fn setup_input(&self) {
+        let key_controller = gtk4::EventControllerKey::new();
+        key_controller.connect_key_pressed({
+            let view = self.downgrade();
+            move |controller, keyval, keycode, state| {
+                let Some(view) = view.upgrade() else {
+                    return glib::Propagation::Proceed;
+                };
+                let rows = view.imp().terminal.borrow().screen().rows() as isize;
+
+                if state.contains(gdk::ModifierType::SHIFT_MASK) {
+                    match keyval {
+                        gdk::Key::Page_Up => {
+                            view.scroll_display(rows - 1);
+                            return glib::Propagation::Stop;
+                        }
+                        gdk::Key::Page_Down => {
+                            view.scroll_display(1 - rows);
+                            return glib::Propagation::Stop;
+                        }
+                        _ => {}
+                    }
+                }
+
+                // Ctrl+Shift+Up/Down jump between prompts, Ctrl+Shift+O
+                // selects a command's output
+                let ctrl_shift = gdk::ModifierType::CONTROL_MASK | gdk::ModifierType::SHIFT_MASK;
+                if state.contains(ctrl_shift) {
+                    match keyval.to_lower() {
+                        gdk::Key::Up => {
+                            view.jump_to_prompt(true);
+                            return glib::Propagation::Stop;
+                        }
+                        gdk::Key::Down => {
+                            view.jump_to_prompt(false);
+                            return glib::Propagation::Stop;
+                        }
+                        gdk::Key::o => {
+                            view.select_command_output();
+                            return glib::Propagation::Stop;
+                        }
+                        _ => {}
+                    }
+                }
+
+                // Ctrl+Shift+C and Cmd+C copy the selection
+                let copy_key = keyval.to_lower() == gdk::Key::c
+                    && (state.contains(ctrl_shift) || state.contains(gdk::ModifierType::META_MASK));
+                if copy_key {
+                    view.copy_clipboard();
+                    return glib::Propagation::Stop;
+                }
+
+                // Ctrl+Shift+V, Shift+Insert and Cmd+V paste the clipboard
+                let paste_key = match keyval.to_lower() {
+                    gdk::Key::v => state.contains(ctrl_shift) || state.contains(gdk::ModifierType::META_MASK),
+                    gdk::Key::Insert => state.contains(gdk::ModifierType::SHIFT_MASK),
+                    _ => false,
+                };
+                if paste_key {
+                    view.paste_clipboard();
+                    return glib::Propagation::Stop;
+                }
+
+                let kind = if view.imp().pressed_keys.borrow_mut().insert(keycode) {
+                    KeyEventKind::Press
+                } else {
+                    KeyEventKind::Repeat
+                };
+                match view.encode_key(controller, keyval, keycode, state, kind) {
+                    Some(bytes) => {
+                        // Typing snaps the viewport back to the live screen
+                        view.scroll_to_bottom();
+                        view.write_to_session(&bytes);
+                        glib::Propagation::Stop
+                    }
+                    None => glib::Propagation::Proceed,
+                }
+            }
+        });
+        key_controller.connect_key_released({
+            let view = self.downgrade();
+            move |controller, keyval, keycode, state| {
+                let Some(view) = view.upgrade() else { return };
+                view.imp().pressed_keys.borrow_mut().remove(&keycode);
+                if let Some(bytes) = view.encode_key(controller, keyval, keycode, state, KeyEventKind::Release) {
+                    view.write_to_session(&bytes);
+                }
+            }
+        });
+        self.add_controller(key_controller);
+
+        let wheel = gtk4::EventControllerScroll::new(gtk4::EventControllerScrollFlags::VERTICAL);
+        wheel.connect_scroll({
+            let view = self.downgrade();
+            move |controller, _, dy| {
+                let Some(view) = view.upgrade() else {
+                    return glib::Propagation::Proceed;
+                };
+                let imp = view.imp();
+                let state = controller.current_event_state();
+                if view.mouse_reporting(state) {
+                    // Applications get one wheel button press per notch.
+                    let notches = imp.wheel_remainder.get() + dy;
+                    imp.wheel_remainder.set(notches.fract());
+                    let button = if notches < 0.0 { MouseButton::WheelUp } else { MouseButton::WheelDown };
+                    let (x, y) = imp.pointer.get();
+                    for _ in 0..notches.trunc().abs() as usize {
+                        view.report_mouse(MouseAction::Press, button, x, y, state);
+                    }
+                    return glib::Propagation::Stop;
+                }
+                let lines = imp.wheel_remainder.get() - dy * WHEEL_SCROLL_LINES;
+                imp.wheel_remainder.set(lines.fract());
+                view.scroll_display(lines.trunc() as isize);
+                glib::Propagation::Stop
+            }
+        });
+        self.add_controller(wheel);
+
+        self.imp().adjustment.connect_value_changed({
+            let view = self.downgrade();
+            move |adjustment| {
+                let Some(view) = view.upgrade() else { return };
+                let imp = view.imp();
+                if imp.syncing_adjustment.get() {
+                    return;
+                }
+                let history = imp.terminal.borrow().screen().history_len() as f64;
+                let offset = (history - adjustment.value()).round().max(0.0) as usize;
+                imp.terminal.borrow_mut().screen_mut().set_display_offset(offset);
+                view.queue_draw();
+            }
+        });
+
+        let click = gtk4::GestureClick::new();
+        click.set_button(0);
+        click.connect_pressed({
+            let view = self.downgrade();
+            move |gesture, n_press, x, y| {
+                let Some(view) = view.upgrade() else { return };
+                view.grab_focus();
+                let state = gesture.current_event_state();
+                let button = gesture.current_button();
+                if !view.mouse_reporting(state) {
+                    if button == gdk::BUTTON_PRIMARY
+                        && state.contains(gdk::ModifierType::CONTROL_MASK)
+                        && view.open_link_at(x, y)
+                    {
+                        return;
+                    }
+                    match button {
+                        gdk::BUTTON_PRIMARY => view.start_selection(n_press, x, y, state),
+                        gdk::BUTTON_MIDDLE => view.paste_primary(),
+                        _ => {}
+                    }
+                    return;
+                }
+                if let Some(button) = mouse_button(button) {
+                    view.report_mouse(MouseAction::Press, button, x, y, state);
+                }
+            }
+        });
+        click.connect_released({
+            let view = self.downgrade();
+            move |gesture, _, x, y| {
+                let Some(view) = view.upgrade() else { return };
+                if view.imp().selecting.replace(false) {
+                    // Copy-on-select to the primary selection
+                    match view.selection_text() {
+                        Some(text) => view.primary_clipboard().set_text(&text),
+                        None => view.clear_selection(),
+                    }
+                    return;
+                }
+                if let Some(button) = mouse_button(gesture.current_button()) {
+                    view.report_mouse(MouseAction::Release, button, x, y, gesture.current_event_state());
+                }
+            }
+        });
+        self.add_controller(click);
+
+        let motion = gtk4::EventControllerMotion::new();
+        motion.connect_motion({
+            let view = self.downgrade();
+            move |controller, x, y| {
+                let Some(view) = view.upgrade() else { return };
+                view.imp().pointer.set((x, y));
+                if view.imp().selecting.get() {
+                    view.extend_selection(x, y);
+                    return;
+                }
+                view.update_hovered_link(x, y);
+                let state = controller.current_event_state();
+                let button = if state.contains(gdk::ModifierType::BUTTON1_MASK) {
+                    MouseButton::Left
+                } else if state.contains(gdk::ModifierType::BUTTON2_MASK) {
+                    MouseButton::Middle
+                } else if state.contains(gdk::ModifierType::BUTTON3_MASK) {
+                    MouseButton::Right
+                } else {
+                    MouseButton::None
+                };
+                view.report_mouse(MouseAction::Motion, button, x, y, state);
+            }
+        });
+        motion.connect_leave({
+            let view = self.downgrade();
+            move |_| {
+                if let Some(view) = view.upgrade() {
+                    view.imp().hover_point.set(None);
+                    view.set_hovered_link(None);
+                }
+            }
+        });
+        self.add_controller(motion);
+
+        // Redraw so the cursor switches between solid and hollow
+        let focus = gtk4::EventControllerFocus::new();
+        focus.connect_enter({
+            let view = self.downgrade();
+            move |_| {
+                if let Some(view) = view.upgrade() {
+                    view.queue_draw();
+                }
+            }
+        });
+        focus.connect_leave({
+            let view = self.downgrade();
+            move |_| {
+                if let Some(view) = view.upgrade() {
+                    // Releases of keys held while focus moves never reach us
+                    view.imp().pressed_keys.borrow_mut().clear();
+                    view.queue_draw();
+                }
+            }
+        });
+        self.add_controller(focus);
+    }
+}
+
+/// Replaces `{name}` placeholders in `template` with `value(name)`;
+/// unknown ones are left as they are.
+fn fill_template<'a>(template: &str, value: impl Fn(&str) -> Option<&'a str>) -> String {
+    let mut filled = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let replacement = rest.find('}').and_then(|end| Some((value(&rest[1..end])?, end)));
+        match replacement {
+            Some((replacement, end)) => {
+                filled.push_str(replacement);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                filled.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+fn mouse_button(button: u32) -> Option<MouseButton> {
+    match button {
+        gdk::BUTTON_PRIMARY => Some(MouseButton::Left),
+        gdk::BUTTON_MIDDLE => Some(MouseButton::Middle),
+        gdk::BUTTON_SECONDARY => Some(MouseButton::Right),
+        _ => None,
+    }
+}
+
+fn rgba(color: Rgb) -> gdk::RGBA {
+    gdk::RGBA::new(
+        f32::from(color.r) / 255.0,
+        f32::from(color.g) / 255.0,
+        f32::from(color.b) / 255.0,
+        1.0,
+    )
+}
+
+/// Resolves a cell's foreground and background; `None` means the default
+/// background, which is left transparent.
+fn resolve_colors(attrs: Attrs, palette: &Palette) -> (gdk::RGBA, Option<gdk::RGBA>) {
+    let foreground = palette.resolve(attrs.fg).unwrap_or(palette.foreground);
+    let background = palette.resolve(attrs.bg);
+
+    let (foreground, background) = if attrs.flags.contains(Flags::INVERSE) {
+        (background.unwrap_or(palette.background), Some(foreground))
+    } else {
+        (foreground, background)
+    };
+
+    let mut foreground = rgba(foreground);
+    if attrs.flags.contains(Flags::DIM) {
+        foreground.set_alpha(0.5);
+    }
+    (foreground, background.map(rgba))
+}